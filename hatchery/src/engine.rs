@@ -1,14 +1,18 @@
 use std::{sync::Arc, time::Instant};
 
+use serde::{Deserialize, Serialize};
 use vulkano::{
     command_buffer::{
         allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder,
         CommandBufferInheritanceInfo, CommandBufferUsage, PrimaryAutoCommandBuffer,
         SecondaryAutoCommandBuffer,
     },
-    device::{physical::PhysicalDeviceType, Device, DeviceExtensions, Features, Queue},
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        Device, DeviceExtensions, Features, Queue,
+    },
     format::Format,
-    instance::{InstanceCreateInfo, InstanceExtensions},
+    instance::{Instance, InstanceCreateInfo, InstanceExtensions},
     pipeline::graphics::viewport::Viewport,
     render_pass::Subpass,
     swapchain::Surface,
@@ -28,7 +32,7 @@ use winit::{
 
 use crate::{
     gui::GuiImplementation, performance::EnginePerformance, render_pass::FinalRenderPass,
-    util::ConstructionContext,
+    storage::Storage, util::ConstructionContext,
 };
 
 /// Display options for the winit window
@@ -47,17 +51,30 @@ impl Default for WindowOptions {
     }
 }
 
+/// Window placement and device which are restored on the next launch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WindowState {
+    dimensions: [u32; 2],
+    position: Option<[f32; 2]>,
+    device: String,
+}
+
 pub struct EngineOptions {
     pub window_options: WindowOptions,
+    /// Name of the config directory the app state is saved to, nothing is saved if `None`
+    pub app_id: Option<&'static str>,
     pub instance_extensions: InstanceExtensions,
     pub device_extensions: DeviceExtensions,
     pub features: Features,
+    /// Enabled only if the selected device supports them, check `Device::enabled_features`
+    pub optional_features: Features,
 }
 
 impl Default for EngineOptions {
     fn default() -> Self {
         Self {
             window_options: Default::default(),
+            app_id: None,
             instance_extensions: InstanceExtensions {
                 ..vulkano_win::required_extensions(&VulkanLibrary::new().unwrap())
             },
@@ -68,6 +85,7 @@ impl Default for EngineOptions {
             features: Features {
                 ..Features::empty()
             },
+            optional_features: Features::empty(),
         }
     }
 }
@@ -107,8 +125,7 @@ where
                             context.resize();
                         }
                         WindowEvent::CloseRequested => {
-                            engine.stop(&mut context.api);
-                            *control_flow = ControlFlow::Exit;
+                            context.api.exit();
                         }
                         _ => (),
                     }
@@ -122,7 +139,16 @@ where
                     EngineLauncher::render(&mut engine, &mut context);
                 }
                 Event::MainEventsCleared => {
-                    context.api.window().request_redraw();
+                    if context.api.exit_requested {
+                        engine.stop(&mut context.api);
+                        *control_flow = ControlFlow::Exit;
+                    } else {
+                        context.api.window().request_redraw();
+                    }
+                }
+                // Every way out of the loop ends here, so the state is saved exactly once
+                Event::LoopDestroyed => {
+                    context.save();
                 }
                 _ => {}
             }
@@ -164,9 +190,17 @@ pub struct EngineApi {
     pub construction: ConstructionContext,
     pub surface: Arc<Surface>,
     pub performance: EnginePerformance,
+    pub storage: Storage,
+    exit_requested: bool,
 }
 
 impl EngineApi {
+    /// Leave the event loop after the current events, the same as closing the window. The engine
+    /// is stopped and the app state saved.
+    pub fn exit(&mut self) {
+        self.exit_requested = true;
+    }
+
     pub fn device(&self) -> Arc<Device> {
         self.context.device().clone()
     }
@@ -200,6 +234,64 @@ impl EngineApi {
     }
 }
 
+fn instance_create_info(extensions: InstanceExtensions) -> InstanceCreateInfo {
+    InstanceCreateInfo {
+        max_api_version: Some(Version::V1_1),
+        enabled_extensions: extensions,
+        enumerate_portability: true,
+        ..InstanceCreateInfo::default()
+    }
+}
+
+/// Prefer the device of the last session, otherwise discrete over integrated GPUs
+fn device_priority(device: &PhysicalDevice, preferred_device: Option<&str>) -> u32 {
+    let properties = device.properties();
+    if preferred_device == Some(properties.device_name.as_str()) {
+        return 0;
+    }
+
+    match properties.device_type {
+        PhysicalDeviceType::DiscreteGpu => 1,
+        PhysicalDeviceType::IntegratedGpu => 2,
+        PhysicalDeviceType::VirtualGpu => 3,
+        PhysicalDeviceType::Cpu => 4,
+        _ => 5,
+    }
+}
+
+/// Optional features supported by the device `VulkanoContext` is going to pick. The context
+/// creates its own instance, so the devices are enumerated with a temporary one.
+fn supported_optional_features(
+    options: &EngineOptions,
+    preferred_device: Option<&str>,
+) -> Features {
+    if options.optional_features == Features::empty() {
+        return Features::empty();
+    }
+
+    let instance = Instance::new(
+        VulkanLibrary::new().unwrap(),
+        instance_create_info(options.instance_extensions),
+    )
+    .unwrap();
+
+    instance
+        .enumerate_physical_devices()
+        .unwrap()
+        .filter(|device| {
+            device
+                .supported_extensions()
+                .contains(&options.device_extensions)
+        })
+        .min_by_key(|device| device_priority(device, preferred_device))
+        .map(|device| {
+            device
+                .supported_features()
+                .intersection(&options.optional_features)
+        })
+        .unwrap_or_else(Features::empty)
+}
+
 pub struct EngineContext<G> {
     api: EngineApi,
     gui: G,
@@ -215,28 +307,40 @@ where
         // Ensure khr_swapchain is enabled
         options.device_extensions.khr_swapchain = true;
 
+        let storage = Storage::load(options.app_id);
+        let window_state: Option<WindowState> = storage.get("window");
+        let preferred_device = window_state.as_ref().map(|state| state.device.clone());
+
+        let optional_features = supported_optional_features(&options, preferred_device.as_deref());
+
         // Create Vulkano context
         let vulkano_config = VulkanoConfig {
-            instance_create_info: InstanceCreateInfo {
-                max_api_version: Some(Version::V1_1),
-                enabled_extensions: options.instance_extensions,
-                enumerate_portability: true,
-                ..InstanceCreateInfo::default()
-            },
-            device_features: options.features,
+            instance_create_info: instance_create_info(options.instance_extensions),
+            device_features: options.features.union(&optional_features),
             device_extensions: options.device_extensions,
+            device_priority_fn: Arc::new(move |device| {
+                device_priority(device, preferred_device.as_deref())
+            }),
             ..VulkanoConfig::default()
         };
         let context = VulkanoContext::new(vulkano_config);
 
-        // Create windows
+        // Create windows, restoring the size and position of the last session
+        let (dimensions, position) = match &window_state {
+            Some(state) => (state.dimensions, state.position),
+            None => {
+                let dimensions = options.window_options.dimensions;
+                ([dimensions.width, dimensions.height], None)
+            }
+        };
         let mut windows = VulkanoWindows::default();
         let window = windows.create_window(
             event_loop,
             &context,
             &WindowDescriptor {
-                width: options.window_options.dimensions.width as f32,
-                height: options.window_options.dimensions.height as f32,
+                width: dimensions[0] as f32,
+                height: dimensions[1] as f32,
+                position,
                 title: options.window_options.title.to_string(),
                 ..WindowDescriptor::default()
             },
@@ -249,17 +353,19 @@ where
         let render_pass = FinalRenderPass::new(
             &context,
             G::requested_format().unwrap_or(Format::B8G8R8A8_SRGB),
+            G::requires_subpass(),
         );
 
         let surface = windows.get_primary_renderer().unwrap().surface();
 
         // Create gui
-        let gui = G::new(
+        let mut gui = G::new(
             event_loop,
             surface.clone(),
             context.graphics_queue().clone(),
             render_pass.ui_subpass(),
         );
+        gui.load_state(&storage);
 
         let construction = ConstructionContext::new(context.compute_queue().clone());
 
@@ -268,6 +374,8 @@ where
             surface,
             performance: Default::default(),
             construction,
+            storage,
+            exit_requested: false,
         };
 
         Self {
@@ -305,11 +413,35 @@ where
     pub fn resize(&mut self) {
         self.window_renderer_mut().resize();
     }
+
+    /// Store the window, device and gui state and write the storage to disk
+    pub fn save(&mut self) {
+        let window = self.api.window();
+        let scale_factor = window.scale_factor();
+        let dimensions = window.inner_size().to_logical::<u32>(scale_factor);
+        let position = window
+            .outer_position()
+            .ok()
+            .map(|position| position.to_logical::<f32>(scale_factor));
+
+        let state = WindowState {
+            dimensions: [dimensions.width, dimensions.height],
+            position: position.map(|position| [position.x, position.y]),
+            device: self.api.device_name().to_owned(),
+        };
+        self.api.storage.set("window", &state);
+        self.gui.save_state(&mut self.api.storage);
+
+        if let Err(err) = self.api.storage.save() {
+            eprintln!("failed to save app state: {err}");
+        }
+    }
 }
 
 pub struct RenderInfo<'a> {
     pub command_buffer: &'a mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
     pub command_allocator: &'a StandardCommandBufferAllocator,
+    pub construction: &'a ConstructionContext,
     pub queue: Arc<Queue>,
     pub subpass: Subpass,
     pub viewport: Viewport,
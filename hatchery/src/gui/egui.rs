@@ -1,7 +1,8 @@
 use std::sync::Arc;
 
 use super::GuiImplementation;
-use egui::Context;
+use crate::storage::Storage;
+use egui::{Context, Memory};
 use egui_winit_vulkano::Gui;
 use vulkano::{
     command_buffer::SecondaryAutoCommandBuffer, device::Queue,
@@ -26,8 +27,9 @@ impl GuiImplementation for EguiImplementation {
         event_loop: &EventLoopWindowTarget<()>,
         surface: Arc<Surface>,
         graphics_queue: Arc<Queue>,
-        subpass: Subpass,
+        subpass: Option<Subpass>,
     ) -> Self {
+        let subpass = subpass.expect("egui requires a ui subpass");
         Self {
             gui: Gui::new_with_subpass(event_loop, surface, None, graphics_queue, subpass),
         }
@@ -56,7 +58,17 @@ impl GuiImplementation for EguiImplementation {
         self.gui.update(event)
     }
 
-    fn render(&mut self, dimensions: [u32; 2]) -> SecondaryAutoCommandBuffer {
-        self.gui.draw_on_subpass_image(dimensions)
+    fn render(&mut self, dimensions: [u32; 2]) -> Option<SecondaryAutoCommandBuffer> {
+        Some(self.gui.draw_on_subpass_image(dimensions))
+    }
+
+    fn load_state(&mut self, storage: &Storage) {
+        if let Some(memory) = storage.get::<Memory>("egui") {
+            *self.context().memory() = memory;
+        }
+    }
+
+    fn save_state(&self, storage: &mut Storage) {
+        storage.set("egui", &*self.context().memory());
     }
 }
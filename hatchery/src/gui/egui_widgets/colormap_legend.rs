@@ -0,0 +1,115 @@
+use egui::*;
+
+use crate::util::colormap::ColorScale;
+
+/// Number of quads used to approximate the gradient
+const SEGMENTS: usize = 64;
+
+/// Horizontal colorbar showing a `ColorScale` with labelled ticks underneath
+pub struct ColormapLegend<'a> {
+    scale: &'a ColorScale,
+    label: Option<WidgetText>,
+    height: f32,
+    ticks: usize,
+}
+
+impl<'a> ColormapLegend<'a> {
+    pub fn new(scale: &'a ColorScale) -> Self {
+        Self {
+            scale,
+            label: None,
+            height: 12.0,
+            ticks: 3,
+        }
+    }
+
+    /// Text shown above the colorbar, usually the quantity and its units
+    pub fn label(mut self, label: impl Into<WidgetText>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Height of the colorbar itself, not including the labels
+    pub fn height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Number of labelled ticks, including both ends
+    pub fn ticks(mut self, ticks: usize) -> Self {
+        self.ticks = ticks.max(2);
+        self
+    }
+}
+
+fn to_color32(color: [f32; 3]) -> Color32 {
+    let [r, g, b] = color.map(|c| (c * 255.0).round() as u8);
+    Color32::from_rgb(r, g, b)
+}
+
+impl Widget for ColormapLegend<'_> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let ColormapLegend {
+            scale,
+            label,
+            height,
+            ticks,
+        } = self;
+
+        ui.vertical(|ui| {
+            if let Some(label) = label {
+                ui.label(label);
+            }
+
+            let font = FontId::proportional(11.0);
+            let text_height = ui.fonts().row_height(&font);
+            let desired_size = vec2(ui.available_width(), height + text_height + 2.0);
+            let (rect, response) = ui.allocate_exact_size(desired_size, Sense::hover());
+
+            if ui.is_rect_visible(rect) {
+                let bar = Rect::from_min_size(rect.min, vec2(rect.width(), height));
+
+                let mut mesh = Mesh::default();
+                for i in 0..=SEGMENTS {
+                    let t = i as f32 / SEGMENTS as f32;
+                    let color = to_color32(scale.colormap.sample(t));
+                    let x = lerp(bar.left()..=bar.right(), t);
+                    mesh.colored_vertex(pos2(x, bar.top()), color);
+                    mesh.colored_vertex(pos2(x, bar.bottom()), color);
+
+                    if i > 0 {
+                        let base = 2 * i as u32;
+                        mesh.add_triangle(base - 2, base - 1, base);
+                        mesh.add_triangle(base - 1, base + 1, base);
+                    }
+                }
+
+                let painter = ui.painter();
+                painter.add(Shape::mesh(mesh));
+                painter.rect_stroke(bar, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+
+                let text_color = ui.visuals().text_color();
+                for i in 0..ticks {
+                    let t = i as f32 / (ticks - 1) as f32;
+                    let x = lerp(bar.left()..=bar.right(), t);
+                    let align = match i {
+                        0 => Align2::LEFT_TOP,
+                        i if i == ticks - 1 => Align2::RIGHT_TOP,
+                        _ => Align2::CENTER_TOP,
+                    };
+
+                    painter.text(
+                        pos2(x, bar.bottom() + 2.0),
+                        align,
+                        format!("{:.3e}", scale.denormalize(t)),
+                        font.clone(),
+                        text_color,
+                    );
+                }
+            }
+
+            response
+        })
+        .inner
+    }
+}
@@ -0,0 +1,42 @@
+use egui::*;
+
+use crate::input::ActionMap;
+
+/// Table of every action in an `ActionMap` with its bindings, generated so it can't get out of
+/// date with the keymap file
+pub struct KeymapHelp<'a> {
+    actions: &'a ActionMap,
+}
+
+impl<'a> KeymapHelp<'a> {
+    pub fn new(actions: &'a ActionMap) -> Self {
+        Self { actions }
+    }
+}
+
+impl Widget for KeymapHelp<'_> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        Grid::new("keymap_help")
+            .num_columns(2)
+            .spacing([10.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                for action in self.actions.actions() {
+                    ui.label(action.description);
+
+                    let bindings = action
+                        .bindings
+                        .iter()
+                        .map(|binding| binding.to_string())
+                        .collect::<Vec<_>>();
+                    if bindings.is_empty() {
+                        ui.weak("unbound");
+                    } else {
+                        ui.monospace(bindings.join(", "));
+                    }
+                    ui.end_row();
+                }
+            })
+            .response
+    }
+}
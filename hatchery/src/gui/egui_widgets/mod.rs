@@ -1,3 +1,13 @@
+mod colormap_legend;
 mod fat_button;
+mod keymap_help;
+mod time_series_plot;
+mod transfer_function_editor;
+mod viewport_overlay;
 
+pub use colormap_legend::ColormapLegend;
 pub use fat_button::FatButton;
+pub use keymap_help::KeymapHelp;
+pub use time_series_plot::{Retention, TimeSeries, TimeSeriesPlot};
+pub use transfer_function_editor::TransferFunctionEditor;
+pub use viewport_overlay::ViewportOverlay;
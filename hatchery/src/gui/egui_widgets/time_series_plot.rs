@@ -0,0 +1,397 @@
+use std::{collections::VecDeque, fmt::Write as _, fs, io, path::Path};
+
+use egui::{
+    plot::{Legend, Line, Plot, PlotPoints},
+    *,
+};
+
+/// What happens to a series once it holds `capacity` samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    /// Drop the oldest sample, only the most recent samples are kept
+    Rolling,
+    /// Drop every other sample and keep only every second new one from then on, so the whole
+    /// run stays visible at a coarser resolution
+    Decimate,
+}
+
+struct Series {
+    name: String,
+    samples: VecDeque<[f64; 2]>,
+    /// First value ever pushed, the reference for the relative error
+    first: Option<f64>,
+    /// Only every `stride`th pushed sample is stored
+    stride: usize,
+    skipped: usize,
+}
+
+impl Series {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            samples: VecDeque::new(),
+            first: None,
+            stride: 1,
+            skipped: 0,
+        }
+    }
+
+    fn push(&mut self, x: f64, y: f64, capacity: usize, retention: Retention) {
+        self.first.get_or_insert(y);
+
+        if self.skipped + 1 < self.stride {
+            self.skipped += 1;
+            return;
+        }
+        self.skipped = 0;
+
+        if self.samples.len() >= capacity {
+            match retention {
+                Retention::Rolling => {
+                    self.samples.pop_front();
+                }
+                Retention::Decimate => {
+                    let mut i = 0;
+                    self.samples.retain(|_| {
+                        i += 1;
+                        i % 2 == 1
+                    });
+                    self.stride *= 2;
+                }
+            }
+        }
+
+        self.samples.push_back([x, y]);
+    }
+
+    /// Samples as they are plotted, i.e. relative to the first value if `relative` is set
+    fn values(&self, relative: bool) -> impl Iterator<Item = [f64; 2]> + '_ {
+        let first = self.first.unwrap_or(0.0);
+        self.samples.iter().map(move |&[x, y]| {
+            if !relative {
+                [x, y]
+            } else if first != 0.0 {
+                [x, (y - first) / first.abs()]
+            } else {
+                [x, y - first]
+            }
+        })
+    }
+}
+
+/// Bounded store for several named series of `(x, y)` samples, usually a quantity over simulation
+/// time. Shown with `TimeSeriesPlot`.
+pub struct TimeSeries {
+    series: Vec<Series>,
+    capacity: usize,
+    retention: Retention,
+}
+
+impl TimeSeries {
+    /// Each series keeps at most `capacity` samples
+    pub fn new(capacity: usize, retention: Retention) -> Self {
+        Self {
+            series: Vec::new(),
+            capacity: capacity.max(2),
+            retention,
+        }
+    }
+
+    /// Append a sample to the series called `name`, creating it if necessary
+    pub fn push(&mut self, name: &str, x: f64, y: f64) {
+        let index = match self.series.iter().position(|series| series.name == name) {
+            Some(index) => index,
+            None => {
+                self.series.push(Series::new(name));
+                self.series.len() - 1
+            }
+        };
+
+        self.series[index].push(x, y, self.capacity, self.retention);
+    }
+
+    /// Remove all series
+    pub fn clear(&mut self) {
+        self.series.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.iter().all(|series| series.samples.is_empty())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.series.iter().map(|series| series.name.as_str())
+    }
+
+    /// Most recent sample of the series called `name`
+    pub fn latest(&self, name: &str) -> Option<[f64; 2]> {
+        self.series
+            .iter()
+            .find(|series| series.name == name)
+            .and_then(|series| series.samples.back().copied())
+    }
+
+    /// All stored samples as `series,x,y` rows, relative to the first value if `relative` is set
+    pub fn to_csv(&self, relative: bool) -> String {
+        let mut csv = String::from("series,x,y\n");
+        for series in &self.series {
+            for [x, y] in series.values(relative) {
+                writeln!(csv, "{},{:e},{:e}", series.name, x, y).unwrap();
+            }
+        }
+        csv
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>, relative: bool) -> io::Result<()> {
+        fs::write(path, self.to_csv(relative))
+    }
+}
+
+/// Display options of a `TimeSeriesPlot`, toggled by the user and kept in egui's memory
+#[derive(Debug, Clone)]
+struct PlotOptions {
+    relative: bool,
+    log_x: bool,
+    log_y: bool,
+    /// Outcome of the last export
+    status: Option<String>,
+}
+
+/// Line plot of the series in a `TimeSeries`, with toggles for relative error and log axes and a
+/// button exporting the plotted data as CSV. The plot keeps its id, so zoom and pan persist
+/// between frames.
+pub struct TimeSeriesPlot<'a> {
+    name: &'a str,
+    series: &'a TimeSeries,
+    view_aspect: f32,
+    options: PlotOptions,
+    export_path: Option<String>,
+}
+
+impl<'a> TimeSeriesPlot<'a> {
+    /// `name` identifies the plot and is the default file name of the export
+    pub fn new(name: &'a str, series: &'a TimeSeries) -> Self {
+        Self {
+            name,
+            series,
+            view_aspect: 2.0,
+            options: PlotOptions {
+                relative: false,
+                log_x: false,
+                log_y: false,
+                status: None,
+            },
+            export_path: None,
+        }
+    }
+
+    pub fn view_aspect(mut self, view_aspect: f32) -> Self {
+        self.view_aspect = view_aspect;
+        self
+    }
+
+    /// Initially plot `(y - y0) / |y0|` instead of `y`, where `y0` is the first sample
+    pub fn relative(mut self, relative: bool) -> Self {
+        self.options.relative = relative;
+        self
+    }
+
+    /// Initially use logarithmic axes, non positive values are plotted by magnitude
+    pub fn log_axes(mut self, log_x: bool, log_y: bool) -> Self {
+        self.options.log_x = log_x;
+        self.options.log_y = log_y;
+        self
+    }
+
+    /// File the export button writes to, defaults to `<name>.csv`
+    pub fn export_path(mut self, path: impl Into<String>) -> Self {
+        self.export_path = Some(path.into());
+        self
+    }
+}
+
+fn to_log(value: f64) -> f64 {
+    value.abs().log10()
+}
+
+fn from_log(value: f64) -> String {
+    format!("{:.0e}", 10.0f64.powf(value))
+}
+
+impl Widget for TimeSeriesPlot<'_> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let TimeSeriesPlot {
+            name,
+            series,
+            view_aspect,
+            options,
+            export_path,
+        } = self;
+
+        let id = Id::new(name);
+        let mut options = ui.data().get_temp_mut_or(id, options).clone();
+
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut options.relative, "Relative");
+                ui.checkbox(&mut options.log_x, "Log x");
+                ui.checkbox(&mut options.log_y, "Log y");
+
+                if ui.button("Export CSV").clicked() {
+                    let path = export_path.unwrap_or_else(|| format!("{name}.csv"));
+                    options.status = Some(match series.write_csv(&path, options.relative) {
+                        Ok(()) => format!("Saved {path}"),
+                        Err(err) => format!("Export failed: {err}"),
+                    });
+                }
+            });
+
+            if let Some(status) = &options.status {
+                ui.label(status);
+            }
+
+            let PlotOptions {
+                relative,
+                log_x,
+                log_y,
+                ..
+            } = options;
+
+            let mut plot = Plot::new(id.with("plot")).view_aspect(view_aspect);
+            if series.series.len() > 1 {
+                plot = plot.legend(Legend::default());
+            }
+            if log_x {
+                plot = plot.x_axis_formatter(|x, _| from_log(x));
+            }
+            if log_y {
+                plot = plot.y_axis_formatter(|y, _| from_log(y));
+            }
+            if log_x || log_y {
+                plot = plot.label_formatter(move |name, point| {
+                    let x = if log_x {
+                        10.0f64.powf(point.x)
+                    } else {
+                        point.x
+                    };
+                    let y = if log_y {
+                        10.0f64.powf(point.y)
+                    } else {
+                        point.y
+                    };
+                    format!("{name}\nx = {x:.4e}\ny = {y:.4e}")
+                });
+            }
+
+            let response = plot
+                .show(ui, |plot_ui| {
+                    for series in &series.series {
+                        let points: PlotPoints = series
+                            .values(relative)
+                            .map(|[x, y]| {
+                                [
+                                    if log_x { to_log(x) } else { x },
+                                    if log_y { to_log(y) } else { y },
+                                ]
+                            })
+                            .filter(|[x, y]| x.is_finite() && y.is_finite())
+                            .collect();
+                        plot_ui.line(Line::new(points).name(&series.name));
+                    }
+                })
+                .response;
+
+            ui.data().insert_temp(id, options);
+            response
+        })
+        .inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xs(series: &TimeSeries) -> Vec<f64> {
+        series.series[0].samples.iter().map(|&[x, _]| x).collect()
+    }
+
+    #[test]
+    fn rolling_drops_the_oldest_samples() {
+        let mut series = TimeSeries::new(4, Retention::Rolling);
+        for i in 0..10 {
+            series.push("a", i as f64, 0.0);
+        }
+
+        assert_eq!(xs(&series), [6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(series.latest("a"), Some([9.0, 0.0]));
+    }
+
+    #[test]
+    fn capacity_is_at_least_two() {
+        let mut series = TimeSeries::new(0, Retention::Rolling);
+        for i in 0..3 {
+            series.push("a", i as f64, 0.0);
+        }
+
+        assert_eq!(xs(&series), [1.0, 2.0]);
+    }
+
+    #[test]
+    fn decimation_doubles_the_stride() {
+        let mut series = TimeSeries::new(4, Retention::Decimate);
+        for i in 0..=4 {
+            series.push("a", i as f64, 0.0);
+        }
+        assert_eq!(xs(&series), [0.0, 2.0, 4.0]);
+        assert_eq!(series.series[0].stride, 2);
+
+        for i in 5..=12 {
+            series.push("a", i as f64, 0.0);
+        }
+        assert_eq!(xs(&series), [0.0, 4.0, 8.0, 12.0]);
+        assert_eq!(series.series[0].stride, 4);
+    }
+
+    #[test]
+    fn relative_values_use_the_first_sample() {
+        let relative = |series: &TimeSeries| -> Vec<f64> {
+            series.series[0].values(true).map(|[_, y]| y).collect()
+        };
+
+        // A zero baseline gives the absolute difference instead of dividing by zero
+        let mut series = TimeSeries::new(8, Retention::Rolling);
+        series.push("a", 0.0, 0.0);
+        series.push("a", 1.0, 2.0);
+        assert_eq!(relative(&series), [0.0, 2.0]);
+
+        let mut series = TimeSeries::new(8, Retention::Rolling);
+        series.push("a", 0.0, -2.0);
+        series.push("a", 1.0, -1.0);
+        assert_eq!(relative(&series), [0.0, 0.5]);
+
+        // The baseline outlives the sample it came from
+        let mut series = TimeSeries::new(2, Retention::Rolling);
+        for (i, y) in [10.0, 20.0, 30.0].into_iter().enumerate() {
+            series.push("a", i as f64, y);
+        }
+        assert_eq!(relative(&series), [1.0, 2.0]);
+    }
+
+    #[test]
+    fn csv_lists_the_series_in_order() {
+        let mut series = TimeSeries::new(8, Retention::Rolling);
+        series.push("a", 1.0, 2.0);
+        series.push("b", 1.0, 0.5);
+        series.push("a", 2.0, 3.0);
+
+        assert_eq!(
+            series.to_csv(false),
+            "series,x,y\na,1e0,2e0\na,2e0,3e0\nb,1e0,5e-1\n"
+        );
+        assert_eq!(
+            series.to_csv(true),
+            "series,x,y\na,1e0,0e0\na,2e0,5e-1\nb,1e0,0e0\n"
+        );
+    }
+}
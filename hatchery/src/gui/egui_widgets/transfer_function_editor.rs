@@ -0,0 +1,160 @@
+use egui::*;
+
+use crate::util::volume::{ColorStop, TransferFunction};
+
+/// Number of quads used to draw the transfer function
+const SEGMENTS: usize = 64;
+
+/// Radius of the draggable stop handles
+const HANDLE_RADIUS: f32 = 5.0;
+
+/// Editor for the stops of a `TransferFunction`. The horizontal axis is log density and the
+/// vertical axis opacity. Drag a stop to move it, click on an empty spot to add one and right
+/// click a stop to remove it. The color of the last touched stop can be edited below the graph.
+pub struct TransferFunctionEditor<'a> {
+    transfer: &'a mut TransferFunction,
+    height: f32,
+}
+
+impl<'a> TransferFunctionEditor<'a> {
+    pub fn new(transfer: &'a mut TransferFunction) -> Self {
+        Self {
+            transfer,
+            height: 80.0,
+        }
+    }
+
+    /// Height of the graph, not including the color picker underneath
+    pub fn height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+}
+
+fn to_color32(color: [f32; 3]) -> Color32 {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    Color32::from_rgb(r, g, b)
+}
+
+impl Widget for TransferFunctionEditor<'_> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let TransferFunctionEditor { transfer, height } = self;
+
+        ui.vertical(|ui| {
+            let desired_size = vec2(ui.available_width(), height);
+            let (rect, mut response) = ui.allocate_exact_size(desired_size, Sense::click());
+            let id = response.id;
+            let graph = rect.shrink(HANDLE_RADIUS);
+
+            let to_screen = |stop: &ColorStop| {
+                pos2(
+                    lerp(graph.left()..=graph.right(), stop.position),
+                    lerp(graph.bottom()..=graph.top(), stop.alpha),
+                )
+            };
+            let from_screen = |pos: Pos2| {
+                (
+                    remap_clamp(pos.x, graph.left()..=graph.right(), 0.0..=1.0),
+                    remap_clamp(pos.y, graph.bottom()..=graph.top(), 0.0..=1.0),
+                )
+            };
+
+            let mut selected: usize = ui.data().get_temp(id).unwrap_or(0);
+            let mut removed = None;
+            let mut on_handle = false;
+
+            for (i, stop) in transfer.stops.iter_mut().enumerate() {
+                let handle =
+                    Rect::from_center_size(to_screen(stop), Vec2::splat(3.0 * HANDLE_RADIUS));
+                let handle = ui.interact(handle, id.with(i), Sense::click_and_drag());
+
+                on_handle |= handle.hovered() || handle.dragged();
+                if handle.dragged() {
+                    if let Some(pos) = handle.interact_pointer_pos() {
+                        (stop.position, stop.alpha) = from_screen(pos);
+                        response.mark_changed();
+                    }
+                }
+                if handle.drag_started() || handle.clicked() {
+                    selected = i;
+                }
+                if handle.secondary_clicked() {
+                    removed = Some(i);
+                }
+            }
+
+            // A transfer function needs at least two stops to be useful
+            if let Some(i) = removed.filter(|_| transfer.stops.len() > 2) {
+                transfer.stops.remove(i);
+                selected = selected.min(transfer.stops.len() - 1);
+                response.mark_changed();
+            }
+
+            if response.clicked() && !on_handle {
+                if let Some(pos) = response.interact_pointer_pos() {
+                    let (position, alpha) = from_screen(pos);
+                    let [r, g, b, _] = transfer.sample(position);
+                    transfer.stops.push(ColorStop {
+                        position,
+                        color: [r, g, b],
+                        alpha,
+                    });
+                    selected = transfer.stops.len() - 1;
+                    response.mark_changed();
+                }
+            }
+
+            if ui.is_rect_visible(rect) {
+                let painter = ui.painter();
+                let visuals = ui.visuals();
+                painter.rect_filled(rect, 2.0, visuals.extreme_bg_color);
+
+                // Opacity curve filled with the color at each position
+                let mut mesh = Mesh::default();
+                for i in 0..=SEGMENTS {
+                    let t = i as f32 / SEGMENTS as f32;
+                    let [r, g, b, a] = transfer.sample(t);
+                    let color = to_color32([r, g, b]);
+                    let x = lerp(graph.left()..=graph.right(), t);
+                    mesh.colored_vertex(pos2(x, graph.bottom()), color);
+                    mesh.colored_vertex(pos2(x, lerp(graph.bottom()..=graph.top(), a)), color);
+
+                    if i > 0 {
+                        let base = 2 * i as u32;
+                        mesh.add_triangle(base - 2, base - 1, base);
+                        mesh.add_triangle(base - 1, base + 1, base);
+                    }
+                }
+                painter.add(Shape::mesh(mesh));
+                painter.rect_stroke(rect, 2.0, visuals.widgets.noninteractive.bg_stroke);
+
+                for (i, stop) in transfer.stops.iter().enumerate() {
+                    let stroke = if i == selected {
+                        visuals.selection.stroke
+                    } else {
+                        visuals.widgets.inactive.fg_stroke
+                    };
+                    painter.circle(
+                        to_screen(stop),
+                        HANDLE_RADIUS,
+                        to_color32(stop.color),
+                        stroke,
+                    );
+                }
+            }
+
+            if let Some(stop) = transfer.stops.get_mut(selected) {
+                ui.horizontal(|ui| {
+                    ui.label("Stop color:");
+                    if ui.color_edit_button_rgb(&mut stop.color).changed() {
+                        response.mark_changed();
+                    }
+                });
+            }
+
+            ui.data().insert_temp(id, selected);
+            response
+        })
+        .inner
+    }
+}
@@ -0,0 +1,148 @@
+use cgmath::{InnerSpace, Point3, Vector3, Vector4};
+use egui::*;
+
+use crate::util::camera::ViewData;
+
+/// Longest the scale bar is allowed to get, in points
+const SCALE_BAR_WIDTH: f32 = 150.0;
+
+/// Margin between the edge of the viewport and corner annotations
+const MARGIN: f32 = 8.0;
+
+/// Draws text and annotations over the viewport, anchored either to world positions or to the
+/// viewport itself. Call it after all panels have been added so the available rect matches the
+/// viewport which is rendered into.
+pub struct ViewportOverlay {
+    view: ViewData,
+    rect: Rect,
+    painter: Painter,
+    font: FontId,
+}
+
+impl ViewportOverlay {
+    pub fn new(context: &Context, view: ViewData) -> Self {
+        let rect = context.available_rect();
+        let painter = context
+            .layer_painter(LayerId::new(Order::Background, Id::new("viewport_overlay")))
+            .with_clip_rect(rect);
+
+        Self {
+            view,
+            rect,
+            painter,
+            font: FontId::proportional(13.0),
+        }
+    }
+
+    pub fn font(mut self, font: FontId) -> Self {
+        self.font = font;
+        self
+    }
+
+    /// Area of the viewport in points
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Screen position of a world position, `None` if it is behind the camera or off screen
+    pub fn project(&self, position: Point3<f32>) -> Option<Pos2> {
+        self.to_screen(position)
+            .filter(|pos| self.rect.contains(*pos))
+    }
+
+    /// Like `project` but without culling positions outside of the viewport
+    fn to_screen(&self, position: Point3<f32>) -> Option<Pos2> {
+        let ViewData {
+            world, view, proj, ..
+        } = self.view;
+        let clip = proj * view * world * Vector4::new(position.x, position.y, position.z, 1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        let ndc = vec2(clip.x, clip.y) / clip.w;
+        Some(self.rect.min + (ndc * 0.5 + vec2(0.5, 0.5)) * self.rect.size())
+    }
+
+    /// Text anchored to a world position, with a small marker at the position itself
+    pub fn label(&self, position: Point3<f32>, text: impl ToString, color: Color32) {
+        if let Some(pos) = self.project(position) {
+            self.painter.circle_filled(pos, 2.0, color);
+            self.text(pos + vec2(4.0, -4.0), Align2::LEFT_BOTTOM, text, color);
+        }
+    }
+
+    /// Text anchored to a corner or edge of the viewport, e.g. `Align2::RIGHT_TOP`
+    pub fn corner_text(&self, align: Align2, text: impl ToString, color: Color32) {
+        let inner = self.rect.shrink(MARGIN);
+        let pos = pos2(
+            lerp(inner.x_range(), align.x().to_factor()),
+            lerp(inner.y_range(), align.y().to_factor()),
+        );
+        self.text(pos, align, text, color);
+    }
+
+    /// Bar in the bottom left corner showing a round length in world units, measured at the
+    /// depth of `reference`
+    pub fn scale_bar(&self, reference: Point3<f32>, units: &str, color: Color32) {
+        // The first row of the view matrix is the camera's right vector in world space
+        let side = Vector3::new(self.view.view.x.x, self.view.view.y.x, self.view.view.z.x);
+        let (Some(start), Some(end)) = (
+            self.to_screen(reference),
+            self.to_screen(reference + side.normalize()),
+        ) else {
+            return;
+        };
+
+        let points_per_unit = (end - start).length();
+        if points_per_unit <= f32::EPSILON {
+            return;
+        }
+
+        let length = nice_length(SCALE_BAR_WIDTH / points_per_unit);
+        let width = length * points_per_unit;
+
+        let left = self.rect.left_bottom() + vec2(MARGIN, -MARGIN);
+        let right = left + vec2(width, 0.0);
+        let stroke = Stroke::new(2.0, color);
+        self.painter.line_segment([left, right], stroke);
+        for end in [left, right] {
+            self.painter
+                .line_segment([end, end - vec2(0.0, 6.0)], stroke);
+        }
+
+        self.text(
+            pos2(0.5 * (left.x + right.x), left.y - 8.0),
+            Align2::CENTER_BOTTOM,
+            format!("{:.*} {}", decimals(length), length, units),
+            color,
+        );
+    }
+
+    fn text(&self, pos: Pos2, align: Align2, text: impl ToString, color: Color32) {
+        let galley = self
+            .painter
+            .layout_no_wrap(text.to_string(), self.font.clone(), color);
+        let rect = align.anchor_rect(Rect::from_min_size(pos, galley.size()));
+
+        // Dark backdrop so the text stays readable over bright particles
+        self.painter
+            .rect_filled(rect.expand(2.0), 2.0, Color32::from_black_alpha(160));
+        self.painter.galley(rect.min, galley);
+    }
+}
+
+/// Largest length of the form 1, 2 or 5 times a power of ten which is at most `max`
+fn nice_length(max: f32) -> f32 {
+    let magnitude = 10f32.powf(max.log10().floor());
+    [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|&length| length <= max)
+        .unwrap_or(magnitude)
+}
+
+/// Number of decimals needed to print a length returned by `nice_length`
+fn decimals(length: f32) -> usize {
+    (-length.log10().floor()).max(0.0) as usize
+}
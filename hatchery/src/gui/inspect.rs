@@ -0,0 +1,102 @@
+use std::ops::RangeInclusive;
+
+use egui::{emath::Numeric, DragValue, Slider, TextEdit, Ui};
+
+pub use hatchery_derive::Inspect;
+
+/// Per field options of the `Inspect` derive, see the derive macro for the matching attributes
+#[derive(Debug, Clone, Default)]
+pub struct InspectOptions {
+    /// Label of the value, also used to keep widget ids unique
+    pub label: &'static str,
+    pub range: Option<RangeInclusive<f64>>,
+    /// Drag speed of numeric values
+    pub speed: Option<f64>,
+    /// Use a logarithmic slider instead of a drag value, only used together with `range`
+    pub logarithmic: bool,
+}
+
+/// A value which can draw an editor for itself. Usually derived with `#[derive(Inspect)]`, which
+/// lays out the fields of a struct in a grid.
+pub trait Inspect {
+    /// Draw the editor, returns true if the value was changed
+    fn inspect(&mut self, ui: &mut Ui, options: &InspectOptions) -> bool;
+}
+
+fn numeric<N: Numeric>(value: &mut N, ui: &mut Ui, options: &InspectOptions) -> bool {
+    let range = options
+        .range
+        .clone()
+        .map(|range| N::from_f64(*range.start())..=N::from_f64(*range.end()));
+
+    match range {
+        Some(range) if options.logarithmic => ui
+            .add(Slider::new(value, range).logarithmic(true))
+            .changed(),
+        range => {
+            let mut drag = DragValue::new(value);
+            if let Some(range) = range {
+                drag = drag.clamp_range(range);
+            }
+            if let Some(speed) = options.speed {
+                drag = drag.speed(speed);
+            }
+            ui.add(drag).changed()
+        }
+    }
+}
+
+macro_rules! impl_numeric {
+    ($($t:ty),*) => {
+        $(
+            impl Inspect for $t {
+                fn inspect(&mut self, ui: &mut Ui, options: &InspectOptions) -> bool {
+                    numeric(self, ui, options)
+                }
+            }
+        )*
+    };
+}
+
+impl_numeric!(f32, f64, i8, u8, i16, u16, i32, u32, i64, u64, isize, usize);
+
+impl Inspect for bool {
+    fn inspect(&mut self, ui: &mut Ui, options: &InspectOptions) -> bool {
+        ui.checkbox(self, "").changed()
+    }
+}
+
+impl Inspect for String {
+    fn inspect(&mut self, ui: &mut Ui, options: &InspectOptions) -> bool {
+        ui.add(TextEdit::singleline(self)).changed()
+    }
+}
+
+impl<T: Inspect, const N: usize> Inspect for [T; N] {
+    fn inspect(&mut self, ui: &mut Ui, options: &InspectOptions) -> bool {
+        ui.horizontal(|ui| {
+            self.iter_mut()
+                .fold(false, |changed, value| value.inspect(ui, options) | changed)
+        })
+        .inner
+    }
+}
+
+/// A checkbox, followed by the editor of the value while there is one. Checking the box starts
+/// from the default value.
+impl<T: Inspect + Default> Inspect for Option<T> {
+    fn inspect(&mut self, ui: &mut Ui, options: &InspectOptions) -> bool {
+        ui.horizontal(|ui| {
+            let mut enabled = self.is_some();
+            let mut changed = ui.checkbox(&mut enabled, "").changed();
+            if changed {
+                *self = enabled.then(T::default);
+            }
+            if let Some(value) = self {
+                changed |= value.inspect(ui, options);
+            }
+            changed
+        })
+        .inner
+    }
+}
@@ -6,12 +6,19 @@ use vulkano::{
 };
 use winit::{event::WindowEvent, event_loop::EventLoopWindowTarget};
 
+use crate::storage::Storage;
+
 #[cfg(feature = "egui")]
 pub mod egui;
 
 #[cfg(feature = "egui")]
 pub mod egui_widgets;
 
+#[cfg(feature = "egui")]
+pub mod inspect;
+
+pub mod null;
+
 // An abstraction layer over the gui library to allow for easier switching between two libraries
 
 /// Represents an arbitrary immediate mode gui implementation such as imgui-rs or egui
@@ -20,10 +27,11 @@ pub trait GuiImplementation {
         event_loop: &EventLoopWindowTarget<()>,
         surface: Arc<Surface>,
         graphics_queue: Arc<Queue>,
-        subpass: Subpass,
+        subpass: Option<Subpass>,
     ) -> Self;
 
-    fn render(&mut self, dimensions: [u32; 2]) -> SecondaryAutoCommandBuffer;
+    /// Draw the ui into the ui subpass, `None` if there is nothing to draw
+    fn render(&mut self, dimensions: [u32; 2]) -> Option<SecondaryAutoCommandBuffer>;
 
     type Context;
 
@@ -36,7 +44,19 @@ pub trait GuiImplementation {
     // Return the leftover area
     fn viewport(&self, scale_factor: f32) -> Viewport;
 
+    /// Restore window positions and other ui state of the last session
+    fn load_state(&mut self, storage: &Storage) {}
+
+    /// Store the ui state, called when the app exits
+    fn save_state(&self, storage: &mut Storage) {}
+
     fn requested_format() -> Option<Format> {
         Some(Format::B8G8R8A8_SRGB)
     }
+
+    /// Whether the final render pass needs a separate subpass for the ui, if not `new` receives
+    /// `None` and `render` is never called
+    fn requires_subpass() -> bool {
+        true
+    }
 }
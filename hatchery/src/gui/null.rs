@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use super::GuiImplementation;
+use vulkano::{
+    command_buffer::SecondaryAutoCommandBuffer, device::Queue,
+    pipeline::graphics::viewport::Viewport, render_pass::Subpass, swapchain::Surface,
+};
+use winit::{event::WindowEvent, event_loop::EventLoopWindowTarget, window::Window};
+
+/// Gui implementation which draws nothing, for apps without a ui or when the `egui` feature is
+/// disabled. The viewport covers the whole window and no ui subpass is created.
+pub struct NullGui {
+    surface: Arc<Surface>,
+}
+
+impl GuiImplementation for NullGui {
+    type Context = ();
+
+    fn new(
+        event_loop: &EventLoopWindowTarget<()>,
+        surface: Arc<Surface>,
+        graphics_queue: Arc<Queue>,
+        subpass: Option<Subpass>,
+    ) -> Self {
+        Self { surface }
+    }
+
+    fn viewport(&self, scale_factor: f32) -> Viewport {
+        let window = self
+            .surface
+            .object()
+            .unwrap()
+            .downcast_ref::<Window>()
+            .unwrap();
+        let dimensions = window.inner_size();
+
+        Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [dimensions.width as f32, dimensions.height as f32],
+            depth_range: 0.0..1.0,
+        }
+    }
+
+    fn immediate(&mut self, ui: impl FnOnce(&mut ())) {
+        ui(&mut ());
+    }
+
+    fn update(&mut self, event: &WindowEvent) -> bool {
+        false
+    }
+
+    fn render(&mut self, dimensions: [u32; 2]) -> Option<SecondaryAutoCommandBuffer> {
+        None
+    }
+
+    fn requires_subpass() -> bool {
+        false
+    }
+}
@@ -0,0 +1,564 @@
+use std::{fmt, fs, io, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use winit::event::{
+    ElementState, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+use crate::storage::Storage;
+
+/// Input which can trigger an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    /// Key by the character it produces, depends on the keyboard layout
+    Key(VirtualKeyCode),
+    /// Key by its physical position, the same on every layout but platform specific
+    ScanCode(u32),
+    Mouse(MouseButton),
+    ScrollUp,
+    ScrollDown,
+}
+
+/// Scan codes of the letter keys, by the letter they produce on a QWERTY layout
+#[cfg(target_os = "macos")]
+const LETTER_SCAN_CODES: [(VirtualKeyCode, u32); 26] = [
+    (VirtualKeyCode::A, 0),
+    (VirtualKeyCode::S, 1),
+    (VirtualKeyCode::D, 2),
+    (VirtualKeyCode::F, 3),
+    (VirtualKeyCode::H, 4),
+    (VirtualKeyCode::G, 5),
+    (VirtualKeyCode::Z, 6),
+    (VirtualKeyCode::X, 7),
+    (VirtualKeyCode::C, 8),
+    (VirtualKeyCode::V, 9),
+    (VirtualKeyCode::B, 11),
+    (VirtualKeyCode::Q, 12),
+    (VirtualKeyCode::W, 13),
+    (VirtualKeyCode::E, 14),
+    (VirtualKeyCode::R, 15),
+    (VirtualKeyCode::Y, 16),
+    (VirtualKeyCode::T, 17),
+    (VirtualKeyCode::O, 31),
+    (VirtualKeyCode::U, 32),
+    (VirtualKeyCode::I, 34),
+    (VirtualKeyCode::P, 35),
+    (VirtualKeyCode::L, 37),
+    (VirtualKeyCode::J, 38),
+    (VirtualKeyCode::K, 40),
+    (VirtualKeyCode::N, 45),
+    (VirtualKeyCode::M, 46),
+];
+
+/// Scan codes of the letter keys, by the letter they produce on a QWERTY layout
+#[cfg(not(target_os = "macos"))]
+const LETTER_SCAN_CODES: [(VirtualKeyCode, u32); 26] = [
+    (VirtualKeyCode::Q, 16),
+    (VirtualKeyCode::W, 17),
+    (VirtualKeyCode::E, 18),
+    (VirtualKeyCode::R, 19),
+    (VirtualKeyCode::T, 20),
+    (VirtualKeyCode::Y, 21),
+    (VirtualKeyCode::U, 22),
+    (VirtualKeyCode::I, 23),
+    (VirtualKeyCode::O, 24),
+    (VirtualKeyCode::P, 25),
+    (VirtualKeyCode::A, 30),
+    (VirtualKeyCode::S, 31),
+    (VirtualKeyCode::D, 32),
+    (VirtualKeyCode::F, 33),
+    (VirtualKeyCode::G, 34),
+    (VirtualKeyCode::H, 35),
+    (VirtualKeyCode::J, 36),
+    (VirtualKeyCode::K, 37),
+    (VirtualKeyCode::L, 38),
+    (VirtualKeyCode::Z, 44),
+    (VirtualKeyCode::X, 45),
+    (VirtualKeyCode::C, 46),
+    (VirtualKeyCode::V, 47),
+    (VirtualKeyCode::B, 48),
+    (VirtualKeyCode::N, 49),
+    (VirtualKeyCode::M, 50),
+];
+
+/// A trigger together with the modifiers which have to be held, written like `Ctrl+Shift+S`,
+/// `Mouse:Left`, `ScrollUp`, `Pos:W` or `Scan:17` in the keymap file. `Pos:W` is the key where W
+/// is on a QWERTY keyboard, whatever layout is active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Binding {
+    pub trigger: Trigger,
+    pub modifiers: ModifiersState,
+}
+
+impl Binding {
+    pub fn key(key: VirtualKeyCode) -> Self {
+        Trigger::Key(key).into()
+    }
+
+    /// The key at the position of `key` on a QWERTY keyboard, so WASD stays WASD-shaped on
+    /// AZERTY or Dvorak. Only letters have known positions, other keys are bound by name.
+    pub fn physical(key: VirtualKeyCode) -> Self {
+        LETTER_SCAN_CODES
+            .iter()
+            .find(|(letter, _)| *letter == key)
+            .map_or(Trigger::Key(key), |(_, code)| Trigger::ScanCode(*code))
+            .into()
+    }
+
+    pub fn mouse(button: MouseButton) -> Self {
+        Trigger::Mouse(button).into()
+    }
+
+    pub fn with_modifiers(mut self, modifiers: ModifiersState) -> Self {
+        self.modifiers = modifiers;
+        self
+    }
+
+    /// Bindings with modifiers need exactly those to be held. Plain bindings ignore Shift, so
+    /// they still work while it is held, but not together with any other modifier.
+    fn accepts(&self, held: ModifiersState) -> bool {
+        if self.modifiers.is_empty() {
+            held.difference(ModifiersState::SHIFT).is_empty()
+        } else {
+            held == self.modifiers
+        }
+    }
+}
+
+impl From<Trigger> for Binding {
+    fn from(trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+}
+
+const MODIFIERS: [(&str, ModifiersState); 4] = [
+    ("Ctrl", ModifiersState::CTRL),
+    ("Shift", ModifiersState::SHIFT),
+    ("Alt", ModifiersState::ALT),
+    ("Logo", ModifiersState::LOGO),
+];
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, modifier) in MODIFIERS {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{name}+")?;
+            }
+        }
+
+        match self.trigger {
+            Trigger::Key(key) => write!(f, "{key:?}"),
+            Trigger::ScanCode(code) => match LETTER_SCAN_CODES.iter().find(|(_, c)| *c == code) {
+                Some((letter, _)) => write!(f, "Pos:{letter:?}"),
+                None => write!(f, "Scan:{code}"),
+            },
+            Trigger::Mouse(MouseButton::Other(button)) => write!(f, "Mouse:{button}"),
+            Trigger::Mouse(button) => write!(f, "Mouse:{button:?}"),
+            Trigger::ScrollUp => write!(f, "ScrollUp"),
+            Trigger::ScrollDown => write!(f, "ScrollDown"),
+        }
+    }
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let trigger = parts.pop().unwrap_or_default();
+
+        let mut modifiers = ModifiersState::empty();
+        for part in parts {
+            let (_, modifier) = MODIFIERS
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(part))
+                .ok_or_else(|| format!("unknown modifier `{part}` in `{text}`"))?;
+            modifiers |= *modifier;
+        }
+
+        let trigger = if trigger.eq_ignore_ascii_case("ScrollUp") {
+            Trigger::ScrollUp
+        } else if trigger.eq_ignore_ascii_case("ScrollDown") {
+            Trigger::ScrollDown
+        } else if let Some(code) = trigger.strip_prefix("Scan:") {
+            let code = code
+                .parse()
+                .map_err(|_| format!("invalid scan code in `{text}`"))?;
+            Trigger::ScanCode(code)
+        } else if let Some(letter) = trigger.strip_prefix("Pos:") {
+            LETTER_SCAN_CODES
+                .iter()
+                .find(|(key, _)| format!("{key:?}").eq_ignore_ascii_case(letter))
+                .map(|(_, code)| Trigger::ScanCode(*code))
+                .ok_or_else(|| format!("`{letter}` has no known position in `{text}`"))?
+        } else if let Some(button) = trigger.strip_prefix("Mouse:") {
+            Trigger::Mouse(match button {
+                "Left" => MouseButton::Left,
+                "Right" => MouseButton::Right,
+                "Middle" => MouseButton::Middle,
+                other => MouseButton::Other(
+                    other
+                        .parse()
+                        .map_err(|_| format!("unknown mouse button in `{text}`"))?,
+                ),
+            })
+        } else {
+            // Key names are the `VirtualKeyCode` variants, e.g. `W`, `Key1` or `Space`
+            let key = serde_json::from_value(Value::String(trigger.to_owned()))
+                .map_err(|_| format!("unknown key `{trigger}` in `{text}`"))?;
+            Trigger::Key(key)
+        };
+
+        Ok(Self { trigger, modifiers })
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+/// A named action and its bindings
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub bindings: Vec<Binding>,
+}
+
+/// An action which was triggered by an input event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActionEvent {
+    pub name: &'static str,
+    /// Scroll distance for scroll bindings, in lines or in pixels for trackpads, 1 otherwise
+    pub amount: f32,
+}
+
+/// Maps raw input to named actions, so apps never match on keys directly. Apps register their
+/// actions with default bindings and can then load a keymap file overriding them, which makes
+/// the controls work on any keyboard layout.
+#[derive(Debug, Clone, Default)]
+pub struct ActionMap {
+    actions: Vec<Action>,
+    modifiers: ModifiersState,
+}
+
+impl ActionMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an action with its default bindings
+    pub fn action(
+        mut self,
+        name: &'static str,
+        description: &'static str,
+        bindings: impl IntoIterator<Item = Binding>,
+    ) -> Self {
+        self.actions.push(Action {
+            name,
+            description,
+            bindings: bindings.into_iter().collect(),
+        });
+        self
+    }
+
+    /// Movement and zoom actions shared by the apps with a free camera
+    pub fn with_camera_controls(self) -> Self {
+        self.action(
+            "forward",
+            "Move the camera forward",
+            [Binding::physical(VirtualKeyCode::W)],
+        )
+        .action(
+            "backward",
+            "Move the camera backward",
+            [Binding::physical(VirtualKeyCode::S)],
+        )
+        .action(
+            "left",
+            "Move the camera left",
+            [Binding::physical(VirtualKeyCode::A)],
+        )
+        .action(
+            "right",
+            "Move the camera right",
+            [Binding::physical(VirtualKeyCode::D)],
+        )
+        .action(
+            "up",
+            "Move the camera up",
+            [Binding::physical(VirtualKeyCode::Q)],
+        )
+        .action(
+            "down",
+            "Move the camera down",
+            [Binding::physical(VirtualKeyCode::E)],
+        )
+        .action("zoom_in", "Zoom in", [Trigger::ScrollUp.into()])
+        .action("zoom_out", "Zoom out", [Trigger::ScrollDown.into()])
+    }
+
+    /// Apply the `keymap.json` next to the app's storage, errors are printed and leave the
+    /// default bindings in place
+    pub fn with_keymap(mut self, storage: &Storage) -> Self {
+        if let Some(path) = storage.file("keymap.json") {
+            if let Err(err) = self.load(&path) {
+                eprintln!("failed to load keymap {}: {err}", path.display());
+            }
+        }
+        self
+    }
+
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// Replace the bindings of an action, returns false if there is no action called `name`
+    pub fn rebind(&mut self, name: &str, bindings: Vec<Binding>) -> bool {
+        match self.actions.iter_mut().find(|action| action.name == name) {
+            Some(action) => {
+                action.bindings = bindings;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Load a keymap file mapping action names to lists of bindings. Actions missing from the
+    /// file keep their bindings, if the file doesn't exist the current bindings are written to it
+    /// so they can be edited.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return self.save(path);
+        }
+
+        let keymap: serde_json::Map<String, Value> =
+            serde_json::from_str(&fs::read_to_string(path)?)?;
+        for (name, bindings) in keymap {
+            let bindings = serde_json::from_value(bindings)?;
+            if !self.rebind(&name, bindings) {
+                eprintln!("keymap: unknown action `{name}`");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let keymap: serde_json::Map<String, Value> = self
+            .actions
+            .iter()
+            .map(|action| {
+                Ok((
+                    action.name.to_owned(),
+                    serde_json::to_value(&action.bindings)?,
+                ))
+            })
+            .collect::<serde_json::Result<_>>()?;
+
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&keymap)?)
+    }
+
+    /// Actions triggered by `event`, keys and buttons only trigger when pressed
+    pub fn handle(&mut self, event: &WindowEvent) -> Vec<ActionEvent> {
+        let (triggers, amount) = match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                return Vec::new();
+            }
+            WindowEvent::KeyboardInput { input, .. } if input.state == ElementState::Pressed => {
+                let mut triggers = vec![Trigger::ScanCode(input.scancode)];
+                triggers.extend(input.virtual_keycode.map(Trigger::Key));
+                (triggers, 1.0)
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button,
+                ..
+            } => (vec![Trigger::Mouse(*button)], 1.0),
+            WindowEvent::MouseWheel { delta, .. } => {
+                // Pixel deltas are passed on unscaled, as the apps zoomed with them before
+                let change = match delta {
+                    MouseScrollDelta::LineDelta(_x, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
+                };
+                let trigger = if change >= 0.0 {
+                    Trigger::ScrollUp
+                } else {
+                    Trigger::ScrollDown
+                };
+                (vec![trigger], change.abs())
+            }
+            _ => return Vec::new(),
+        };
+
+        self.actions
+            .iter()
+            .filter(|action| {
+                action.bindings.iter().any(|binding| {
+                    binding.accepts(self.modifiers) && triggers.contains(&binding.trigger)
+                })
+            })
+            .map(|action| ActionEvent {
+                name: action.name,
+                amount,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::{DeviceId, KeyboardInput};
+
+    use super::*;
+
+    fn scan_code(letter: VirtualKeyCode) -> u32 {
+        LETTER_SCAN_CODES
+            .iter()
+            .find(|(key, _)| *key == letter)
+            .map(|(_, code)| *code)
+            .unwrap()
+    }
+
+    #[allow(deprecated)]
+    fn press(scancode: u32, key: Option<VirtualKeyCode>) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode,
+                state: ElementState::Pressed,
+                virtual_keycode: key,
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    fn triggered(actions: &mut ActionMap, event: &WindowEvent) -> Vec<&'static str> {
+        actions
+            .handle(event)
+            .into_iter()
+            .map(|action| action.name)
+            .collect()
+    }
+
+    #[test]
+    fn bindings_round_trip() {
+        for text in [
+            "Ctrl+Shift+S",
+            "Pos:W",
+            "Mouse:Left",
+            "Mouse:4",
+            "ScrollUp",
+            "Space",
+        ] {
+            let binding: Binding = text.parse().unwrap();
+            assert_eq!(binding.to_string(), text);
+        }
+
+        // Modifiers are written in a fixed order, whatever order they are parsed in
+        let binding: Binding = "shift+ctrl+S".parse().unwrap();
+        assert_eq!(
+            binding.modifiers,
+            ModifiersState::CTRL | ModifiersState::SHIFT
+        );
+        assert_eq!(binding.to_string(), "Ctrl+Shift+S");
+
+        // Scan codes of letters are written as their position
+        let binding: Binding = "Scan:17".parse().unwrap();
+        assert_eq!(binding.trigger, Trigger::ScanCode(17));
+        assert_eq!(binding.to_string().parse::<Binding>(), Ok(binding));
+        assert_eq!(
+            "Scan:999".parse::<Binding>().unwrap().to_string(),
+            "Scan:999"
+        );
+
+        for text in ["Hyper+W", "Pos:1", "Scan:x", "Mouse:Back", "NotAKey"] {
+            assert!(text.parse::<Binding>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn scan_code_table_is_a_bijection() {
+        for (i, (key, code)) in LETTER_SCAN_CODES.iter().enumerate() {
+            for (other_key, other_code) in &LETTER_SCAN_CODES[i + 1..] {
+                assert_ne!(key, other_key);
+                assert_ne!(code, other_code);
+            }
+            assert_eq!(Binding::physical(*key).trigger, Trigger::ScanCode(*code));
+        }
+    }
+
+    #[test]
+    fn physical_bindings_follow_the_key_position() {
+        let mut actions = ActionMap::new().with_camera_controls();
+
+        // The key of W on QWERTY produces Z on AZERTY and a comma on Dvorak
+        let w = scan_code(VirtualKeyCode::W);
+        for produced in [VirtualKeyCode::W, VirtualKeyCode::Z, VirtualKeyCode::Comma] {
+            assert_eq!(
+                triggered(&mut actions, &press(w, Some(produced))),
+                ["forward"]
+            );
+        }
+
+        // AZERTY has W where QWERTY has Z, which isn't bound
+        let z = scan_code(VirtualKeyCode::Z);
+        assert!(triggered(&mut actions, &press(z, Some(VirtualKeyCode::W))).is_empty());
+
+        // A and Q swap places on AZERTY, so the left key still moves left
+        let a = scan_code(VirtualKeyCode::A);
+        assert_eq!(
+            triggered(&mut actions, &press(a, Some(VirtualKeyCode::Q))),
+            ["left"]
+        );
+    }
+
+    #[test]
+    fn plain_bindings_ignore_shift() {
+        let mut actions = ActionMap::new()
+            .action("jump", "", [Binding::key(VirtualKeyCode::Space)])
+            .action(
+                "save",
+                "",
+                [Binding::key(VirtualKeyCode::S).with_modifiers(ModifiersState::CTRL)],
+            );
+        let space = press(57, Some(VirtualKeyCode::Space));
+        let s = press(31, Some(VirtualKeyCode::S));
+
+        let held = |actions: &mut ActionMap, modifiers| {
+            actions.handle(&WindowEvent::ModifiersChanged(modifiers));
+        };
+
+        held(&mut actions, ModifiersState::SHIFT);
+        assert_eq!(triggered(&mut actions, &space), ["jump"]);
+        assert!(triggered(&mut actions, &s).is_empty());
+
+        held(&mut actions, ModifiersState::CTRL);
+        assert!(triggered(&mut actions, &space).is_empty());
+        assert_eq!(triggered(&mut actions, &s), ["save"]);
+
+        held(&mut actions, ModifiersState::CTRL | ModifiersState::SHIFT);
+        assert!(triggered(&mut actions, &s).is_empty());
+    }
+}
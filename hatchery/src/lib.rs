@@ -1,11 +1,16 @@
 #![allow(unused_variables, dead_code)]
 
+// Lets the derive macros refer to `::hatchery` from inside this crate as well
+extern crate self as hatchery;
+
 mod gui;
+pub mod input;
 pub mod util;
 
 mod engine;
 mod performance;
 mod render_pass;
+mod storage;
 
 pub use engine::Engine;
 pub use engine::EngineApi;
@@ -14,7 +19,9 @@ pub use engine::EngineLauncher;
 pub use engine::EngineOptions;
 pub use engine::RenderInfo;
 pub use engine::WindowOptions;
+pub use gui::null::NullGui;
 pub use gui::GuiImplementation;
+pub use storage::Storage;
 
 // pub extern crate vulkano;
 // pub extern crate vulkano_util;
@@ -38,3 +45,6 @@ pub mod egui_implementation {
 
 #[cfg(feature = "egui")]
 pub use crate::gui::egui_widgets;
+
+#[cfg(feature = "egui")]
+pub use crate::gui::inspect;
@@ -2,8 +2,7 @@ use std::sync::Arc;
 
 use vulkano::{
     command_buffer::{
-        AutoCommandBufferBuilder, CommandBufferUsage,
-        RenderPassBeginInfo, SubpassContents,
+        AutoCommandBufferBuilder, CommandBufferUsage, RenderPassBeginInfo, SubpassContents,
     },
     device::{Device, Queue},
     format::Format,
@@ -25,8 +24,8 @@ pub struct FinalRenderPass {
 }
 
 impl FinalRenderPass {
-    pub fn new(context: &VulkanoContext, format: Format) -> Self {
-        let render_pass = Self::create_render_pass(context.device().clone(), format);
+    pub fn new(context: &VulkanoContext, format: Format, ui_subpass: bool) -> Self {
+        let render_pass = Self::create_render_pass(context.device().clone(), format, ui_subpass);
 
         Self {
             device: context.device().clone(),
@@ -39,11 +38,32 @@ impl FinalRenderPass {
         Subpass::from(self.render_pass.clone(), 0).unwrap()
     }
 
-    pub fn ui_subpass(&self) -> Subpass {
-        Subpass::from(self.render_pass.clone(), 1).unwrap()
+    /// `None` if the render pass was created without a ui subpass
+    pub fn ui_subpass(&self) -> Option<Subpass> {
+        Subpass::from(self.render_pass.clone(), 1)
     }
 
-    fn create_render_pass(device: Arc<Device>, format: Format) -> Arc<RenderPass> {
+    fn create_render_pass(
+        device: Arc<Device>,
+        format: Format,
+        ui_subpass: bool,
+    ) -> Arc<RenderPass> {
+        if !ui_subpass {
+            return vulkano::single_pass_renderpass!(
+                device,
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    }
+                },
+                pass: { color: [color], depth_stencil: {} }
+            )
+            .expect("error creating render pass");
+        }
+
         vulkano::ordered_passes_renderpass!(
             device,
             attachments: {
@@ -113,6 +133,7 @@ impl FinalRenderPass {
             command_buffer: &mut primary_builder,
             queue: self.graphics_queue.clone(),
             command_allocator: api.construction().command_allocator(),
+            construction: api.construction(),
             subpass,
             viewport,
         };
@@ -120,12 +141,15 @@ impl FinalRenderPass {
         engine.render(&mut render_info, api);
 
         // Render gui
-        primary_builder
-            .next_subpass(SubpassContents::SecondaryCommandBuffers)
-            .unwrap();
-
-        let cb = gui.render(image_dimensions.width_height());
-        primary_builder.execute_commands(cb).unwrap();
+        if self.ui_subpass().is_some() {
+            primary_builder
+                .next_subpass(SubpassContents::SecondaryCommandBuffers)
+                .unwrap();
+
+            if let Some(cb) = gui.render(image_dimensions.width_height()) {
+                primary_builder.execute_commands(cb).unwrap();
+            }
+        }
 
         // End render pass
         primary_builder.end_render_pass().unwrap();
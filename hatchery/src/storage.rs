@@ -0,0 +1,74 @@
+use std::{collections::HashMap, env, fs, io, path::PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Key value store which is loaded when the engine starts and written back when it stops. Each
+/// app gets its own json file in the user's config directory, apps without an id get an empty
+/// storage which is never written.
+pub struct Storage {
+    path: Option<PathBuf>,
+    values: HashMap<String, Value>,
+}
+
+impl Storage {
+    /// Load the storage of the app called `app_id`, missing or unreadable files start out empty
+    pub fn load(app_id: Option<&str>) -> Self {
+        let path = app_id.map(|app_id| config_dir().join(app_id).join("state.json"));
+        let values = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        Self { path, values }
+    }
+
+    /// Value stored under `key`, `None` if it is missing or no longer matches the type
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.values
+            .get(key)
+            .and_then(|value| T::deserialize(value).ok())
+    }
+
+    pub fn set<T: Serialize>(&mut self, key: &str, value: &T) {
+        if let Ok(value) = serde_json::to_value(value) {
+            self.values.insert(key.to_owned(), value);
+        }
+    }
+
+    /// Path of another file in the app's config directory, `None` if the storage isn't persisted
+    pub fn file(&self, name: &str) -> Option<PathBuf> {
+        self.path
+            .as_ref()
+            .and_then(|path| path.parent())
+            .map(|dir| dir.join(name))
+    }
+
+    /// Write all values to disk
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&self.values)?)
+    }
+}
+
+/// Platform config directory, falls back to the working directory
+fn config_dir() -> PathBuf {
+    let dir = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    dir.unwrap_or_default()
+}
@@ -5,7 +5,7 @@ use vulkano::{
     buffer::{
         BufferAccess, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess,
     },
-    command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferInfo},
+    command_buffer::{AutoCommandBufferBuilder, BufferCopy, CommandBufferUsage, CopyBufferInfo},
     sync::{self, GpuFuture},
 };
 
@@ -56,6 +56,51 @@ pub trait AbstractBuffer<T: BufferData> {
         future.wait(None).unwrap();
     }
 
+    /// Copy `len` elements starting at `offset` back to the CPU, the buffer must have been
+    /// created with `transfer_src` usage
+    fn read_back(&self, context: &ConstructionContext, offset: u64, len: u64) -> Vec<T> {
+        let stride = std::mem::size_of::<T>() as u64;
+        let staging = SharedBuffer::<T>::new(
+            context,
+            BufferUsage {
+                transfer_dst: true,
+                ..BufferUsage::empty()
+            },
+            len,
+        );
+
+        let mut cb_builder = AutoCommandBufferBuilder::primary(
+            context.command_allocator(),
+            context.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        cb_builder
+            .copy_buffer(CopyBufferInfo {
+                regions: [BufferCopy {
+                    src_offset: offset * stride,
+                    dst_offset: 0,
+                    size: len * stride,
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyBufferInfo::buffers(self.buffer(), staging.buffer())
+            })
+            .unwrap();
+
+        let cb = cb_builder.build().unwrap();
+        let future = sync::now(context.device())
+            .then_execute(context.queue(), cb)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap();
+
+        future.wait(None).unwrap();
+
+        staging.typed_buffer().read().unwrap().to_vec()
+    }
+
     /// Length of the buffer
     fn len(&self) -> u32;
 }
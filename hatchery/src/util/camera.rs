@@ -1,5 +1,7 @@
 use cgmath::{Deg, Matrix4, Point3, SquareMatrix, Vector3};
+use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Copy)]
 pub struct ViewData {
     pub world: Matrix4<f32>,
     pub view: Matrix4<f32>,
@@ -7,6 +9,7 @@ pub struct ViewData {
     pub scale: f32,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Camera {
     pub scale: f32,
     pub fov: f32,
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+// Polynomial fits of the matplotlib colormaps, the same coefficients are used in
+// `point_cloud_vert.glsl` so the legend and the viewport agree
+
+const VIRIDIS: [[f32; 3]; 7] = [
+    [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+    [0.105_093_04, 1.404_613_5, 1.384_590_2],
+    [-0.330_861_83, 0.214_847_56, 0.095_095_165],
+    [-4.634_230_6, -5.799_101, -19.332_441],
+    [6.228_27, 14.179_933, 56.690_55],
+    [4.776_385, -13.745_146, -65.353_03],
+    [-5.435_456, 4.645_852_6, 26.312_435],
+];
+
+const MAGMA: [[f32; 3]; 7] = [
+    [-0.002_136_485, -0.000_749_655_05, -0.005_386_128],
+    [0.251_660_54, 0.677_523_24, 2.494_026_6],
+    [8.353_717, -3.577_719_5, 0.314_467_9],
+    [-27.668_733, 14.264_731, -13.649_213],
+    [52.176_14, -27.943_606, 12.944_169],
+    [-50.768_524, 29.046_583, 4.234_153],
+    [18.655_705, -11.489_774, -5.601_961_5],
+];
+
+const INFERNO: [[f32; 3]; 7] = [
+    [0.000_218_940_37, 0.001_651_004_6, -0.019_480_899],
+    [0.106_513_42, 0.563_956_4, 3.932_712_4],
+    [11.602_493, -3.972_854, -15.942_394],
+    [-41.703_995, 17.436_399, 44.354_145],
+    [77.162_94, -33.402_36, -81.807_31],
+    [-71.319_43, 32.626_064, 73.209_52],
+    [25.131_126, -12.242_669, -23.070_326],
+];
+
+const DIVERGING: [[f32; 3]; 3] = [
+    [0.230, 0.299, 0.754],
+    [0.865, 0.865, 0.865],
+    [0.706, 0.016, 0.150],
+];
+
+/// Colormaps available for scalar point attributes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Inferno,
+    Diverging,
+}
+
+impl Colormap {
+    /// Index used to select the colormap in the shaders
+    pub fn index(&self) -> u32 {
+        match self {
+            Colormap::Viridis => 0,
+            Colormap::Magma => 1,
+            Colormap::Inferno => 2,
+            Colormap::Diverging => 3,
+        }
+    }
+
+    /// Sample the colormap at `t` in [0, 1], returns an sRGB color
+    pub fn sample(&self, t: f32) -> [f32; 3] {
+        let t = t.clamp(0.0, 1.0);
+
+        let coefficients = match self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Inferno => &INFERNO,
+            Colormap::Diverging => {
+                let (low, high, s) = if t < 0.5 {
+                    (DIVERGING[0], DIVERGING[1], 2.0 * t)
+                } else {
+                    (DIVERGING[1], DIVERGING[2], 2.0 * t - 1.0)
+                };
+
+                return [0, 1, 2].map(|i| low[i] + (high[i] - low[i]) * s);
+            }
+        };
+
+        // Horner evaluation of the polynomial fit
+        [0, 1, 2].map(|i| {
+            coefficients
+                .iter()
+                .rev()
+                .fold(0.0, |acc, c| acc * t + c[i])
+                .clamp(0.0, 1.0)
+        })
+    }
+}
+
+/// How scalar values are mapped onto [0, 1] before sampling a colormap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub enum Normalization {
+    Linear,
+    Log,
+}
+
+impl Normalization {
+    pub fn index(&self) -> u32 {
+        match self {
+            Normalization::Linear => 0,
+            Normalization::Log => 1,
+        }
+    }
+}
+
+/// Full description of how a scalar attribute is turned into a color
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub struct ColorScale {
+    pub colormap: Colormap,
+    pub normalization: Normalization,
+    /// Overwritten by `fit` with auto range
+    pub range: [f32; 2],
+    pub auto_range: bool,
+}
+
+impl Default for ColorScale {
+    fn default() -> Self {
+        Self {
+            colormap: Colormap::Viridis,
+            normalization: Normalization::Linear,
+            range: [0.0, 1.0],
+            auto_range: true,
+        }
+    }
+}
+
+impl ColorScale {
+    /// Map a value onto [0, 1] according to the range and normalization
+    pub fn normalize(&self, value: f32) -> f32 {
+        let [min, max] = self.range;
+
+        let t = match self.normalization {
+            Normalization::Linear => (value - min) / (max - min),
+            Normalization::Log => {
+                let min = min.max(f32::MIN_POSITIVE);
+                (value.max(min) / min).ln() / (max.max(min) / min).ln()
+            }
+        };
+
+        if t.is_finite() {
+            t.clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Inverse of `normalize`, used to label the legend
+    pub fn denormalize(&self, t: f32) -> f32 {
+        let [min, max] = self.range;
+
+        match self.normalization {
+            Normalization::Linear => min + (max - min) * t,
+            Normalization::Log => {
+                let min = min.max(f32::MIN_POSITIVE);
+                min * (max.max(min) / min).powf(t)
+            }
+        }
+    }
+
+    /// Update the range to the extent of `values` if auto range is enabled, for log
+    /// normalization only positive values are considered
+    pub fn fit(&mut self, values: impl IntoIterator<Item = f32>) {
+        if !self.auto_range {
+            return;
+        }
+
+        let log = self.normalization == Normalization::Log;
+        let (min, max) = values
+            .into_iter()
+            .filter(|v| v.is_finite() && (!log || *v > 0.0))
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+
+        if min <= max {
+            self.range = [min, max];
+        }
+    }
+}
@@ -4,8 +4,11 @@ use std::{
 };
 
 use vulkano::{
-    buffer::BufferContents,
-    command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage},
+    buffer::{BufferContents, DeviceLocalBuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, DispatchIndirectCommand,
+        PrimaryAutoCommandBuffer,
+    },
     descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
     device::Device,
     pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
@@ -15,8 +18,6 @@ use vulkano::{
 
 use super::ConstructionContext;
 
-// TODO: multiple invocations
-
 pub struct ComputeShaderExecutor<G: ComputeShader> {
     module: Arc<ShaderModule>,
     pipeline: Arc<ComputePipeline>,
@@ -26,7 +27,7 @@ pub struct ComputeShaderExecutor<G: ComputeShader> {
 
 impl<G: ComputeShader> ComputeShaderExecutor<G> {
     pub fn new(context: &ConstructionContext, shader: G) -> Self {
-        let module = G::load_module(context.device());
+        let module = shader.load_variant(context.device());
         let pipeline = ComputePipeline::new(
             context.device(),
             module.entry_point(G::entry_point()).unwrap(),
@@ -53,13 +54,15 @@ impl<G: ComputeShader> ComputeShaderExecutor<G> {
     }
 
     pub fn execute(&self, context: &ConstructionContext) {
-        let mut builder = AutoCommandBufferBuilder::primary(
-            context.command_allocator(),
-            context.queue().queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
+        let mut builder = begin(context);
+        self.record(&mut builder);
+        submit(context, builder);
+    }
 
+    /// Record a dispatch with the current constants of the shader, so several passes or shaders
+    /// can run in one submission. Vulkano inserts the barriers between dispatches which share
+    /// buffers.
+    pub fn record(&self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
         let constants = self.shader.push_constants();
 
         builder
@@ -75,19 +78,38 @@ impl<G: ComputeShader> ComputeShaderExecutor<G> {
             builder.push_constants(self.pipeline.layout().clone(), 0, constants);
         }
 
-        builder.dispatch(self.shader.dispatch_size()).unwrap();
-
-        let command_buffer = builder.build().unwrap();
-        sync::now(context.device())
-            .then_execute(context.queue(), command_buffer)
-            .unwrap()
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
+        match self.shader.indirect_dispatch() {
+            Some(indirect) => builder.dispatch_indirect(indirect).unwrap(),
+            None => builder.dispatch(self.shader.dispatch_size()).unwrap(),
+        };
     }
 }
 
+/// Start a command buffer on the compute queue for `ComputeShaderExecutor::record`
+pub fn begin(context: &ConstructionContext) -> AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> {
+    AutoCommandBufferBuilder::primary(
+        context.command_allocator(),
+        context.queue().queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap()
+}
+
+/// Submit a command buffer started with `begin` and wait for it to finish
+pub fn submit(
+    context: &ConstructionContext,
+    builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+) {
+    let command_buffer = builder.build().unwrap();
+    sync::now(context.device())
+        .then_execute(context.queue(), command_buffer)
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+}
+
 impl<G: ComputeShader> Deref for ComputeShaderExecutor<G> {
     type Target = G;
 
@@ -105,12 +127,24 @@ impl<G: ComputeShader> DerefMut for ComputeShaderExecutor<G> {
 pub trait ComputeShader {
     fn load_module(device: Arc<Device>) -> Arc<ShaderModule>;
 
+    /// Load the module for this instance, for shaders compiled in several variants which are
+    /// picked at runtime
+    fn load_variant(&self, device: Arc<Device>) -> Arc<ShaderModule> {
+        Self::load_module(device)
+    }
+
     fn entry_point() -> &'static str {
         "main"
     }
 
     fn dispatch_size(&self) -> [u32; 3];
 
+    /// Take the dispatch size from a buffer instead of `dispatch_size`, so an earlier pass on the
+    /// GPU can decide it. The buffer needs `indirect_buffer` usage.
+    fn indirect_dispatch(&self) -> Option<Arc<DeviceLocalBuffer<[DispatchIndirectCommand]>>> {
+        None
+    }
+
     fn write_descriptors(&self) -> Vec<WriteDescriptorSet>;
 
     type Constants: BufferContents;
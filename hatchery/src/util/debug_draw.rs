@@ -0,0 +1,251 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Point3, Vector3};
+use vulkano::{
+    buffer::BufferUsage,
+    impl_vertex,
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendState,
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            vertex_input::BuffersDefinition,
+            viewport::ViewportState,
+        },
+        GraphicsPipeline, Pipeline,
+    },
+    render_pass::Subpass,
+};
+
+use crate::RenderInfo;
+
+use super::{
+    buffer::{AbstractBuffer, SharedBuffer},
+    camera::ViewData,
+    ConstructionContext,
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/util/debug_draw_vert.glsl",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        },
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/util/debug_draw_frag.glsl"
+    }
+}
+
+/// Number of segments used to approximate circles
+const CIRCLE_SEGMENTS: u32 = 32;
+
+#[repr(C)]
+#[derive(Default, Pod, Zeroable, Clone, Copy)]
+pub struct DebugVertex {
+    pub line_pos: [f32; 4],
+    pub line_color: [f32; 4],
+}
+
+impl_vertex!(DebugVertex, line_pos, line_color);
+
+/// Immediate mode line renderer, everything queued during a frame is drawn in a single batch
+/// when `draw` is called
+pub struct DebugDraw {
+    pipeline: Arc<GraphicsPipeline>,
+    vertices: Vec<DebugVertex>,
+    subpass: Subpass,
+}
+
+impl DebugDraw {
+    pub fn new(context: &ConstructionContext, subpass: Subpass) -> Self {
+        let vs = vs::load(context.device()).unwrap();
+        let fs = fs::load(context.device()).unwrap();
+
+        let pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new().vertex::<DebugVertex>())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new().topology(PrimitiveTopology::LineList))
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .render_pass(subpass.clone())
+            .color_blend_state(ColorBlendState::new(1).blend_alpha())
+            .build(context.device())
+            .expect("failed to make pipeline");
+
+        Self {
+            pipeline,
+            vertices: Vec::new(),
+            subpass,
+        }
+    }
+
+    /// Queue a single line segment
+    pub fn line(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4]) {
+        self.vertices.push(DebugVertex {
+            line_pos: [from.x, from.y, from.z, 1.0],
+            line_color: color,
+        });
+        self.vertices.push(DebugVertex {
+            line_pos: [to.x, to.y, to.z, 1.0],
+            line_color: color,
+        });
+    }
+
+    /// Queue a line with a small four-pronged head at `to`
+    pub fn arrow(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4]) {
+        self.line(from, to, color);
+
+        let direction = to - from;
+        let length = direction.magnitude();
+        if length <= f32::EPSILON {
+            return;
+        }
+
+        let direction = direction / length;
+        let (side, other) = perpendicular_basis(direction);
+        let head = 0.2 * length;
+        let base = to - direction * head;
+
+        for offset in [side, -side, other, -other] {
+            self.line(to, base + offset * (0.5 * head), color);
+        }
+    }
+
+    /// Queue the edges of an axis aligned box
+    pub fn wire_box(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 4]) {
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+
+        // Each edge connects two corners which differ in exactly one bit
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    /// Queue a circle lying in the plane perpendicular to `normal`
+    pub fn circle(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        color: [f32; 4],
+    ) {
+        let (side, other) = perpendicular_basis(normal.normalize());
+        let point = |i: u32| {
+            let angle = TAU * i as f32 / CIRCLE_SEGMENTS as f32;
+            center + (side * angle.cos() + other * angle.sin()) * radius
+        };
+
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    /// Queue a red/green/blue axis triad for the x/y/z axes
+    pub fn axes(&mut self, origin: Point3<f32>, length: f32) {
+        self.arrow(
+            origin,
+            origin + Vector3::unit_x() * length,
+            [1.0, 0.2, 0.2, 1.0],
+        );
+        self.arrow(
+            origin,
+            origin + Vector3::unit_y() * length,
+            [0.2, 1.0, 0.2, 1.0],
+        );
+        self.arrow(
+            origin,
+            origin + Vector3::unit_z() * length,
+            [0.2, 0.2, 1.0, 1.0],
+        );
+    }
+
+    /// Queue a square grid in the xz plane (the ground plane for the default camera)
+    pub fn grid(&mut self, center: Point3<f32>, extent: f32, divisions: u32, color: [f32; 4]) {
+        let divisions = divisions.max(1);
+        let step = 2.0 * extent / divisions as f32;
+
+        for i in 0..=divisions {
+            let offset = -extent + step * i as f32;
+            self.line(
+                center + Vector3::new(offset, 0.0, -extent),
+                center + Vector3::new(offset, 0.0, extent),
+                color,
+            );
+            self.line(
+                center + Vector3::new(-extent, 0.0, offset),
+                center + Vector3::new(extent, 0.0, offset),
+                color,
+            );
+        }
+    }
+
+    /// Discard everything queued so far without drawing it
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Draw and clear everything queued since the last call
+    pub fn draw(&mut self, view: ViewData, info: &mut RenderInfo) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let vertices = SharedBuffer::from_iter(
+            info.construction,
+            BufferUsage {
+                vertex_buffer: true,
+                ..BufferUsage::empty()
+            },
+            self.vertices.drain(..),
+        );
+
+        let mut builder = info.create_builder();
+
+        let uniform = vs::ty::UniformData {
+            world: view.world.into(),
+            proj: view.proj.into(),
+            view: view.view.into(),
+        };
+
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .bind_vertex_buffers(0, vertices.buffer())
+            .push_constants(self.pipeline.layout().clone(), 0, uniform)
+            .set_viewport(0, vec![info.viewport.clone()])
+            .draw(vertices.len(), 1, 0, 0)
+            .unwrap();
+
+        info.execute(builder);
+    }
+}
+
+/// Two unit vectors which together with `direction` form an orthonormal basis
+fn perpendicular_basis(direction: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let helper = if direction.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+
+    let side = direction.cross(helper).normalize();
+    let other = direction.cross(side);
+    (side, other)
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec4 line_pos;
+layout(location = 1) in vec4 line_color;
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform UniformData {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+} uniforms;
+
+void main() {
+    mat4 worldview = uniforms.view * uniforms.world;
+    gl_Position = uniforms.proj * worldview * vec4(line_pos.xyz, 1.0);
+    f_color = line_color;
+}
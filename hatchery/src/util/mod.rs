@@ -9,18 +9,26 @@ use vulkano::{
 
 pub mod buffer;
 pub mod camera;
+pub mod colormap;
 pub mod compute;
+pub mod debug_draw;
 // pub mod mesh;
+pub mod picking;
 pub mod point_cloud;
 pub mod quad;
+pub mod trails;
+pub mod volume;
 
+/// Compile a compute shader into `$module`, optionally with preprocessor defines to build
+/// several variants of the same file, e.g. `compute! { "a.glsl", a_f64, ("DOUBLE", "1") }`
 #[macro_export]
 macro_rules! compute {
-    ($path:expr, $module:ident) => {
+    ($path:expr, $module:ident $(, ($name:literal, $value:literal))*) => {
         mod $module {
             vulkano_shaders::shader! {
                 ty: "compute",
                 path: $path,
+                define: [$(($name, $value)),*],
                 types_meta: {
                     use bytemuck::{Pod, Zeroable};
 
@@ -0,0 +1,58 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer Points { vec4 data[]; } points;
+layout(set = 0, binding = 1) buffer PickResult {
+    uint depth;
+    uint index;
+} result;
+
+#define PASS_DEPTH 0
+#define PASS_INDEX 1
+
+layout(push_constant) uniform PickData {
+    mat4 transform;
+    vec2 cursor;
+    vec2 pick_radius;
+    float size;
+    uint buffer_size;
+    uint pass;
+} pd;
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+
+    if (gi >= pd.buffer_size) {
+        return;
+    }
+
+    vec4 point = points.data[gi];
+    // Hidden points can't be picked
+    if (point.w < 0.0) {
+        return;
+    }
+    vec4 clip = pd.transform * vec4(point.xyz, 1.0);
+    if (clip.w <= 0.0) {
+        return;
+    }
+
+    // Same footprint as in point_cloud_vert.glsl, the quad offset is applied in clip space so the
+    // radius in normalized device coordinates shrinks with depth
+    float point_size = point.w > 0.0 ? point.w : 1.0;
+    vec2 radius = max(vec2(pd.size * point_size / clip.w), pd.pick_radius);
+    vec2 offset = (clip.xy / clip.w - pd.cursor) / radius;
+    if (dot(offset, offset) > 1.0) {
+        return;
+    }
+
+    // Positive floats compare the same way as their bit patterns
+    uint depth = floatBitsToUint(clip.w);
+    if (pd.pass == PASS_DEPTH) {
+        atomicMin(result.depth, depth);
+    } else if (depth == result.depth) {
+        atomicMin(result.index, gi);
+    }
+}
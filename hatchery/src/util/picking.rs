@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage},
+    descriptor_set::WriteDescriptorSet,
+    device::Device,
+    pipeline::graphics::viewport::Viewport,
+    shader::ShaderModule,
+};
+
+use super::{
+    buffer::{AbstractBuffer, SharedBuffer},
+    camera::ViewData,
+    compute::{ComputeShader, ComputeShaderExecutor},
+    point_cloud::{PointStyle, RenderPoint},
+    ConstructionContext,
+};
+
+crate::compute! { "src/util/picking.glsl", picking }
+
+/// Points smaller than this many pixels are still picked if the cursor is within this radius
+const PICK_RADIUS: f32 = 4.0;
+
+const PASS_DEPTH: u32 = 0;
+const PASS_INDEX: u32 = 1;
+
+struct PickShader {
+    points: Arc<dyn BufferAccess>,
+    num_points: u32,
+    result: SharedBuffer<u32>,
+    constants: picking::ty::PickData,
+}
+
+impl ComputeShader for PickShader {
+    type Constants = picking::ty::PickData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        picking::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        [self.num_points.div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.points.clone()),
+            WriteDescriptorSet::buffer(1, self.result.buffer()),
+        ]
+    }
+}
+
+/// Finds the point under the cursor on the GPU. Points are tested against the same footprint
+/// `PointCloudPipeline` draws them with and the one closest to the camera wins.
+pub struct PointPicker {
+    executor: ComputeShaderExecutor<PickShader>,
+}
+
+impl PointPicker {
+    /// The points buffer needs `storage_buffer` usage
+    pub fn new<B: AbstractBuffer<RenderPoint>>(context: &ConstructionContext, points: &B) -> Self {
+        let shader = PickShader {
+            points: points.buffer(),
+            num_points: points.len(),
+            result: SharedBuffer::from_iter(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                [u32::MAX; 2],
+            ),
+            constants: picking::ty::PickData {
+                transform: [[0.0; 4]; 4],
+                cursor: [0.0; 2],
+                pick_radius: [0.0; 2],
+                size: 0.0,
+                buffer_size: points.len(),
+                pass: PASS_DEPTH,
+            },
+        };
+
+        Self {
+            executor: ComputeShaderExecutor::new(context, shader),
+        }
+    }
+
+    /// Index of the point under `cursor`, given in physical pixels relative to the window
+    pub fn pick(
+        &mut self,
+        context: &ConstructionContext,
+        cursor: [f32; 2],
+        view: ViewData,
+        viewport: &Viewport,
+        style: &PointStyle,
+    ) -> Option<u32> {
+        let cursor =
+            [0, 1].map(|i| 2.0 * (cursor[i] - viewport.origin[i]) / viewport.dimensions[i] - 1.0);
+        if cursor.iter().any(|c| c.abs() > 1.0) {
+            return None;
+        }
+
+        self.executor
+            .result
+            .typed_buffer()
+            .write()
+            .unwrap()
+            .copy_from_slice(&[u32::MAX; 2]);
+
+        let constants = &mut self.executor.constants;
+        constants.transform = (view.proj * view.view * view.world).into();
+        constants.cursor = cursor;
+        constants.pick_radius = [0, 1].map(|i| 2.0 * PICK_RADIUS / viewport.dimensions[i]);
+        constants.size = style.size * view.scale;
+
+        // The first pass finds the smallest depth, the second the point which has it
+        for pass in [PASS_DEPTH, PASS_INDEX] {
+            self.executor.constants.pass = pass;
+            self.executor.execute(context);
+        }
+
+        let index = self.executor.result.typed_buffer().read().unwrap()[1];
+        (index != u32::MAX).then_some(index)
+    }
+}
@@ -1,16 +1,28 @@
 use std::sync::Arc;
 
 use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
 use vulkano::{
+    buffer::BufferAccess,
+    command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, RenderPassBeginInfo, SubpassContents,
+    },
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    format::Format,
+    image::{view::ImageView, AttachmentImage, ImageUsage},
     impl_vertex,
     pipeline::{
         graphics::{
-            color_blend::ColorBlendState, input_assembly::InputAssemblyState,
-            viewport::ViewportState,
+            color_blend::{AttachmentBlend, BlendFactor, BlendOp, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            vertex_input::BuffersDefinition,
+            viewport::{Viewport, ViewportState},
         },
-        GraphicsPipeline, Pipeline,
+        GraphicsPipeline, Pipeline, PipelineBindPoint,
     },
-    render_pass::Subpass,
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+    sync::{self, GpuFuture},
 };
 
 use crate::RenderInfo;
@@ -18,6 +30,7 @@ use crate::RenderInfo;
 use super::{
     buffer::{AbstractBuffer, SharedBuffer},
     camera::ViewData,
+    colormap::ColorScale,
     quad::TexturedQuad,
     ConstructionContext,
 };
@@ -40,10 +53,41 @@ mod vs {
 mod fs {
     vulkano_shaders::shader! {
         ty: "fragment",
-        path: "src/util/point_cloud_frag.glsl"
+        path: "src/util/point_cloud_frag.glsl",
+        include: ["src/util"],
+    }
+}
+
+mod density_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/util/point_cloud_density_frag.glsl",
+        include: ["src/util"],
+    }
+}
+
+mod tonemap_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/util/tonemap_vert.glsl"
+    }
+}
+
+mod tonemap_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/util/tonemap_frag.glsl",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        },
     }
 }
 
+/// Float format of the density accumulation target, 16 bit floats are guaranteed to be blendable
+const DENSITY_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
 #[repr(C)]
 #[derive(Default, Pod, Zeroable, Clone, Copy)]
 pub struct RenderPoint {
@@ -52,8 +96,149 @@ pub struct RenderPoint {
 
 impl_vertex!(RenderPoint, point_pos);
 
+/// Per-point attribute, either a scalar in `x` or an RGBA color depending on `PointColors`
+#[repr(C)]
+#[derive(Default, Pod, Zeroable, Clone, Copy)]
+pub struct PointAttribute {
+    pub point_attr: [f32; 4],
+}
+
+impl_vertex!(PointAttribute, point_attr);
+
+/// How the points should be colored
+pub enum PointColors<'a> {
+    /// Every point is white
+    Uniform,
+    /// Map the `x` component of each `PointAttribute` through a colormap
+    Scalar {
+        values: Arc<dyn BufferAccess>,
+        scale: &'a ColorScale,
+    },
+    /// Use each `PointAttribute` directly as a color, alpha scales the brightness
+    Rgba(Arc<dyn BufferAccess>),
+}
+
+impl PointColors<'_> {
+    fn mode(&self) -> u32 {
+        match self {
+            PointColors::Uniform => 0,
+            PointColors::Scalar { .. } => 1,
+            PointColors::Rgba(_) => 2,
+        }
+    }
+}
+
+/// Stretch applied to the accumulated density before display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub enum Stretch {
+    Log,
+    Asinh,
+}
+
+/// Maps accumulated density onto the displayable range, densities below `black` are black and
+/// densities above `white` are saturated
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub struct ToneMapping {
+    pub stretch: Stretch,
+    // Both points span several decades
+    #[cfg_attr(feature = "egui", inspect(label = "Black point", range = 1e-6..=1e6, log))]
+    pub black: f32,
+    #[cfg_attr(feature = "egui", inspect(label = "White point", range = 1e-6..=1e6, log))]
+    pub white: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            stretch: Stretch::Log,
+            black: 0.01,
+            white: 100.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub enum RenderMode {
+    /// Alpha blend the points directly into the viewport, depends on draw order
+    Alpha,
+    /// Accumulate the points additively into a float target and tone map the result
+    Density(ToneMapping),
+}
+
+/// Radial profile of a single point, see `point_kernels.glsl`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub enum Kernel {
+    Disk,
+    Gaussian,
+    #[cfg_attr(feature = "egui", inspect(label = "Cubic spline"))]
+    CubicSpline,
+    #[cfg_attr(feature = "egui", inspect(label = "Wendland C2"))]
+    WendlandC2,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 4] = [
+        Kernel::Disk,
+        Kernel::Gaussian,
+        Kernel::CubicSpline,
+        Kernel::WendlandC2,
+    ];
+
+    /// Value of the `kernel` specialization constant, also the index of the pipeline variant
+    fn index(&self) -> usize {
+        match self {
+            Kernel::Disk => 0,
+            Kernel::Gaussian => 1,
+            Kernel::CubicSpline => 2,
+            Kernel::WendlandC2 => 3,
+        }
+    }
+}
+
+/// Appearance of the points, shared between all render modes. The size of an individual point is
+/// additionally scaled by the `w` component of its `RenderPoint` if that is positive.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub struct PointStyle {
+    #[cfg_attr(feature = "egui", inspect(speed = 0.02, range = 0.01..=50.0))]
+    pub brightness: f32,
+    #[cfg_attr(feature = "egui", inspect(label = "Scale", speed = 0.02, range = 0.0..=2.0))]
+    pub size: f32,
+    pub kernel: Kernel,
+    #[cfg_attr(feature = "egui", inspect(label = "Render mode"))]
+    pub mode: RenderMode,
+}
+
+impl Default for PointStyle {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            size: 0.2,
+            kernel: Kernel::Disk,
+            mode: RenderMode::Alpha,
+        }
+    }
+}
+
+/// Offscreen float image which the density mode accumulates into
+struct DensityTarget {
+    dimensions: [u32; 2],
+    framebuffer: Arc<Framebuffer>,
+    descriptor_set: Arc<PersistentDescriptorSet>,
+}
+
 pub struct PointCloudPipeline {
-    pipeline: Arc<GraphicsPipeline>,
+    /// One variant per `Kernel`
+    pipelines: Vec<Arc<GraphicsPipeline>>,
+    density_pipelines: Vec<Arc<GraphicsPipeline>>,
+    tonemap_pipeline: Arc<GraphicsPipeline>,
+    density_render_pass: Arc<RenderPass>,
+    density_target: Option<DensityTarget>,
+    sampler: Arc<Sampler>,
     quad: TexturedQuad,
     subpass: Subpass,
 }
@@ -62,22 +247,111 @@ impl PointCloudPipeline {
     pub fn new(context: &ConstructionContext, subpass: Subpass) -> Self {
         let vs = vs::load(context.device()).unwrap();
         let fs = fs::load(context.device()).unwrap();
+        let density_fs = density_fs::load(context.device()).unwrap();
+        let tonemap_vs = tonemap_vs::load(context.device()).unwrap();
+        let tonemap_fs = tonemap_fs::load(context.device()).unwrap();
+
+        let points_definition = TexturedQuad::buffers_definition()
+            .instance::<RenderPoint>()
+            .instance::<PointAttribute>();
+
+        let pipelines = Kernel::ALL
+            .iter()
+            .map(|kernel| {
+                GraphicsPipeline::start()
+                    .vertex_input_state(points_definition.clone())
+                    .vertex_shader(vs.entry_point("main").unwrap(), ())
+                    .input_assembly_state(InputAssemblyState::new())
+                    .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                    .fragment_shader(
+                        fs.entry_point("main").unwrap(),
+                        fs::SpecializationConstants {
+                            kernel: kernel.index() as u32,
+                        },
+                    )
+                    .render_pass(subpass.clone())
+                    .color_blend_state(ColorBlendState::new(1).blend_alpha())
+                    .build(context.device())
+                    .expect("failed to make pipeline")
+            })
+            .collect();
 
-        let pipeline = GraphicsPipeline::start()
-            .vertex_input_state(TexturedQuad::buffers_definition().instance::<RenderPoint>())
-            .vertex_shader(vs.entry_point("main").unwrap(), ())
+        let density_render_pass = vulkano::single_pass_renderpass!(
+            context.device(),
+            attachments: {
+                density: {
+                    load: Clear,
+                    store: Store,
+                    format: DENSITY_FORMAT,
+                    samples: 1,
+                }
+            },
+            pass: { color: [density], depth_stencil: {} }
+        )
+        .expect("error creating render pass");
+
+        // Plain sum of both the weighted colors and the weights
+        let accumulate = AttachmentBlend {
+            color_op: BlendOp::Add,
+            color_source: BlendFactor::One,
+            color_destination: BlendFactor::One,
+            alpha_op: BlendOp::Add,
+            alpha_source: BlendFactor::One,
+            alpha_destination: BlendFactor::One,
+        };
+
+        let density_pipelines = Kernel::ALL
+            .iter()
+            .map(|kernel| {
+                GraphicsPipeline::start()
+                    .vertex_input_state(points_definition.clone())
+                    .vertex_shader(vs.entry_point("main").unwrap(), ())
+                    .input_assembly_state(InputAssemblyState::new())
+                    .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                    .fragment_shader(
+                        density_fs.entry_point("main").unwrap(),
+                        density_fs::SpecializationConstants {
+                            kernel: kernel.index() as u32,
+                        },
+                    )
+                    .render_pass(Subpass::from(density_render_pass.clone(), 0).unwrap())
+                    .color_blend_state(ColorBlendState::new(1).blend(accumulate))
+                    .build(context.device())
+                    .expect("failed to make pipeline")
+            })
+            .collect();
+
+        let tonemap_pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new())
+            .vertex_shader(tonemap_vs.entry_point("main").unwrap(), ())
             .input_assembly_state(InputAssemblyState::new())
             .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
-            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .fragment_shader(tonemap_fs.entry_point("main").unwrap(), ())
             .render_pass(subpass.clone())
-            .color_blend_state(ColorBlendState::new(1).blend_alpha())
+            .color_blend_state(ColorBlendState::new(1).blend_additive())
             .build(context.device())
             .expect("failed to make pipeline");
 
+        let sampler = Sampler::new(
+            context.device(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
         let quad = TexturedQuad::new(context, [-1.0, -1.0], [1.0, 1.0]);
 
         Self {
-            pipeline,
+            pipelines,
+            density_pipelines,
+            tonemap_pipeline,
+            density_render_pass,
+            density_target: None,
+            sampler,
             quad,
             subpass,
         }
@@ -86,30 +360,215 @@ impl PointCloudPipeline {
     pub fn draw(
         &mut self,
         points: &impl AbstractBuffer<RenderPoint>,
+        colors: PointColors,
         view: ViewData,
-        brightness: f32,
-        size: f32,
+        style: &PointStyle,
         info: &mut RenderInfo,
     ) {
-        let mut builder = info.create_builder();
+        let (colormap, normalization, [range_min, range_max]) = match &colors {
+            PointColors::Scalar { scale, .. } => (
+                scale.colormap.index(),
+                scale.normalization.index(),
+                scale.range,
+            ),
+            _ => (0, 0, [0.0, 1.0]),
+        };
 
         let uniform = vs::ty::UniformData {
             world: view.world.into(),
             proj: view.proj.into(),
             view: view.view.into(),
-            brightness,
-            size: size * view.scale,
+            brightness: style.brightness,
+            size: style.size * view.scale,
+            color_mode: colors.mode(),
+            colormap,
+            normalization,
+            range_min,
+            range_max,
+        };
+
+        // The attribute binding always needs a buffer, the points themselves have the same
+        // layout and are ignored by the shader in uniform mode
+        let attributes = match colors {
+            PointColors::Uniform => points.buffer(),
+            PointColors::Scalar { values, .. } => values,
+            PointColors::Rgba(values) => values,
         };
 
+        match style.mode {
+            RenderMode::Alpha => {
+                let mut builder = info.create_builder();
+                self.record_points(
+                    &mut builder,
+                    self.pipelines[style.kernel.index()].clone(),
+                    points,
+                    attributes,
+                    uniform,
+                    info.viewport.clone(),
+                );
+                info.execute(builder);
+            }
+            RenderMode::Density(tone) => {
+                let pipeline = self.density_pipelines[style.kernel.index()].clone();
+                self.accumulate_density(pipeline, points, attributes, uniform, info);
+                self.tone_map(&tone, info);
+            }
+        }
+    }
+
+    fn record_points<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        points: &impl AbstractBuffer<RenderPoint>,
+        attributes: Arc<dyn BufferAccess>,
+        uniform: vs::ty::UniformData,
+        viewport: Viewport,
+    ) {
         builder
-            .bind_pipeline_graphics(self.pipeline.clone())
-            .bind_vertex_buffers(0, (self.quad.vertex.buffer(), points.buffer()))
+            .bind_pipeline_graphics(pipeline.clone())
+            .bind_vertex_buffers(0, (self.quad.vertex.buffer(), points.buffer(), attributes))
             .bind_index_buffer(self.quad.index.typed_buffer())
-            .push_constants(self.pipeline.layout().clone(), 0, uniform)
-            .set_viewport(0, vec![info.viewport.clone()])
+            .push_constants(pipeline.layout().clone(), 0, uniform)
+            .set_viewport(0, vec![viewport])
             .draw_indexed(self.quad.index.len(), points.len(), 0, 0, 0)
             .unwrap();
+    }
+
+    /// (Re)create the density target if the viewport size changed
+    fn density_target(&mut self, info: &RenderInfo) -> &DensityTarget {
+        let dimensions = info.viewport.dimensions.map(|d| (d.round() as u32).max(1));
+
+        if self
+            .density_target
+            .as_ref()
+            .map_or(true, |target| target.dimensions != dimensions)
+        {
+            let image = AttachmentImage::with_usage(
+                info.construction.memory_allocator(),
+                dimensions,
+                DENSITY_FORMAT,
+                ImageUsage {
+                    sampled: true,
+                    ..ImageUsage::empty()
+                },
+            )
+            .unwrap();
+            let view = ImageView::new_default(image).unwrap();
+
+            let framebuffer = Framebuffer::new(
+                self.density_render_pass.clone(),
+                FramebufferCreateInfo {
+                    attachments: vec![view.clone()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            let layout = self.tonemap_pipeline.layout().set_layouts().get(0).unwrap();
+            let descriptor_set = PersistentDescriptorSet::new(
+                info.construction.descriptor_allocator(),
+                layout.clone(),
+                [WriteDescriptorSet::image_view_sampler(
+                    0,
+                    view,
+                    self.sampler.clone(),
+                )],
+            )
+            .unwrap();
+
+            self.density_target = Some(DensityTarget {
+                dimensions,
+                framebuffer,
+                descriptor_set,
+            });
+        }
+
+        self.density_target.as_ref().unwrap()
+    }
+
+    /// Splat all points additively into the density target, this is submitted separately since
+    /// it needs its own render pass
+    fn accumulate_density(
+        &mut self,
+        pipeline: Arc<GraphicsPipeline>,
+        points: &impl AbstractBuffer<RenderPoint>,
+        attributes: Arc<dyn BufferAccess>,
+        uniform: vs::ty::UniformData,
+        info: &RenderInfo,
+    ) {
+        let framebuffer = self.density_target(info).framebuffer.clone();
+        let [width, height] = framebuffer.extent();
+
+        let context = info.construction;
+        let mut builder = AutoCommandBufferBuilder::primary(
+            context.command_allocator(),
+            info.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some([0.0, 0.0, 0.0, 0.0].into())],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassContents::Inline,
+            )
+            .unwrap();
+
+        self.record_points(
+            &mut builder,
+            pipeline,
+            points,
+            attributes,
+            uniform,
+            Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [width as f32, height as f32],
+                depth_range: 0.0..1.0,
+            },
+        );
+
+        builder.end_render_pass().unwrap();
+
+        let command_buffer = builder.build().unwrap();
+        sync::now(context.device())
+            .then_execute(info.queue.clone(), command_buffer)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+    }
 
+    /// Draw the density target into the viewport through the tone mapping curve
+    fn tone_map(&mut self, tone: &ToneMapping, info: &mut RenderInfo) {
+        let descriptor_set = self.density_target(info).descriptor_set.clone();
+
+        let constants = tonemap_fs::ty::ToneData {
+            stretch: match tone.stretch {
+                Stretch::Log => 0,
+                Stretch::Asinh => 1,
+            },
+            black: tone.black,
+            white: tone.white,
+        };
+
+        let mut builder = info.create_builder();
+        builder
+            .bind_pipeline_graphics(self.tonemap_pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.tonemap_pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .push_constants(self.tonemap_pipeline.layout().clone(), 0, constants)
+            .set_viewport(0, vec![info.viewport.clone()])
+            .draw(3, 1, 0, 0)
+            .unwrap();
         info.execute(builder);
     }
 }
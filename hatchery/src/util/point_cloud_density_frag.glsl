@@ -0,0 +1,15 @@
+#version 450
+
+#include "point_kernels.glsl"
+
+layout(location = 0) in vec2 f_uv;
+layout(location = 1) in float f_brightness;
+layout(location = 2) in vec3 f_color;
+
+layout(location = 0) out vec4 out_density;
+
+void main() {
+    // Accumulate the weighted color in rgb and the total weight in alpha
+    float weight = f_brightness * particle_kernel(f_uv);
+    out_density = vec4(f_color * weight, weight);
+}
@@ -3,9 +3,18 @@
 layout(location = 0) in vec2 quad_pos;
 layout(location = 1) in vec2 quad_uv;
 layout(location = 2) in vec4 point_pos;
+layout(location = 3) in vec4 point_attr;
 
 layout(location = 0) out vec2 f_uv;
 layout(location = 1) out float f_brightness;
+layout(location = 2) out vec3 f_color;
+
+#define COLOR_UNIFORM 0
+#define COLOR_SCALAR 1
+#define COLOR_RGBA 2
+
+#define NORMALIZATION_LINEAR 0
+#define NORMALIZATION_LOG 1
 
 layout(push_constant) uniform UniformData {
     mat4 world;
@@ -14,11 +23,105 @@ layout(push_constant) uniform UniformData {
 
     float brightness;
     float size;
+
+    uint color_mode;
+    uint colormap;
+    uint normalization;
+    float range_min;
+    float range_max;
 } uniforms;
 
+// Polynomial fits of the matplotlib colormaps, see `colormap.rs`
+vec3 polynomial_colormap(float t, vec3 c0, vec3 c1, vec3 c2, vec3 c3, vec3 c4, vec3 c5, vec3 c6) {
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+vec3 viridis(float t) {
+    return polynomial_colormap(t,
+        vec3(0.2777273272234177, 0.005407344544966578, 0.3340998053353061),
+        vec3(0.1050930431085774, 1.404613529898575, 1.384590162594685),
+        vec3(-0.3308618287255563, 0.214847559468213, 0.09509516302823659),
+        vec3(-4.634230498983486, -5.799100973351585, -19.33244095627987),
+        vec3(6.228269936347081, 14.17993336680509, 56.69055260068105),
+        vec3(4.776384997670288, -13.74514537774601, -65.35303263337234),
+        vec3(-5.435455855934631, 4.645852612178535, 26.3124352495832));
+}
+
+vec3 magma(float t) {
+    return polynomial_colormap(t,
+        vec3(-0.002136485053939582, -0.000749655052795221, -0.005386127855323933),
+        vec3(0.2516605407371642, 0.6775232436837668, 2.494026599312351),
+        vec3(8.353717279216625, -3.577719514958484, 0.3144679030132573),
+        vec3(-27.66873308576866, 14.26473078096533, -13.64921318813922),
+        vec3(52.17613981234068, -27.94360607168351, 12.94416944238394),
+        vec3(-50.76852536473588, 29.04658282127291, 4.23415299384598),
+        vec3(18.65570506591883, -11.48977351997711, -5.601961508734096));
+}
+
+vec3 inferno(float t) {
+    return polynomial_colormap(t,
+        vec3(0.0002189403691192265, 0.001651004631001012, -0.01948089843709184),
+        vec3(0.1065134194856116, 0.5639564367884091, 3.932712388889277),
+        vec3(11.60249308247187, -3.972853965665698, -15.9423941062914),
+        vec3(-41.70399613139459, 17.43639888205313, 44.35414519872813),
+        vec3(77.162935699427, -33.40235894210092, -81.80730925738993),
+        vec3(-71.31942824499214, 32.62606426397723, 73.20951985803202),
+        vec3(25.13112622477341, -12.24266895238567, -23.07032500287172));
+}
+
+vec3 diverging(float t) {
+    vec3 low = vec3(0.230, 0.299, 0.754);
+    vec3 mid = vec3(0.865, 0.865, 0.865);
+    vec3 high = vec3(0.706, 0.016, 0.150);
+    return t < 0.5 ? mix(low, mid, 2.0 * t) : mix(mid, high, 2.0 * t - 1.0);
+}
+
+float normalize_scalar(float value) {
+    float t;
+    if (uniforms.normalization == NORMALIZATION_LOG) {
+        float low = max(uniforms.range_min, 1e-30);
+        float high = max(uniforms.range_max, low);
+        t = log(max(value, low) / low) / log(high / low);
+    } else {
+        t = (value - uniforms.range_min) / (uniforms.range_max - uniforms.range_min);
+    }
+
+    return isnan(t) || isinf(t) ? 0.0 : clamp(t, 0.0, 1.0);
+}
+
+vec3 scalar_color(float value) {
+    float t = normalize_scalar(value);
+    vec3 color;
+    switch (uniforms.colormap) {
+        case 0: color = viridis(t); break;
+        case 1: color = magma(t); break;
+        case 2: color = inferno(t); break;
+        default: color = diverging(t); break;
+    }
+
+    // Colormaps are defined in sRGB, but the framebuffer expects linear colors
+    return pow(clamp(color, 0.0, 1.0), vec3(2.2));
+}
+
 void main() {
+    // A negative w component hides the point, a positive one scales its size
+    if (point_pos.w < 0.0) {
+        gl_Position = vec4(0.0, 0.0, -1.0, 1.0);
+        return;
+    }
+    float point_size = point_pos.w > 0.0 ? point_pos.w : 1.0;
+
     mat4 worldview = uniforms.view * uniforms.world;
-    gl_Position = uniforms.size * vec4(quad_pos, 0.0, 0.0) + uniforms.proj * worldview * vec4(point_pos.xyz, 1.0);
+    gl_Position = uniforms.size * point_size * vec4(quad_pos, 0.0, 0.0) + uniforms.proj * worldview * vec4(point_pos.xyz, 1.0);
     f_uv = quad_uv;
     f_brightness = uniforms.brightness;
+
+    if (uniforms.color_mode == COLOR_SCALAR) {
+        f_color = scalar_color(point_attr.x);
+    } else if (uniforms.color_mode == COLOR_RGBA) {
+        f_color = point_attr.rgb;
+        f_brightness *= point_attr.a;
+    } else {
+        f_color = vec3(1.0);
+    }
 }
@@ -0,0 +1,37 @@
+// Particle kernels shared by the point cloud fragment shaders, the kernel is picked per pipeline
+// through a specialization constant. All kernels are normalized to a peak of one and have compact
+// support on the disk inscribed in the quad.
+
+layout(constant_id = 0) const uint kernel = 0;
+
+#define KERNEL_DISK 0
+#define KERNEL_GAUSSIAN 1
+#define KERNEL_CUBIC_SPLINE 2
+#define KERNEL_WENDLAND_C2 3
+
+float particle_kernel(vec2 uv) {
+    float q = 2.0 * length(uv - vec2(0.5));
+    if (q > 1.0) {
+        return 0.0;
+    }
+
+    switch (kernel) {
+        case KERNEL_GAUSSIAN:
+            // Truncated at three standard deviations
+            return exp(-4.5 * q * q);
+        case KERNEL_CUBIC_SPLINE: {
+            // M4 spline with smoothing length of half the radius
+            float s = 2.0 * q;
+            if (s <= 1.0) {
+                return 1.0 - 1.5 * s * s * (1.0 - 0.5 * s);
+            }
+            return 0.25 * (2.0 - s) * (2.0 - s) * (2.0 - s);
+        }
+        case KERNEL_WENDLAND_C2: {
+            float t = 1.0 - q;
+            return t * t * t * t * (1.0 + 4.0 * q);
+        }
+        default:
+            return 1.0;
+    }
+}
@@ -0,0 +1,40 @@
+#version 450
+
+layout(location = 0) in vec2 f_uv;
+
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler2D density;
+
+#define STRETCH_LOG 0
+#define STRETCH_ASINH 1
+
+layout(push_constant) uniform ToneData {
+    uint stretch;
+    float black;
+    float white;
+} tone;
+
+float stretch(float value) {
+    float t;
+    if (tone.stretch == STRETCH_LOG) {
+        float black = max(tone.black, 1e-12);
+        float white = max(tone.white, 1.0001 * black);
+        t = log(max(value, black) / black) / log(white / black);
+    } else {
+        t = (asinh(value) - asinh(tone.black)) / (asinh(tone.white) - asinh(tone.black));
+    }
+
+    return isnan(t) ? 0.0 : clamp(t, 0.0, 1.0);
+}
+
+void main() {
+    vec4 density = texture(density, f_uv);
+    if (density.a <= 0.0) {
+        discard;
+    }
+
+    // Weighted average color, scaled by the stretched surface brightness
+    vec3 hue = density.rgb / density.a;
+    out_color = vec4(hue * stretch(density.a), 1.0);
+}
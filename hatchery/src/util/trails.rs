@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage},
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Device,
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendState,
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            vertex_input::BuffersDefinition,
+            viewport::ViewportState,
+        },
+        GraphicsPipeline, Pipeline, PipelineBindPoint,
+    },
+    render_pass::Subpass,
+    shader::ShaderModule,
+};
+
+use crate::RenderInfo;
+
+use super::{
+    buffer::{AbstractBuffer, DeviceBuffer, SharedBuffer},
+    camera::ViewData,
+    compute::{ComputeShader, ComputeShaderExecutor},
+    point_cloud::RenderPoint,
+    ConstructionContext,
+};
+
+crate::compute! { "src/util/trails_record.glsl", record }
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/util/trails_vert.glsl",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        },
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/util/debug_draw_frag.glsl"
+    }
+}
+
+/// Appearance of the trails, `length` is clamped to the capacity of the `Trails`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub struct TrailStyle {
+    #[cfg_attr(feature = "egui", inspect(range = 2.0..=16384.0))]
+    pub length: u32,
+    #[cfg_attr(feature = "egui", inspect(color))]
+    pub color: [f32; 4],
+}
+
+impl Default for TrailStyle {
+    fn default() -> Self {
+        Self {
+            length: 256,
+            color: [1.0, 0.6, 0.2, 1.0],
+        }
+    }
+}
+
+struct TrailRecorder {
+    points: Arc<dyn BufferAccess>,
+    tracked: DeviceBuffer<u32>,
+    ring: DeviceBuffer<[f32; 4]>,
+    capacity: u32,
+    head: u32,
+}
+
+impl ComputeShader for TrailRecorder {
+    type Constants = record::ty::TrailData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(Self::Constants {
+            num_tracked: self.tracked.len(),
+            capacity: self.capacity,
+            head: self.head,
+        })
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        record::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        [self.tracked.len().div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.points.clone()),
+            WriteDescriptorSet::buffer(1, self.tracked.buffer()),
+            WriteDescriptorSet::buffer(2, self.ring.buffer()),
+        ]
+    }
+}
+
+/// Recent positions of a subset of points, kept in a ring buffer on the GPU and drawn as fading
+/// polylines. `record` has to be called after every simulation step.
+pub struct Trails {
+    recorder: ComputeShaderExecutor<TrailRecorder>,
+    pipeline: Arc<GraphicsPipeline>,
+    descriptor_set: Arc<PersistentDescriptorSet>,
+    num_trails: u32,
+    /// Number of valid samples in the ring buffer
+    samples: u32,
+}
+
+impl Trails {
+    /// Track the points at `indices` of the `points` buffer, keeping at most `capacity` samples
+    pub fn new<B: AbstractBuffer<RenderPoint>>(
+        context: &ConstructionContext,
+        subpass: Subpass,
+        points: &B,
+        indices: &[u32],
+        capacity: u32,
+    ) -> Self {
+        let vs = vs::load(context.device()).unwrap();
+        let fs = fs::load(context.device()).unwrap();
+
+        let pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new().topology(PrimitiveTopology::LineStrip))
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .render_pass(subpass)
+            .color_blend_state(ColorBlendState::new(1).blend_alpha())
+            .build(context.device())
+            .expect("failed to make pipeline");
+
+        let recorder = Self::create_recorder(context, points.buffer(), indices, capacity);
+        let descriptor_set = Self::create_descriptor_set(context, &pipeline, &recorder);
+
+        Self {
+            recorder,
+            pipeline,
+            descriptor_set,
+            num_trails: indices.len() as u32,
+            samples: 0,
+        }
+    }
+
+    fn create_recorder(
+        context: &ConstructionContext,
+        points: Arc<dyn BufferAccess>,
+        indices: &[u32],
+        capacity: u32,
+    ) -> ComputeShaderExecutor<TrailRecorder> {
+        let capacity = capacity.max(2);
+        let usage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        };
+
+        // Buffers can't be empty, an unused index is cheaper than special casing everything
+        let tracked = if indices.is_empty() {
+            &[0][..]
+        } else {
+            indices
+        };
+        let recorder = TrailRecorder {
+            points,
+            tracked: DeviceBuffer::from_iter(context, usage, tracked.iter().copied()),
+            ring: DeviceBuffer::new(context, usage, (tracked.len() as u32 * capacity) as u64),
+            capacity,
+            head: 0,
+        };
+
+        ComputeShaderExecutor::new(context, recorder)
+    }
+
+    fn create_descriptor_set(
+        context: &ConstructionContext,
+        pipeline: &Arc<GraphicsPipeline>,
+        recorder: &TrailRecorder,
+    ) -> Arc<PersistentDescriptorSet> {
+        PersistentDescriptorSet::new(
+            context.descriptor_allocator(),
+            pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            [WriteDescriptorSet::buffer(0, recorder.ring.buffer())],
+        )
+        .unwrap()
+    }
+
+    /// Start tracking a different set of points, this discards the current trails
+    pub fn track(&mut self, context: &ConstructionContext, indices: &[u32]) {
+        self.recorder = Self::create_recorder(
+            context,
+            self.recorder.points.clone(),
+            indices,
+            self.recorder.capacity,
+        );
+        self.descriptor_set = Self::create_descriptor_set(context, &self.pipeline, &self.recorder);
+        self.num_trails = indices.len() as u32;
+        self.samples = 0;
+    }
+
+    /// Follow the tracked points to new indices, keeping the current trails. `indices` has an
+    /// entry for every trail, in the order they were tracked.
+    pub fn retarget(&mut self, context: &ConstructionContext, indices: &[u32]) {
+        debug_assert_eq!(indices.len() as u32, self.num_trails);
+        if indices.is_empty() {
+            return;
+        }
+
+        let staging = SharedBuffer::from_iter(
+            context,
+            BufferUsage {
+                transfer_src: true,
+                ..BufferUsage::empty()
+            },
+            indices.iter().copied(),
+        );
+        self.recorder.tracked.copy(context, &staging);
+    }
+
+    /// Maximum number of samples kept per trail
+    pub fn capacity(&self) -> u32 {
+        self.recorder.capacity
+    }
+
+    /// Append the current positions of the tracked points
+    pub fn record(&mut self, context: &ConstructionContext) {
+        if self.samples > 0 {
+            self.recorder.head = (self.recorder.head + 1) % self.recorder.capacity;
+        }
+
+        self.recorder.execute(context);
+        self.samples = (self.samples + 1).min(self.recorder.capacity);
+    }
+
+    /// Forget all recorded samples, e.g. after the simulation has been reset
+    pub fn clear(&mut self) {
+        self.samples = 0;
+    }
+
+    pub fn draw(&self, view: ViewData, style: &TrailStyle, info: &mut RenderInfo) {
+        let count = style.length.min(self.samples);
+        if count < 2 || self.num_trails == 0 {
+            return;
+        }
+
+        let uniform = vs::ty::TrailUniforms {
+            world: view.world.into(),
+            view: view.view.into(),
+            proj: view.proj.into(),
+            color: style.color,
+            capacity: self.recorder.capacity,
+            head: self.recorder.head,
+            count,
+        };
+
+        let mut builder = info.create_builder();
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                self.descriptor_set.clone(),
+            )
+            .push_constants(self.pipeline.layout().clone(), 0, uniform)
+            .set_viewport(0, vec![info.viewport.clone()])
+            .draw(count, self.num_trails, 0, 0)
+            .unwrap();
+        info.execute(builder);
+    }
+}
@@ -0,0 +1,26 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer Points { vec4 data[]; } points;
+layout(set = 0, binding = 1) buffer Tracked { uint data[]; } tracked;
+layout(set = 0, binding = 2) buffer Ring { vec4 data[]; } ring;
+
+layout(push_constant) uniform TrailData {
+    uint num_tracked;
+    uint capacity;
+    uint head;
+} td;
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+
+    if (gi >= td.num_tracked) {
+        return;
+    }
+
+    // Each tracked point owns a contiguous block of `capacity` samples
+    ring.data[gi * td.capacity + td.head] = vec4(points.data[tracked.data[gi]].xyz, 1.0);
+}
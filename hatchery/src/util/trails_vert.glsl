@@ -0,0 +1,28 @@
+#version 450
+
+layout(set = 0, binding = 0) buffer Ring { vec4 data[]; } ring;
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform TrailUniforms {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    vec4 color;
+    uint capacity;
+    uint head;
+    uint count;
+} uniforms;
+
+void main() {
+    // Vertices go from the oldest sample to the newest one, which is stored at `head`
+    uint age = uniforms.count - 1 - uint(gl_VertexIndex);
+    uint slot = (uniforms.head + uniforms.capacity - age) % uniforms.capacity;
+    vec4 position = ring.data[uint(gl_InstanceIndex) * uniforms.capacity + slot];
+
+    mat4 worldview = uniforms.view * uniforms.world;
+    gl_Position = uniforms.proj * worldview * vec4(position.xyz, 1.0);
+
+    float fade = 1.0 - float(age) / float(uniforms.count);
+    f_color = vec4(uniforms.color.rgb, uniforms.color.a * fade);
+}
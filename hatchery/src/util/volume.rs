@@ -0,0 +1,474 @@
+use std::sync::Arc;
+
+use cgmath::{Matrix4, Point3, SquareMatrix};
+use serde::{Deserialize, Serialize};
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage},
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Device,
+    format::Format,
+    image::{view::ImageView, ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage},
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, BlendFactor, BlendOp, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            vertex_input::BuffersDefinition,
+            viewport::ViewportState,
+        },
+        GraphicsPipeline, Pipeline, PipelineBindPoint,
+    },
+    render_pass::Subpass,
+    sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+    shader::ShaderModule,
+};
+
+use crate::RenderInfo;
+
+use super::{
+    buffer::{AbstractBuffer, DeviceBuffer, SharedBuffer},
+    camera::ViewData,
+    compute::{ComputeShader, ComputeShaderExecutor},
+    ConstructionContext,
+};
+
+crate::compute! { "src/util/volume_deposit.glsl", deposit }
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/util/tonemap_vert.glsl"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/util/volume_frag.glsl",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        },
+    }
+}
+
+/// Number of entries in the baked transfer function, has to match `volume_frag.glsl`
+const TABLE_SIZE: usize = 256;
+
+/// Number of empty cells kept around the particles when the bounds are automatic, has to match
+/// `volume_deposit.glsl`
+const MARGIN: f32 = 2.0;
+
+const PASS_CLEAR: u32 = 0;
+const PASS_BOUNDS: u32 = 1;
+const PASS_DEPOSIT: u32 = 2;
+const PASS_RESOLVE: u32 = 3;
+
+/// Mass assignment scheme used to deposit particles onto the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub enum Assignment {
+    /// Cloud in cell, each particle is spread over the 8 nearest cells
+    #[cfg_attr(feature = "egui", inspect(label = "CIC"))]
+    Cic,
+    /// Triangular shaped cloud, each particle is spread over the 27 nearest cells
+    #[cfg_attr(feature = "egui", inspect(label = "TSC"))]
+    Tsc,
+}
+
+/// Control point of a `TransferFunction`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ColorStop {
+    /// Position in [0, 1], zero is the faintest density shown and one the peak density
+    pub position: f32,
+    /// sRGB color
+    pub color: [f32; 3],
+    pub alpha: f32,
+}
+
+/// Maps log density onto color and opacity, the stops don't need to be sorted. The inspector
+/// only shows the scales, the stops are edited with the `TransferFunctionEditor`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub struct TransferFunction {
+    #[cfg_attr(feature = "egui", inspect(skip))]
+    pub stops: Vec<ColorStop>,
+    /// Orders of magnitude below the peak density which are still visible
+    #[cfg_attr(feature = "egui", inspect(speed = 0.05, range = 0.5..=10.0))]
+    pub decades: f32,
+    /// Opacity scale, in units of the inverse box diagonal
+    #[cfg_attr(feature = "egui", inspect(speed = 0.1, range = 0.0..=1000.0))]
+    pub opacity: f32,
+}
+
+impl Default for TransferFunction {
+    fn default() -> Self {
+        Self {
+            stops: vec![
+                ColorStop {
+                    position: 0.0,
+                    color: [0.1, 0.0, 0.3],
+                    alpha: 0.0,
+                },
+                ColorStop {
+                    position: 0.5,
+                    color: [0.8, 0.2, 0.3],
+                    alpha: 0.2,
+                },
+                ColorStop {
+                    position: 1.0,
+                    color: [1.0, 0.9, 0.6],
+                    alpha: 1.0,
+                },
+            ],
+            decades: 4.0,
+            opacity: 20.0,
+        }
+    }
+}
+
+impl TransferFunction {
+    /// Interpolated color and opacity at `t` in [0, 1]
+    pub fn sample(&self, t: f32) -> [f32; 4] {
+        let mut stops = self.stops.clone();
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+
+        let rgba = |stop: &ColorStop| [stop.color[0], stop.color[1], stop.color[2], stop.alpha];
+        let (first, last) = match (stops.first(), stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return [0.0; 4],
+        };
+
+        if t <= first.position {
+            return rgba(first);
+        }
+
+        for pair in stops.windows(2) {
+            let (low, high) = (&pair[0], &pair[1]);
+            if t <= high.position {
+                let s = (t - low.position) / (high.position - low.position).max(f32::EPSILON);
+                let (low, high) = (rgba(low), rgba(high));
+                return [0, 1, 2, 3].map(|i| low[i] + (high[i] - low[i]) * s);
+            }
+        }
+
+        rgba(last)
+    }
+
+    fn table(&self) -> Vec<[f32; 4]> {
+        (0..TABLE_SIZE)
+            .map(|i| {
+                let [r, g, b, a] = self.sample(i as f32 / (TABLE_SIZE - 1) as f32);
+                // Colors are blended in linear space
+                [r.powf(2.2), g.powf(2.2), b.powf(2.2), a]
+            })
+            .collect()
+    }
+}
+
+struct DensityDeposit {
+    particles: Arc<dyn BufferAccess>,
+    num_particles: u32,
+    cells: DeviceBuffer<u32>,
+    stats: SharedBuffer<u32>,
+    image: Arc<ImageView<StorageImage>>,
+    resolution: u32,
+    constants: deposit::ty::DepositData,
+}
+
+impl ComputeShader for DensityDeposit {
+    type Constants = deposit::ty::DepositData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        deposit::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        let threads = match self.constants.pass {
+            PASS_BOUNDS | PASS_DEPOSIT => self.num_particles,
+            _ => self.resolution.pow(3),
+        };
+        [threads.div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.particles.clone()),
+            WriteDescriptorSet::buffer(1, self.cells.buffer()),
+            WriteDescriptorSet::buffer(2, self.stats.buffer()),
+            WriteDescriptorSet::image_view(3, self.image.clone()),
+        ]
+    }
+}
+
+/// Particle mass deposited onto a regular grid and rendered by ray marching through it
+#[cfg_attr(feature = "egui", derive(crate::inspect::Inspect))]
+pub struct DensityVolume {
+    #[cfg_attr(feature = "egui", inspect(skip))]
+    deposit: ComputeShaderExecutor<DensityDeposit>,
+    #[cfg_attr(feature = "egui", inspect(skip))]
+    pipeline: Arc<GraphicsPipeline>,
+    #[cfg_attr(feature = "egui", inspect(skip))]
+    sampler: Arc<Sampler>,
+    /// Transfer function table and the descriptor set using it, rebuilt when the table changes
+    #[cfg_attr(feature = "egui", inspect(skip))]
+    table: Vec<[f32; 4]>,
+    #[cfg_attr(feature = "egui", inspect(skip))]
+    descriptor_set: Option<Arc<PersistentDescriptorSet>>,
+    #[cfg_attr(feature = "egui", inspect(skip))]
+    bounds: [Point3<f32>; 2],
+    #[cfg_attr(feature = "egui", inspect(skip))]
+    max_density: f32,
+    /// Number of ray marching steps through the whole volume
+    #[cfg_attr(feature = "egui", inspect(range = 8.0..=1024.0))]
+    pub steps: u32,
+}
+
+impl DensityVolume {
+    /// `particles` holds a position and mass per particle in a `vec4`, the grid has `resolution`
+    /// cells along each axis
+    pub fn new(
+        context: &ConstructionContext,
+        subpass: Subpass,
+        particles: Arc<dyn BufferAccess>,
+        num_particles: u32,
+        resolution: u32,
+    ) -> Self {
+        let image = ImageView::new_default(
+            StorageImage::with_usage(
+                context.memory_allocator(),
+                ImageDimensions::Dim3d {
+                    width: resolution,
+                    height: resolution,
+                    depth: resolution,
+                },
+                Format::R32_SFLOAT,
+                ImageUsage {
+                    storage: true,
+                    sampled: true,
+                    ..ImageUsage::empty()
+                },
+                ImageCreateFlags::empty(),
+                context.queue_family_indices(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let deposit = DensityDeposit {
+            particles,
+            num_particles,
+            cells: DeviceBuffer::new(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                (resolution as u64).pow(3),
+            ),
+            stats: SharedBuffer::from_iter(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                [0; 7],
+            ),
+            image,
+            resolution,
+            constants: deposit::ty::DepositData {
+                box_min: [0.0; 4],
+                box_max: [0.0; 4],
+                num_particles,
+                resolution,
+                assignment: 0,
+                pass: PASS_CLEAR,
+                auto_bounds: 1,
+            },
+        };
+
+        let vs = vs::load(context.device()).unwrap();
+        let fs = fs::load(context.device()).unwrap();
+
+        // Premultiplied alpha, the ray marcher composites front to back
+        let over = AttachmentBlend {
+            color_op: BlendOp::Add,
+            color_source: BlendFactor::One,
+            color_destination: BlendFactor::OneMinusSrcAlpha,
+            alpha_op: BlendOp::Add,
+            alpha_source: BlendFactor::One,
+            alpha_destination: BlendFactor::OneMinusSrcAlpha,
+        };
+
+        let pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .render_pass(subpass)
+            .color_blend_state(ColorBlendState::new(1).blend(over))
+            .build(context.device())
+            .expect("failed to make pipeline");
+
+        let sampler = Sampler::new(
+            context.device(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        Self {
+            deposit: ComputeShaderExecutor::new(context, deposit),
+            pipeline,
+            sampler,
+            table: Vec::new(),
+            descriptor_set: None,
+            bounds: [Point3::new(0.0, 0.0, 0.0); 2],
+            max_density: 0.0,
+            steps: 128,
+        }
+    }
+
+    /// Deposit the particles onto the grid, over `bounds` if given and otherwise over the
+    /// bounding box of all particles
+    pub fn update(
+        &mut self,
+        context: &ConstructionContext,
+        assignment: Assignment,
+        bounds: Option<[Point3<f32>; 2]>,
+    ) {
+        let constants = &mut self.deposit.constants;
+        constants.assignment = match assignment {
+            Assignment::Cic => 0,
+            Assignment::Tsc => 1,
+        };
+        constants.auto_bounds = bounds.is_none() as u32;
+        if let Some([min, max]) = bounds {
+            constants.box_min = [min.x, min.y, min.z, 0.0];
+            constants.box_max = [max.x, max.y, max.z, 0.0];
+        }
+
+        for pass in [PASS_CLEAR, PASS_BOUNDS, PASS_DEPOSIT, PASS_RESOLVE] {
+            if pass == PASS_BOUNDS && bounds.is_some() {
+                continue;
+            }
+
+            self.deposit.constants.pass = pass;
+            self.deposit.execute(context);
+        }
+
+        let stats = self.deposit.stats.typed_buffer().read().unwrap().to_vec();
+        self.max_density = f32::from_bits(stats[6]);
+        self.bounds = bounds.unwrap_or_else(|| {
+            let min = [0, 1, 2].map(|i| from_order_preserving(stats[i]));
+            let max = [0, 1, 2].map(|i| from_order_preserving(stats[3 + i]));
+
+            // Same padding as `grid_box` in the shader
+            let cell = [0, 1, 2].map(|i| {
+                (max[i] - min[i]).max(1e-6) / (self.deposit.resolution as f32 - 2.0 * MARGIN)
+            });
+            [
+                Point3::new(
+                    min[0] - MARGIN * cell[0],
+                    min[1] - MARGIN * cell[1],
+                    min[2] - MARGIN * cell[2],
+                ),
+                Point3::new(
+                    max[0] + MARGIN * cell[0],
+                    max[1] + MARGIN * cell[1],
+                    max[2] + MARGIN * cell[2],
+                ),
+            ]
+        });
+    }
+
+    /// Bounds of the grid used by the last `update`
+    pub fn bounds(&self) -> [Point3<f32>; 2] {
+        self.bounds
+    }
+
+    /// Highest cell density found by the last `update`
+    pub fn max_density(&self) -> f32 {
+        self.max_density
+    }
+
+    pub fn draw(&mut self, view: ViewData, transfer: &TransferFunction, info: &mut RenderInfo) {
+        if self.max_density <= 0.0 {
+            return;
+        }
+
+        let table = transfer.table();
+        if self.descriptor_set.is_none() || table != self.table {
+            let buffer = SharedBuffer::from_iter(
+                info.construction,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                table.iter().copied(),
+            );
+
+            self.descriptor_set = Some(
+                PersistentDescriptorSet::new(
+                    info.construction.descriptor_allocator(),
+                    self.pipeline.layout().set_layouts().get(0).unwrap().clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(
+                            0,
+                            self.deposit.image.clone(),
+                            self.sampler.clone(),
+                        ),
+                        WriteDescriptorSet::buffer(1, buffer.buffer()),
+                    ],
+                )
+                .unwrap(),
+            );
+            self.table = table;
+        }
+
+        let transform: Matrix4<f32> = view.proj * view.view * view.world;
+        let [min, max] = self.bounds;
+        let constants = fs::ty::VolumeData {
+            inverse_transform: transform.invert().unwrap_or(transform).into(),
+            box_min: [min.x, min.y, min.z, 0.0],
+            box_max: [max.x, max.y, max.z, 0.0],
+            max_density: self.max_density,
+            decades: transfer.decades.max(0.1),
+            opacity: transfer.opacity,
+            steps: self.steps.max(1),
+        };
+
+        let mut builder = info.create_builder();
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                self.descriptor_set.clone().unwrap(),
+            )
+            .push_constants(self.pipeline.layout().clone(), 0, constants)
+            .set_viewport(0, vec![info.viewport.clone()])
+            .draw(3, 1, 0, 0)
+            .unwrap();
+        info.execute(builder);
+    }
+}
+
+/// Inverse of `order_preserving` in `volume_deposit.glsl`
+fn from_order_preserving(bits: u32) -> f32 {
+    f32::from_bits(if bits & 0x8000_0000 != 0 {
+        bits & 0x7fff_ffff
+    } else {
+        !bits
+    })
+}
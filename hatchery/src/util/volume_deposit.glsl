@@ -0,0 +1,173 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer Particles { vec4 data[]; } particles;
+layout(set = 0, binding = 1) buffer Cells { uint data[]; } cells;
+layout(set = 0, binding = 2) buffer Stats {
+    uint bounds_min[3];
+    uint bounds_max[3];
+    uint max_density;
+} stats;
+layout(set = 0, binding = 3, r32f) uniform writeonly image3D density;
+
+#define PASS_CLEAR 0
+#define PASS_BOUNDS 1
+#define PASS_DEPOSIT 2
+#define PASS_RESOLVE 3
+
+#define ASSIGNMENT_CIC 0
+#define ASSIGNMENT_TSC 1
+
+// Empty cells around the particles so the assignment kernels never leave the grid
+#define MARGIN 2.0
+
+layout(push_constant) uniform DepositData {
+    vec4 box_min;
+    vec4 box_max;
+    uint num_particles;
+    uint resolution;
+    uint assignment;
+    uint pass;
+    uint auto_bounds;
+} dd;
+
+// Maps floats onto uints with the same ordering, so bounds can be found with integer atomics
+uint order_preserving(float value) {
+    uint bits = floatBitsToUint(value);
+    return (bits & 0x80000000u) != 0 ? ~bits : bits | 0x80000000u;
+}
+
+float from_order_preserving(uint bits) {
+    return uintBitsToFloat((bits & 0x80000000u) != 0 ? bits & 0x7fffffffu : ~bits);
+}
+
+// There are no float atomics in core Vulkan, so emulate them with a compare and swap loop
+void atomic_add_cell(uint index, float value) {
+    uint expected = cells.data[index];
+    while (true) {
+        uint desired = floatBitsToUint(uintBitsToFloat(expected) + value);
+        uint actual = atomicCompSwap(cells.data[index], expected, desired);
+        if (actual == expected) {
+            break;
+        }
+        expected = actual;
+    }
+}
+
+void grid_box(out vec3 box_min, out vec3 box_max) {
+    if (dd.auto_bounds == 0) {
+        box_min = dd.box_min.xyz;
+        box_max = dd.box_max.xyz;
+        return;
+    }
+
+    vec3 particles_min, particles_max;
+    for (int i = 0; i < 3; i++) {
+        particles_min[i] = from_order_preserving(stats.bounds_min[i]);
+        particles_max[i] = from_order_preserving(stats.bounds_max[i]);
+    }
+
+    vec3 cell = max(particles_max - particles_min, vec3(1e-6)) / (float(dd.resolution) - 2.0 * MARGIN);
+    box_min = particles_min - MARGIN * cell;
+    box_max = particles_max + MARGIN * cell;
+}
+
+void deposit(ivec3 cell, float mass) {
+    int n = int(dd.resolution);
+    if (any(lessThan(cell, ivec3(0))) || any(greaterThanEqual(cell, ivec3(n)))) {
+        return;
+    }
+
+    atomic_add_cell(uint(cell.x + n * (cell.y + n * cell.z)), mass);
+}
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+    uint n = dd.resolution;
+
+    if (dd.pass == PASS_CLEAR) {
+        if (gi == 0) {
+            for (int i = 0; i < 3; i++) {
+                stats.bounds_min[i] = 0xffffffffu;
+                stats.bounds_max[i] = 0u;
+            }
+            stats.max_density = 0u;
+        }
+        if (gi < n * n * n) {
+            cells.data[gi] = 0u;
+        }
+        return;
+    }
+
+    if (dd.pass == PASS_RESOLVE) {
+        if (gi >= n * n * n) {
+            return;
+        }
+
+        vec3 box_min, box_max;
+        grid_box(box_min, box_max);
+        vec3 cell = (box_max - box_min) / float(n);
+
+        float rho = uintBitsToFloat(cells.data[gi]) / (cell.x * cell.y * cell.z);
+        imageStore(density, ivec3(gi % n, (gi / n) % n, gi / (n * n)), vec4(rho));
+        // Densities are never negative so their bit patterns are already ordered
+        atomicMax(stats.max_density, floatBitsToUint(rho));
+        return;
+    }
+
+    if (gi >= dd.num_particles) {
+        return;
+    }
+
+    vec4 particle = particles.data[gi];
+
+    if (dd.pass == PASS_BOUNDS) {
+        // Particles without mass deposit nothing, so they don't stretch the bounds
+        if (particle.w <= 0.0) {
+            return;
+        }
+        for (int i = 0; i < 3; i++) {
+            atomicMin(stats.bounds_min[i], order_preserving(particle[i]));
+            atomicMax(stats.bounds_max[i], order_preserving(particle[i]));
+        }
+        return;
+    }
+
+    vec3 box_min, box_max;
+    grid_box(box_min, box_max);
+
+    // Grid coordinates relative to the cell centers
+    vec3 g = (particle.xyz - box_min) / (box_max - box_min) * float(n) - 0.5;
+
+    if (dd.assignment == ASSIGNMENT_CIC) {
+        vec3 base = floor(g);
+        vec3 f = g - base;
+        for (int z = 0; z < 2; z++) {
+            for (int y = 0; y < 2; y++) {
+                for (int x = 0; x < 2; x++) {
+                    vec3 w = mix(1.0 - f, f, vec3(x, y, z));
+                    deposit(ivec3(base) + ivec3(x, y, z), particle.w * w.x * w.y * w.z);
+                }
+            }
+        }
+    } else {
+        vec3 center = round(g);
+        vec3 d = g - center;
+        vec3 weights[3] = vec3[3](
+            0.5 * (0.5 - d) * (0.5 - d),
+            0.75 - d * d,
+            0.5 * (0.5 + d) * (0.5 + d)
+        );
+        for (int z = 0; z < 3; z++) {
+            for (int y = 0; y < 3; y++) {
+                for (int x = 0; x < 3; x++) {
+                    float w = weights[x].x * weights[y].y * weights[z].z;
+                    deposit(ivec3(center) + ivec3(x - 1, y - 1, z - 1), particle.w * w);
+                }
+            }
+        }
+    }
+}
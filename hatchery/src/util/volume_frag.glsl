@@ -0,0 +1,75 @@
+#version 450
+
+layout(location = 0) in vec2 f_uv;
+
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler3D density;
+layout(set = 0, binding = 1) buffer Transfer { vec4 data[]; } transfer;
+
+#define TABLE_SIZE 256
+
+layout(push_constant) uniform VolumeData {
+    mat4 inverse_transform;
+    vec4 box_min;
+    vec4 box_max;
+    float max_density;
+    float decades;
+    float opacity;
+    uint steps;
+} vd;
+
+vec4 transfer_function(float t) {
+    float x = clamp(t, 0.0, 1.0) * float(TABLE_SIZE - 1);
+    uint i = min(uint(x), TABLE_SIZE - 2);
+    return mix(transfer.data[i], transfer.data[i + 1], x - float(i));
+}
+
+void main() {
+    // The projection uses OpenGL depth conventions, so -1 and 1 are the near and far planes
+    vec2 ndc = f_uv * 2.0 - 1.0;
+    vec4 near = vd.inverse_transform * vec4(ndc, -1.0, 1.0);
+    vec4 far = vd.inverse_transform * vec4(ndc, 1.0, 1.0);
+    vec3 origin = near.xyz / near.w;
+    vec3 direction = far.xyz / far.w - origin;
+
+    // Slab test against the volume bounds, in units of the near to far distance
+    vec3 t0 = (vd.box_min.xyz - origin) / direction;
+    vec3 t1 = (vd.box_max.xyz - origin) / direction;
+    vec3 t_min = min(t0, t1);
+    vec3 t_max = max(t0, t1);
+    float t_enter = max(max(max(t_min.x, t_min.y), t_min.z), 0.0);
+    float t_exit = min(min(min(t_max.x, t_max.y), t_max.z), 1.0);
+    if (t_exit <= t_enter) {
+        discard;
+    }
+
+    // Opacity is given per box diagonal so it doesn't depend on the number of steps
+    vec3 extent = vd.box_max.xyz - vd.box_min.xyz;
+    float dt = (t_exit - t_enter) / float(vd.steps);
+    float step_length = dt * length(direction) / length(extent);
+
+    vec4 color = vec4(0.0);
+    for (uint i = 0; i < vd.steps && color.a < 0.99; i++) {
+        vec3 position = origin + (t_enter + (float(i) + 0.5) * dt) * direction;
+        float rho = texture(density, (position - vd.box_min.xyz) / extent).r;
+        if (rho <= 0.0) {
+            continue;
+        }
+
+        // Zero at `decades` orders of magnitude below the peak density, one at the peak
+        float level = 1.0 + log(rho / vd.max_density) / (vd.decades * log(10.0));
+        if (level <= 0.0) {
+            continue;
+        }
+
+        vec4 sample_color = transfer_function(level);
+        float alpha = 1.0 - exp(-sample_color.a * vd.opacity * step_length);
+
+        // Front to back compositing with premultiplied alpha
+        color.rgb += (1.0 - color.a) * alpha * sample_color.rgb;
+        color.a += (1.0 - color.a) * alpha;
+    }
+
+    out_color = color;
+}
@@ -0,0 +1,329 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Attribute, Data, DataEnum, DeriveInput, Error, Expr, ExprRange, Fields,
+    Ident, LitStr, Member, RangeLimits, Result,
+};
+
+/// Derives `hatchery::inspect::Inspect`, which draws an egui editor for a value.
+///
+/// Structs are shown as a two column grid with a row per field, fields need to implement
+/// `Inspect` themselves so nested structs work as well. Enums are shown as a combo box, followed
+/// by the fields of the selected variant. Switching to a variant with fields fills them with
+/// their `Default`. Fields and variants accept the following options:
+///
+/// - `#[inspect(skip)]` leaves the field out
+/// - `#[inspect(label = "Time step")]` overrides the label, which defaults to the field name
+/// - `#[inspect(range = 0.0..=1.0)]` clamps numbers to an inclusive range
+/// - `#[inspect(speed = 0.01)]` sets the drag speed of numbers
+/// - `#[inspect(log)]` uses a logarithmic slider, this requires a range
+/// - `#[inspect(color)]` edits a `[f32; 4]` as an unmultiplied RGBA color
+/// - `#[inspect(on_change = "method")]` calls `self.method()` whenever the field is edited, only
+///   on the fields of structs
+#[proc_macro_derive(Inspect, attributes(inspect))]
+pub fn derive_inspect(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => inspect_struct(name, &data.fields)?,
+        Data::Enum(data) => inspect_enum(name, data)?,
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                name,
+                "`Inspect` can't be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::hatchery::inspect::Inspect for #name #ty_generics #where_clause {
+            fn inspect(
+                &mut self,
+                ui: &mut ::hatchery::egui_implementation::egui::Ui,
+                options: &::hatchery::inspect::InspectOptions,
+            ) -> bool {
+                #body
+            }
+        }
+    })
+}
+
+#[derive(Default)]
+struct Options {
+    skip: bool,
+    label: Option<LitStr>,
+    range: Option<(Expr, Expr)>,
+    speed: Option<Expr>,
+    log: bool,
+    color: bool,
+    on_change: Option<Ident>,
+}
+
+fn parse_options(attrs: &[Attribute]) -> Result<Options> {
+    let mut options = Options::default();
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("inspect")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                options.skip = true;
+            } else if meta.path.is_ident("log") {
+                options.log = true;
+            } else if meta.path.is_ident("color") {
+                options.color = true;
+            } else if meta.path.is_ident("label") {
+                options.label = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("speed") {
+                options.speed = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("range") {
+                let range: ExprRange = meta.value()?.parse()?;
+                match (range.start, range.limits, range.end) {
+                    (Some(start), RangeLimits::Closed(_), Some(end)) => {
+                        options.range = Some((*start, *end));
+                    }
+                    _ => return Err(meta.error("expected an inclusive range like `0.0..=1.0`")),
+                }
+            } else if meta.path.is_ident("on_change") {
+                let method: LitStr = meta.value()?.parse()?;
+                options.on_change = Some(method.parse()?);
+            } else {
+                return Err(meta.error("unknown `inspect` option"));
+            }
+
+            Ok(())
+        })?;
+
+        if options.log && options.range.is_none() {
+            return Err(Error::new_spanned(
+                attr,
+                "`log` requires a `range` to be specified",
+            ));
+        }
+    }
+
+    Ok(options)
+}
+
+/// Turns `field_name` into `Field name`
+fn humanize(name: &str) -> String {
+    let name = name.trim_start_matches("r#").replace('_', " ");
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+/// Grid rows for the given fields, `value` turns a field into an expression borrowing it mutably
+fn field_rows(
+    fields: &Fields,
+    value: impl Fn(usize, &Member) -> TokenStream2,
+    with_on_change: bool,
+) -> Result<Vec<TokenStream2>> {
+    let mut rows = Vec::new();
+
+    for (i, field) in fields.iter().enumerate() {
+        let options = parse_options(&field.attrs)?;
+        if options.skip {
+            continue;
+        }
+
+        let (member, default_label) = match &field.ident {
+            Some(ident) => (Member::from(ident.clone()), humanize(&ident.to_string())),
+            None => (Member::from(i), i.to_string()),
+        };
+        let label = options.label.map_or(default_label, |label| label.value());
+
+        let range = match options.range {
+            Some((start, end)) => quote! { Some((#start) as f64..=(#end) as f64) },
+            None => quote! { None },
+        };
+        let speed = match options.speed {
+            Some(speed) => quote! { Some((#speed) as f64) },
+            None => quote! { None },
+        };
+        let logarithmic = options.log;
+        let on_change = match options.on_change {
+            Some(method) if with_on_change => Some(quote! { self.#method(); }),
+            Some(method) => {
+                return Err(Error::new_spanned(
+                    method,
+                    "`on_change` is only supported on the fields of structs",
+                ))
+            }
+            None => None,
+        };
+        let value = value(i, &member);
+        let edit = if options.color {
+            quote! { ui.color_edit_button_rgba_unmultiplied(#value).changed() }
+        } else {
+            quote! { ::hatchery::inspect::Inspect::inspect(#value, ui, &field_options) }
+        };
+
+        rows.push(quote! {
+            ui.label(#label);
+            let field_options = ::hatchery::inspect::InspectOptions {
+                label: #label,
+                range: #range,
+                speed: #speed,
+                logarithmic: #logarithmic,
+            };
+            if #edit {
+                changed = true;
+                #on_change
+            }
+            ui.end_row();
+        });
+    }
+
+    Ok(rows)
+}
+
+fn grid(id: TokenStream2, rows: &[TokenStream2]) -> TokenStream2 {
+    quote! {
+        ::hatchery::egui_implementation::egui::Grid::new(#id)
+            .num_columns(2)
+            .spacing([10.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                #(#rows)*
+            });
+    }
+}
+
+fn inspect_struct(name: &Ident, fields: &Fields) -> Result<TokenStream2> {
+    let rows = field_rows(fields, |_, member| quote! { &mut self.#member }, true)?;
+
+    let id = name.to_string();
+    let grid = grid(quote! { (#id, options.label) }, &rows);
+    Ok(quote! {
+        let mut changed = false;
+        #grid
+        changed
+    })
+}
+
+/// Name of the binding of a field in a `match` over the variants
+fn binding(i: usize) -> Ident {
+    format_ident!("field_{}", i)
+}
+
+fn inspect_enum(name: &Ident, data: &DataEnum) -> Result<TokenStream2> {
+    let mut labels = Vec::new();
+    let mut patterns = Vec::new();
+    let mut defaults = Vec::new();
+    let mut editors = Vec::new();
+
+    let id = name.to_string();
+    for variant in &data.variants {
+        let options = parse_options(&variant.attrs)?;
+        if options.skip {
+            continue;
+        }
+
+        let ident = &variant.ident;
+        let label = options
+            .label
+            .map_or(ident.to_string(), |label| label.value());
+
+        let bindings: Vec<Ident> = (0..variant.fields.len()).map(binding).collect();
+        let (pattern, default, destructure) = match &variant.fields {
+            Fields::Unit => (quote! { #name::#ident }, quote! { #name::#ident }, None),
+            Fields::Unnamed(_) => (
+                quote! { #name::#ident(..) },
+                {
+                    let defaults = bindings.iter().map(|_| quote! { Default::default() });
+                    quote! { #name::#ident(#(#defaults),*) }
+                },
+                Some(quote! { #name::#ident(#(#bindings),*) }),
+            ),
+            Fields::Named(fields) => {
+                let members: Vec<_> = fields.named.iter().map(|field| &field.ident).collect();
+                (
+                    quote! { #name::#ident { .. } },
+                    quote! { #name::#ident { #(#members: Default::default()),* } },
+                    Some(quote! { #name::#ident { #(#members: #bindings),* } }),
+                )
+            }
+        };
+
+        // The fields of the selected variant are shown below the combo box. A single unnamed
+        // field is shown without a label, like a nested struct.
+        if let Some(destructure) = destructure {
+            let single = matches!(variant.fields, Fields::Unnamed(_)) && variant.fields.len() == 1;
+            let editor =
+                if single && !parse_options(&variant.fields.iter().next().unwrap().attrs)?.skip {
+                    quote! {
+                        changed |= ::hatchery::inspect::Inspect::inspect(field_0, ui, options);
+                    }
+                } else {
+                    let rows = field_rows(
+                        &variant.fields,
+                        |i, _| {
+                            let binding = binding(i);
+                            quote! { #binding }
+                        },
+                        false,
+                    )?;
+                    grid(quote! { (#id, #label, options.label) }, &rows)
+                };
+            editors.push(quote! {
+                #[allow(unused_variables)]
+                #destructure => { #editor }
+            });
+        }
+
+        labels.push(label);
+        patterns.push(pattern);
+        defaults.push(default);
+    }
+
+    let combo_box = quote! {
+        ::hatchery::egui_implementation::egui::ComboBox::from_id_source((#id, options.label))
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                #(
+                    let active = matches!(self, #patterns);
+                    if ui.selectable_label(active, #labels).clicked() && !active {
+                        *self = #defaults;
+                        changed = true;
+                    }
+                )*
+            });
+    };
+
+    // Enums with only unit variants are just a combo box
+    let body = if editors.is_empty() {
+        combo_box
+    } else {
+        quote! {
+            ui.vertical(|ui| {
+                #combo_box
+                match self {
+                    #(#editors)*
+                    #[allow(unreachable_patterns)]
+                    _ => {}
+                }
+            });
+        }
+    };
+
+    Ok(quote! {
+        let mut changed = false;
+        let selected = match self {
+            #(#patterns => #labels,)*
+            #[allow(unreachable_patterns)]
+            _ => "",
+        };
+        #body
+        changed
+    })
+}
@@ -8,61 +8,198 @@ use std::{
 
 use cgmath::{num_traits::Pow, InnerSpace, Point3, Vector3, Zero};
 use distributions::{BallOfGas, Galaxy, Plummer};
-use egui::{
-    plot::{HLine, Line, Plot, PlotPoints},
-    Color32, DragValue, Grid, Window,
-};
+use egui::{Align2, Color32, Grid, Key, ScrollArea, TextEdit, Window};
 use egui_implementation::*;
 use egui_widgets::*;
 use hatchery::{
-    dpi::PhysicalPosition,
-    event::{
-        ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
-    },
+    event::{ModifiersState, MouseButton, VirtualKeyCode, WindowEvent},
+    input::{ActionMap, Binding},
+    inspect::{Inspect, InspectOptions},
     util::compute::ComputeShaderExecutor,
     *,
 };
 use noise::{core::perlin, NoiseFn, Perlin};
-use physics::{energy::EnergyCalculator, verlet::VerletIntegrator, Particle, SimulationBuffers};
-use rand::{thread_rng, Rng};
+use physics::{
+    attributes::{AttributeCalculator, Quantity},
+    collisions::Collisions,
+    conservation::{Conservation, QUANTITIES},
+    cpu::{self, Body, Comparison, CpuSimulation, CpuSolver, Gravity},
+    energy::EnergyCalculator,
+    integrator::TimeIntegrator,
+    Particle, ParticleState, SimulationBuffers,
+};
+use rand::{seq::index, thread_rng, Rng};
 use rand_distr::{Uniform, UnitBall, UnitCircle};
-use util::{buffer::AbstractBuffer, camera::Camera, point_cloud::PointCloudPipeline};
+#[cfg(feature = "scripting")]
+use scripting::{Command, Diagnostics, Scripting};
+use serde::{Deserialize, Serialize};
+use util::{
+    buffer::AbstractBuffer,
+    camera::Camera,
+    colormap::{ColorScale, Normalization},
+    debug_draw::DebugDraw,
+    picking::PointPicker,
+    point_cloud::{PointCloudPipeline, PointColors, PointStyle},
+    trails::{TrailStyle, Trails},
+    volume::{Assignment, DensityVolume, TransferFunction},
+};
 
 mod distributions;
 mod physics;
+#[cfg(feature = "scripting")]
+mod scripting;
 
 const GRAVITATIONAL_CONSTANT: f32 = 0.01;
+const SOFTENING: f32 = 0.1;
+const TRAIL_CAPACITY: u32 = 2048;
+const VOLUME_RESOLUTION: u32 = 64;
+const ENERGY_SAMPLES: usize = 4096;
+/// Particles checked against the CPU, the exact sum for all of them would take too long
+const COMPARISON_SAMPLES: usize = 1024;
+const HEADLESS_PARTICLES: u32 = 20_000;
+/// Most recent mergers listed in the GUI, the full log is exported as csv
+const MERGER_LOG_ROWS: usize = 8;
+/// Steps between the energy reports of the headless mode
+const HEADLESS_REPORT_INTERVAL: u64 = 100;
+/// Values of `--solver=` in the headless mode
+const HEADLESS_SOLVERS: &str = "direct, barnes-hut, fmm, pm, treepm";
 
+/// Settings which are restored on the next launch, everything tied to the current run is skipped.
+/// The settings of the side panel are inspected, the rest has its own widgets.
+#[derive(Serialize, Deserialize, Inspect)]
+#[serde(default)]
 pub struct GuiState {
+    #[serde(skip)]
+    #[inspect(skip)]
     active: bool,
-    brightness: f32,
-    scale: f32,
+    #[inspect(label = "Points")]
+    style: PointStyle,
 
     show_energy: bool,
+    show_conservation: bool,
+    #[inspect(label = "Show key bindings")]
+    show_help: bool,
+    show_grid: bool,
+    show_labels: bool,
+    show_trails: bool,
+    #[inspect(label = "Trails")]
+    trail_style: TrailStyle,
+    #[serde(skip)]
+    #[inspect(skip)]
+    tracked: Vec<u32>,
+    show_volume: bool,
+    #[inspect(label = "Volume assignment")]
+    assignment: Assignment,
+    #[inspect(label = "Transfer function")]
+    transfer: TransferFunction,
+    color_by: Option<Quantity>,
+    #[inspect(label = "Colors")]
+    color_scale: ColorScale,
+    #[serde(skip)]
+    #[inspect(skip)]
     last_simulation_time: Duration,
-    energy: Vec<f32>,
+    #[serde(skip)]
+    #[inspect(skip)]
+    simulation_time: f32,
+    #[serde(skip)]
+    #[inspect(skip)]
+    steps: u64,
+    #[serde(skip)]
+    #[inspect(skip)]
+    energy: TimeSeries,
+    /// Drifts of the conserved quantities
+    #[serde(skip)]
+    #[inspect(skip)]
+    conservation: TimeSeries,
+    #[serde(skip)]
+    #[inspect(skip)]
+    landmarks: Vec<(String, Point3<f32>)>,
+
+    #[cfg(feature = "scripting")]
+    show_console: bool,
+    #[cfg(feature = "scripting")]
+    #[serde(skip)]
+    #[inspect(skip)]
+    console_input: String,
+
+    #[serde(skip)]
+    #[inspect(skip)]
+    cursor: [f32; 2],
+    #[serde(skip)]
+    #[inspect(skip)]
+    pick_request: Option<[f32; 2]>,
+    #[serde(skip)]
+    #[inspect(skip)]
+    selected: Option<u32>,
+    #[serde(skip)]
+    #[inspect(skip)]
+    inspected: Option<ParticleState>,
+    #[serde(skip)]
+    #[inspect(skip)]
+    comparison: Option<Comparison>,
+    /// Outcome of the last export of the merger log
+    #[serde(skip)]
+    #[inspect(skip)]
+    merger_export: Option<String>,
 }
 
 impl Default for GuiState {
     fn default() -> Self {
         Self {
             active: false,
-            brightness: 1.0,
-            scale: 0.2,
+            style: PointStyle::default(),
 
             last_simulation_time: Duration::default(),
             show_energy: false,
-            energy: Vec::new(),
+            show_conservation: false,
+            show_help: false,
+            show_grid: false,
+            show_labels: true,
+            show_trails: true,
+            trail_style: TrailStyle::default(),
+            tracked: Vec::new(),
+            show_volume: false,
+            assignment: Assignment::Cic,
+            transfer: TransferFunction::default(),
+            color_by: None,
+            color_scale: ColorScale::default(),
+            simulation_time: 0.0,
+            steps: 0,
+            energy: TimeSeries::new(ENERGY_SAMPLES, Retention::Decimate),
+            conservation: TimeSeries::new(ENERGY_SAMPLES, Retention::Decimate),
+            landmarks: Vec::new(),
+
+            #[cfg(feature = "scripting")]
+            show_console: false,
+            #[cfg(feature = "scripting")]
+            console_input: String::new(),
+
+            cursor: [0.0, 0.0],
+            pick_request: None,
+            selected: None,
+            inspected: None,
+            comparison: None,
+            merger_export: None,
         }
     }
 }
 
 pub struct TardigradeEngine {
     simulation: Arc<SimulationBuffers>,
-    integrator: ComputeShaderExecutor<VerletIntegrator>,
-    energy: ComputeShaderExecutor<EnergyCalculator>,
+    integrator: TimeIntegrator,
+    energy: EnergyCalculator,
+    conservation: Conservation,
+    collisions: Collisions,
+    attributes: ComputeShaderExecutor<AttributeCalculator>,
     render: PointCloudPipeline,
+    picker: PointPicker,
+    trails: Trails,
+    volume: DensityVolume,
+    debug: DebugDraw,
     camera: Camera,
+    actions: ActionMap,
+    #[cfg(feature = "scripting")]
+    scripting: Scripting,
     state: GuiState,
 }
 
@@ -83,16 +220,26 @@ impl Engine for TardigradeEngine {
         // );
         // particles.append(&mut gas.get_particles(num_particles, &mut rng));
 
-        let galaxy1 = Galaxy::new(
-            1000.0,
-            1.0,
-            Plummer::new(1.0, 0.1),
-            // Uniform::new(0.1, 3.0),
-            Point3::new(0.0, 4.0, 4.0),
-            Vector3::new(0.0, 0.0, 0.0),
-            Vector3::new(1.0, 0.0, 0.0),
-        );
-        particles.append(&mut galaxy1.get_particles(num_particles / 2, &mut rng));
+        // A script passed as the first argument can build the scene instead
+        #[cfg(feature = "scripting")]
+        let mut scripting = Scripting::new();
+        #[cfg(feature = "scripting")]
+        if let Some(path) = std::env::args().skip(1).find(|arg| !arg.starts_with("--")) {
+            // Errors end up in the console log
+            let _ = scripting.run_file(path);
+            particles.append(&mut scripting.take_particles());
+        }
+
+        let mut tracked = Vec::new();
+        let mut landmarks = Vec::new();
+        if particles.is_empty() {
+            let (mut galaxy, center) =
+                default_galaxy(num_particles / 2, Point3::new(0.0, 4.0, 4.0), &mut rng);
+            particles.append(&mut galaxy);
+            // The central black hole is appended last
+            tracked.push(particles.len() as u32 - 1);
+            landmarks.push(("Galaxy 1".to_string(), center));
+        }
         //
         // let dim: f32 = 1.0;
         // let scale: f32 = 0.1;
@@ -115,47 +262,213 @@ impl Engine for TardigradeEngine {
         // }
 
         let dt: f32 = 0.001;
-        let softening: f32 = 0.1;
 
         let simulation = SimulationBuffers::new(context.api().construction(), particles);
 
-        let integrator =
-            VerletIntegrator::new(simulation.clone(), dt, GRAVITATIONAL_CONSTANT, softening);
-        let integrator = ComputeShaderExecutor::new(context.api().construction(), integrator);
+        let integrator = TimeIntegrator::new(
+            context.api().construction(),
+            simulation.clone(),
+            dt,
+            GRAVITATIONAL_CONSTANT,
+            SOFTENING,
+        );
+
+        let energy = EnergyCalculator::new(context.api().construction(), simulation.clone());
+        let conservation = Conservation::new(context.api().construction(), simulation.clone());
+        let collisions = Collisions::new(context.api().construction(), simulation.clone());
+
+        let attributes = AttributeCalculator::new(simulation.clone(), context.api().construction());
+        let attributes = ComputeShaderExecutor::new(context.api().construction(), attributes);
+
+        let picker = PointPicker::new(context.api().construction(), &simulation.points);
+
+        let trails = Trails::new(
+            context.api().construction(),
+            context.viewport_subpass(),
+            &simulation.points,
+            &tracked,
+            TRAIL_CAPACITY,
+        );
+
+        let volume = DensityVolume::new(
+            context.api().construction(),
+            context.viewport_subpass(),
+            simulation.position_mass.buffer(),
+            simulation.capacity,
+            VOLUME_RESOLUTION,
+        );
 
-        let energy = EnergyCalculator::new(simulation.clone(), context.api().construction());
-        let energy = ComputeShaderExecutor::new(context.api().construction(), energy);
+        // Settings of the last session
+        let storage = &context.api().storage;
 
         Self {
             simulation,
             integrator,
             energy,
+            conservation,
+            collisions,
+            attributes,
             render: PointCloudPipeline::new(
                 context.api().construction(),
                 context.viewport_subpass(),
             ),
-            camera: Camera::new(),
-            state: Default::default(),
+            picker,
+            trails,
+            volume,
+            debug: DebugDraw::new(context.api().construction(), context.viewport_subpass()),
+            camera: storage.get("camera").unwrap_or_else(Camera::new),
+            actions: ActionMap::new()
+                .with_camera_controls()
+                .action(
+                    "pick",
+                    "Select the particle under the cursor",
+                    [Binding::mouse(MouseButton::Left)],
+                )
+                .action(
+                    "help",
+                    "Show the key bindings",
+                    [Binding::key(VirtualKeyCode::F1)],
+                )
+                .action(
+                    "quit",
+                    "Save the settings and quit",
+                    [Binding::key(VirtualKeyCode::Q).with_modifiers(ModifiersState::CTRL)],
+                )
+                .with_keymap(storage),
+            #[cfg(feature = "scripting")]
+            scripting,
+            state: GuiState {
+                landmarks,
+                tracked,
+                ..storage.get("gui").unwrap_or_default()
+            },
         }
     }
 
+    fn stop(&mut self, api: &mut EngineApi) {
+        api.storage.set("camera", &self.camera);
+        api.storage.set("gui", &self.state);
+    }
+
     fn render(&mut self, info: &mut RenderInfo, api: &EngineApi) {
+        #[cfg(feature = "scripting")]
+        self.apply_script_commands(api);
+
+        // The inspected particle only changes with a step or a new selection
+        let mut refresh = self.state.active || self.state.inspected.is_none();
         if self.state.active {
             let start = Instant::now();
-            self.integrator.execute(api.construction());
-            self.energy.execute(api.construction());
-            self.state.energy.push(self.energy.get_total_energy());
+            if self.conservation.due(self.state.steps) {
+                self.measure(api);
+            }
+            self.integrator.step(api.construction());
+            self.trails.record(api.construction());
+            self.state.simulation_time += self.integrator.dt();
+            self.state.steps += 1;
+            let merged = self
+                .collisions
+                .resolve(api.construction(), self.state.simulation_time);
+            if merged > 0 {
+                self.integrator.invalidate_forces();
+                self.follow_mergers(api);
+            }
+
+            #[cfg(feature = "scripting")]
+            self.scripting.update(Diagnostics {
+                time: self.state.simulation_time,
+                steps: self.state.steps,
+                kinetic_energy: self.energy.energy().kinetic as f32,
+                potential_energy: self.energy.energy().potential as f32,
+                num_particles: self.simulation.num_particles(),
+            });
             self.state.last_simulation_time = start.elapsed();
         }
 
+        let view = self
+            .camera
+            .generate_view(info.viewport.dimensions[0] / info.viewport.dimensions[1]);
+
+        if let Some(cursor) = self.state.pick_request.take() {
+            self.state.selected = self.picker.pick(
+                api.construction(),
+                cursor,
+                view,
+                &info.viewport,
+                &self.state.style,
+            );
+            refresh = true;
+        }
+
+        if refresh {
+            self.state.inspected = self.state.selected.map(|index| {
+                self.energy.potential_at(
+                    api.construction(),
+                    index,
+                    self.integrator.g(),
+                    self.integrator.softening(),
+                );
+                self.simulation.particle(api.construction(), index)
+            });
+        }
+
+        if let Some(quantity) = self.state.color_by {
+            let scale = &mut self.state.color_scale;
+            self.attributes.quantity = quantity;
+            self.attributes.positive_only = scale.normalization == Normalization::Log;
+            self.attributes.reset_range();
+            self.attributes.execute(api.construction());
+            if scale.auto_range {
+                if let Some(range) = self.attributes.range() {
+                    scale.fit(range);
+                }
+            }
+        }
+
+        let colors = match self.state.color_by {
+            Some(_) => PointColors::Scalar {
+                values: self.attributes.attributes().buffer(),
+                scale: &self.state.color_scale,
+            },
+            None => PointColors::Uniform,
+        };
+
         self.render.draw(
             &self.simulation.points,
-            self.camera
-                .generate_view(info.viewport.dimensions[0] / info.viewport.dimensions[1]),
-            self.state.brightness,
-            self.state.scale,
+            colors,
+            view,
+            &self.state.style,
             info,
         );
+
+        if self.state.show_volume {
+            self.volume
+                .update(api.construction(), self.state.assignment, None);
+            self.volume.draw(view, &self.state.transfer, info);
+        }
+
+        if self.state.show_trails {
+            self.trails.draw(view, &self.state.trail_style, info);
+        }
+
+        if self.state.show_grid {
+            self.debug
+                .grid(Point3::new(0.0, 0.0, 0.0), 10.0, 20, [0.5, 0.5, 0.5, 0.3]);
+            self.debug.axes(Point3::new(0.0, 0.0, 0.0), 1.0);
+        }
+
+        if let Some(particle) = &self.state.inspected {
+            // Slightly larger than the drawn footprint of the particle, the scale of the camera is
+            // already part of the view matrix
+            let radius = 1.5 * self.state.style.size / view.proj.y.y;
+            self.debug.circle(
+                particle.position,
+                self.camera.direction,
+                radius,
+                [1.0, 0.9, 0.2, 1.0],
+            );
+        }
+
+        self.debug.draw(view, info);
     }
 
     fn immediate(&mut self, context: &mut egui::Context, api: &mut EngineApi) {
@@ -170,6 +483,16 @@ impl Engine for TardigradeEngine {
                 ui.label(format!("Using: {}", api.device_name()));
                 ui.separator();
 
+                self.state.inspect(
+                    ui,
+                    &InspectOptions {
+                        label: "Settings",
+                        ..Default::default()
+                    },
+                );
+
+                ui.separator();
+
                 Grid::new("render_settings")
                     .num_columns(2)
                     .spacing([10.0, 4.0])
@@ -177,21 +500,6 @@ impl Engine for TardigradeEngine {
                     .min_col_width(width / 2.0)
                     .striped(true)
                     .show(ui, |ui| {
-                        ui.label("Brightness");
-                        ui.add(
-                            DragValue::new(&mut self.state.brightness)
-                                .speed(0.02)
-                                .clamp_range(0.01..=50.0),
-                        );
-                        ui.end_row();
-                        ui.label("Scale:");
-                        ui.add(
-                            DragValue::new(&mut self.state.scale)
-                                .speed(0.02)
-                                .clamp_range(0.0..=2.0),
-                        );
-                        ui.end_row();
-
                         ui.label("FPS");
                         ui.label(format!(
                             "{:.0}",
@@ -209,10 +517,147 @@ impl Engine for TardigradeEngine {
                         ));
                         ui.end_row();
 
-                        ui.label("Show energy:");
-                        ui.checkbox(&mut self.state.show_energy, "");
-                        ui.end_row()
+                        if !self.conservation.warnings().is_empty() {
+                            ui.label("Conservation:");
+                            ui.colored_label(Color32::RED, "Drifting");
+                            ui.end_row();
+                        }
+                    });
+
+                ui.separator();
+
+                if let Some(quantity) = self.state.color_by {
+                    ui.add(ColormapLegend::new(&self.state.color_scale).label(quantity.name()));
+                    ui.separator();
+                }
+
+                if self.state.show_volume {
+                    self.volume.inspect(
+                        ui,
+                        &InspectOptions {
+                            label: "Volume",
+                            ..Default::default()
+                        },
+                    );
+                    ui.add(TransferFunctionEditor::new(&mut self.state.transfer));
+                    ui.separator();
+                }
+
+                ui.collapsing("Integrator", |ui| {
+                    self.integrator.inspect(ui, &InspectOptions::default());
+
+                    let precision = self.integrator.precision();
+                    if precision.available(api.construction()) != precision {
+                        ui.label("No shader_float64 on this device, using compensated sums");
+                    }
+
+                    if let Some(levels) = self.integrator.level_statistics() {
+                        let dt = self.integrator.dt();
+                        let num_particles = self.simulation.num_particles() as f32;
+                        Grid::new("timestep_levels")
+                            .num_columns(3)
+                            .spacing([10.0, 4.0])
+                            .striped(true)
+                            .show(ui, |ui| {
+                                ui.label("Level");
+                                ui.label("Step");
+                                ui.label("Particles");
+                                ui.end_row();
+
+                                for (level, count) in levels.histogram.iter().enumerate() {
+                                    ui.label(level.to_string());
+                                    ui.label(format!("{:.3e}", dt / (1 << level) as f32));
+                                    ui.label(count.to_string());
+                                    ui.end_row();
+                                }
+                            });
+
+                        // Relative to kicking every particle on the deepest level
+                        let global = num_particles * (1 << levels.depth) as f32;
+                        ui.label(format!(
+                            "Updates per step: {} ({:.1}% of a global step)",
+                            levels.updates,
+                            100.0 * levels.updates as f32 / global
+                        ));
+                    }
+
+                    if ui.button("Compare with CPU").clicked() {
+                        self.compare_with_cpu(api);
+                    }
+
+                    if let Some(comparison) = &self.state.comparison {
+                        Grid::new("cpu_comparison")
+                            .num_columns(2)
+                            .spacing([10.0, 4.0])
+                            .striped(true)
+                            .show(ui, |ui| {
+                                ui.label("Sampled particles:");
+                                ui.label(comparison.errors.len().to_string());
+                                ui.end_row();
+
+                                ui.label("Max relative error:");
+                                ui.label(format!("{:.3e}", comparison.max));
+                                ui.end_row();
+
+                                ui.label("Mean relative error:");
+                                ui.label(format!("{:.3e}", comparison.mean));
+                                ui.end_row();
+
+                                ui.label("RMS relative error:");
+                                ui.label(format!("{:.3e}", comparison.rms));
+                                ui.end_row();
+                            });
+                    }
+                });
+
+                ui.collapsing("Collisions", |ui| {
+                    self.collisions.inspect(ui, &InspectOptions::default());
+
+                    let live = self.simulation.num_particles();
+                    let removed = self.simulation.capacity - live;
+                    ui.label(format!("Particles: {live} ({removed} removed)"));
+                    let events = self.collisions.events();
+                    Grid::new("merger_log")
+                        .num_columns(4)
+                        .spacing([10.0, 4.0])
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.label("Time");
+                            ui.label("Into");
+                            ui.label("Removed");
+                            ui.label("Masses");
+                            ui.end_row();
+
+                            for event in events.iter().rev().take(MERGER_LOG_ROWS) {
+                                ui.label(format!("{:.3}", event.time));
+                                let sink = if event.sink { " (sink)" } else { "" };
+                                ui.label(format!("{}{sink}", event.receiver));
+                                ui.label(event.donor.to_string());
+                                ui.label(format!(
+                                    "{:.2e} + {:.2e}",
+                                    event.receiver_mass, event.donor_mass
+                                ));
+                                ui.end_row();
+                            }
+                        });
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Export CSV").clicked() {
+                            let path = "mergers.csv";
+                            self.state.merger_export =
+                                Some(match self.collisions.write_csv(path) {
+                                    Ok(()) => format!("Saved {path}"),
+                                    Err(err) => format!("Export failed: {err}"),
+                                });
+                        }
+                        if ui.button("Clear").clicked() {
+                            self.collisions.clear_events();
+                        }
                     });
+                    if let Some(status) = &self.state.merger_export {
+                        ui.label(status);
+                    }
+                });
 
                 ui.separator();
 
@@ -240,81 +685,409 @@ impl Engine for TardigradeEngine {
                     });
             });
 
-        if self.state.show_energy {
-            Window::new("Total Energy").show(context, |ctx| {
-                let total: PlotPoints = self
-                    .state
-                    .energy
-                    .iter()
-                    .enumerate()
-                    .map(|(i, &x)| [i as f64, x as f64])
-                    .collect();
-                let total = Line::new(total);
-                Plot::new(format!("total_energy"))
-                    .view_aspect(2.0)
-                    .show(ctx, |plot_ui| {
-                        plot_ui.line(total);
-                        if self.state.energy.len() > 0 {
-                            plot_ui.hline(
-                                HLine::new(self.state.energy.last().unwrap() * 1.01)
-                                    .color(Color32::WHITE),
-                            );
-                            plot_ui.hline(
-                                HLine::new(self.state.energy.last().unwrap() * 0.99)
-                                    .color(Color32::WHITE),
-                            );
+        if self.state.show_labels {
+            // Panels have been added at this point, so the available rect is the viewport
+            let rect = context.available_rect();
+            let view = self.camera.generate_view(rect.width() / rect.height());
+            let overlay = ViewportOverlay::new(context, view);
+
+            overlay.corner_text(
+                Align2::RIGHT_TOP,
+                format!("t = {:.3}", self.state.simulation_time),
+                Color32::WHITE,
+            );
+            overlay.scale_bar(Point3::new(0.0, 0.0, 0.0), "units", Color32::WHITE);
+
+            for (name, position) in &self.state.landmarks {
+                overlay.label(*position, name, Color32::LIGHT_BLUE);
+            }
+
+            if let (Some(index), Some(particle)) = (self.state.selected, &self.state.inspected) {
+                overlay.label(
+                    particle.position,
+                    format!("#{}", index),
+                    Color32::from_rgb(255, 230, 50),
+                );
+            }
+        }
+
+        if let (Some(index), Some(particle)) = (self.state.selected, self.state.inspected) {
+            let synchronized = self.integrator.synchronized_forces();
+            let mut open = true;
+            Window::new(format!("Particle {}", index))
+                .id(egui::Id::new("inspector"))
+                .open(&mut open)
+                .resizable(false)
+                .show(context, |ui| {
+                    Grid::new("inspector_grid")
+                        .num_columns(2)
+                        .spacing([10.0, 4.0])
+                        .striped(true)
+                        .show(ui, |ui| {
+                            let vector =
+                                |v: [f32; 3]| format!("{:.3e}, {:.3e}, {:.3e}", v[0], v[1], v[2]);
+
+                            ui.label("Position:");
+                            ui.label(vector(particle.position.into()));
+                            ui.end_row();
+                            ui.label("Velocity:");
+                            ui.label(vector(particle.velocity.into()));
+                            ui.end_row();
+                            ui.label("Acceleration:");
+                            if synchronized {
+                                ui.label(vector(particle.acceleration.into()));
+                            } else {
+                                ui.label("mid-step").on_hover_text(
+                                    "The forces of this integrator belong to positions in the \
+                                     middle of a step",
+                                );
+                            }
+                            ui.end_row();
+                            ui.label("Mass:");
+                            ui.label(format!("{:.3e}", particle.mass));
+                            ui.end_row();
+                            ui.label("Specific energy:");
+                            ui.label(format!("{:.3e}", particle.specific_energy()));
+                            ui.end_row();
+                        });
+
+                    let tracked = self.state.tracked.contains(&index);
+                    if ui
+                        .button(if tracked {
+                            "Remove trail"
+                        } else {
+                            "Show trail"
+                        })
+                        .clicked()
+                    {
+                        if tracked {
+                            self.state.tracked.retain(|&i| i != index);
+                        } else {
+                            self.state.tracked.push(index);
                         }
+                        self.trails.track(api.construction(), &self.state.tracked);
+                    }
+                });
+
+            if !open {
+                self.state.selected = None;
+                self.state.inspected = None;
+            }
+        }
+
+        if self.state.show_energy {
+            Window::new("Energy").show(context, |ui| {
+                let energy = self.energy.energy();
+                Grid::new("energy").show(ui, |ui| {
+                    ui.label("Kinetic:");
+                    ui.label(format!("{:.6e}", energy.kinetic));
+                    ui.end_row();
+                    ui.label("Potential:");
+                    ui.label(format!("{:.6e}", energy.potential));
+                    ui.end_row();
+                    ui.label("Total:");
+                    ui.label(format!("{:.6e}", energy.total()));
+                    ui.end_row();
+                });
+                ui.add(TimeSeriesPlot::new("energy", &self.state.energy).relative(true));
+            });
+        }
+
+        if self.state.show_conservation {
+            Window::new("Conservation").show(context, |ui| {
+                self.conservation.inspect(ui, &InspectOptions::default());
+
+                if let Some(last) = self.conservation.last() {
+                    let moments = &last.moments;
+                    let com = moments.center_of_mass;
+                    let velocity = moments.center_of_mass_velocity;
+                    Grid::new("conserved").show(ui, |ui| {
+                        ui.label("Momentum:");
+                        ui.label(format!("{:.3e}", moments.momentum.magnitude()));
+                        ui.end_row();
+                        ui.label("Angular momentum:");
+                        ui.label(format!("{:.3e}", moments.angular_momentum.magnitude()));
+                        ui.end_row();
+                        ui.label("Center of mass:");
+                        ui.label(format!("{:.3} {:.3} {:.3}", com.x, com.y, com.z));
+                        ui.end_row();
+                        ui.label("Center of mass velocity:");
+                        ui.label(format!(
+                            "{:.3e} {:.3e} {:.3e}",
+                            velocity.x, velocity.y, velocity.z
+                        ));
+                        ui.end_row();
+                        ui.label("Virial ratio:");
+                        ui.label(format!("{:.3}", last.virial_ratio()));
+                        ui.end_row();
                     });
+                }
+
+                for (name, drift, threshold) in self.conservation.warnings() {
+                    ui.colored_label(
+                        Color32::RED,
+                        format!("{name} drifted by {drift:.2e}, above {threshold:.0e}"),
+                    );
+                }
+
+                if ui.button("Restart").clicked() {
+                    self.conservation.restart();
+                    self.state.conservation.clear();
+                }
+
+                ui.add(
+                    TimeSeriesPlot::new("conservation", &self.state.conservation)
+                        .log_axes(false, true),
+                );
             });
         }
+
+        Window::new("Key Bindings")
+            .open(&mut self.state.show_help)
+            .resizable(false)
+            .show(context, |ui| {
+                ui.add(KeymapHelp::new(&self.actions));
+            });
+
+        #[cfg(feature = "scripting")]
+        Window::new("Console")
+            .open(&mut self.state.show_console)
+            .default_width(400.0)
+            .show(context, |ui| {
+                ScrollArea::vertical()
+                    .max_height(200.0)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for line in &self.scripting.log {
+                            ui.monospace(line);
+                        }
+                    });
+                ui.separator();
+
+                let input = ui.add(
+                    TextEdit::singleline(&mut self.state.console_input)
+                        .code_editor()
+                        .desired_width(f32::INFINITY),
+                );
+                let submitted = input.lost_focus() && ui.input().key_pressed(Key::Enter);
+                if submitted && !self.state.console_input.is_empty() {
+                    let source = std::mem::take(&mut self.state.console_input);
+                    self.scripting.log.push(format!("> {source}"));
+                    let _ = self.scripting.run(&source);
+                    if !self.scripting.take_particles().is_empty() {
+                        self.scripting
+                            .log
+                            .push("particles can only be added by the startup script".into());
+                    }
+                    input.request_focus();
+                }
+
+                ui.label(format!("{} scheduled actions", self.scripting.pending()));
+            });
     }
 
     fn on_winit_event(&mut self, event: &WindowEvent, api: &mut EngineApi) {
-        match event {
-            WindowEvent::KeyboardInput { input, .. } => self.on_keyboard_event(input),
-            WindowEvent::MouseInput { state, button, .. } => {
-                self.on_mouse_click_event(*state, *button)
+        if let WindowEvent::CursorMoved { position, .. } = event {
+            self.state.cursor = [position.x as f32, position.y as f32];
+        }
+
+        for action in self.actions.handle(event) {
+            match action.name {
+                "forward" => self.camera.forward(),
+                "backward" => self.camera.backward(),
+                "right" => self.camera.right(),
+                "left" => self.camera.left(),
+                "up" => self.camera.up(),
+                "down" => self.camera.down(),
+                "zoom_in" => self.camera.zoom(action.amount),
+                "zoom_out" => self.camera.zoom(-action.amount),
+                // Picking needs the view and viewport, so it is resolved in the next `render`
+                "pick" => self.state.pick_request = Some(self.state.cursor),
+                "help" => self.state.show_help = !self.state.show_help,
+                "quit" => api.exit(),
+                _ => (),
             }
-            WindowEvent::CursorMoved { position, .. } => self.on_cursor_moved_event(position),
-            WindowEvent::MouseWheel { delta, .. } => self.on_mouse_wheel_event(delta),
-            _ => {}
         }
     }
 }
 
 impl TardigradeEngine {
-    fn on_keyboard_event(&mut self, input: &KeyboardInput) {
-        if let Some(key_code) = input.virtual_keycode {
-            match key_code {
-                VirtualKeyCode::W => self.camera.forward(),
-                VirtualKeyCode::S => self.camera.backward(),
-                VirtualKeyCode::D => self.camera.right(),
-                VirtualKeyCode::A => self.camera.left(),
-                VirtualKeyCode::Q => self.camera.up(),
-                VirtualKeyCode::E => self.camera.down(),
-                _ => (),
+    /// Calculate the energy and the conserved quantities at the current time and add them to
+    /// the plots
+    fn measure(&mut self, api: &EngineApi) {
+        let context = api.construction();
+        let time = self.state.simulation_time;
+        let energy = self.energy.calculate(context, &mut self.integrator);
+        let drift = self.conservation.measure(context, time, energy);
+
+        let time = time as f64;
+        self.state.energy.push("Kinetic", time, energy.kinetic);
+        self.state.energy.push("Potential", time, energy.potential);
+        self.state.energy.push("Total", time, energy.total());
+        for (name, value) in QUANTITIES.into_iter().zip(drift.values()) {
+            self.state.conservation.push(name, time, value);
+        }
+    }
+
+    /// Check the accelerations at the current positions against the f64 reference of the solver
+    /// on the CPU, for a random sample of particles
+    fn compare_with_cpu(&mut self, api: &EngineApi) {
+        let context = api.construction();
+        // The last step may have left the forces of the middle of the step
+        self.integrator.calculate_forces(context);
+        let bodies: Vec<Body> = self
+            .simulation
+            .particles(context)
+            .iter()
+            .map(Body::from)
+            .collect();
+        let accelerations = self.simulation.accelerations(context);
+
+        let count = COMPARISON_SAMPLES.min(bodies.len());
+        let sample = index::sample(&mut thread_rng(), bodies.len(), count).into_vec();
+        let gravity = Gravity::new(self.integrator.g(), self.integrator.softening());
+        let reference = self
+            .integrator
+            .reference_solver()
+            .accelerations_of(&bodies, gravity, &sample);
+
+        let sampled: Vec<_> = sample.iter().map(|&i| accelerations[i]).collect();
+        self.state.comparison = Some(cpu::compare(&sampled, &reference));
+    }
+
+    /// Move the selection and the trails along with the particles, after the mergers compacted
+    /// the buffers
+    fn follow_mergers(&mut self, api: &EngineApi) {
+        let collisions = &self.collisions;
+        self.state.selected = self.state.selected.map(|index| collisions.remap(index));
+        for index in &mut self.state.tracked {
+            *index = collisions.remap(*index);
+        }
+        self.trails
+            .retarget(api.construction(), &self.state.tracked);
+    }
+
+    #[cfg(feature = "scripting")]
+    fn apply_script_commands(&mut self, api: &EngineApi) {
+        for command in self.scripting.take_commands() {
+            match command {
+                Command::Pause => self.state.active = false,
+                Command::Resume => self.state.active = true,
+                Command::Capture(path) => {
+                    if let Err(err) = self.simulation.write_snapshot(api.construction(), &path) {
+                        self.scripting
+                            .log
+                            .push(format!("failed to write {path}: {err}"));
+                    }
+                }
+                Command::SetDt(dt) => self.integrator.set_dt(dt),
+                Command::SetG(g) => self.integrator.set_g(g),
+                Command::SetSoftening(softening) => self.integrator.set_softening(softening),
             }
         }
     }
+}
 
-    fn on_mouse_click_event(&mut self, state: ElementState, button: MouseButton) {}
+/// Galaxy used when no script builds the scene, returns its particles with the central black
+/// hole last and its center
+fn default_galaxy(
+    num_particles: u32,
+    center: Point3<f32>,
+    rng: &mut impl Rng,
+) -> (Vec<Particle>, Point3<f32>) {
+    let galaxy = Galaxy::new(
+        1000.0,
+        1.0,
+        Plummer::new(1.0, 0.1),
+        // Uniform::new(0.1, 3.0),
+        center,
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+    );
 
-    fn on_cursor_moved_event(&mut self, position: &PhysicalPosition<f64>) {}
+    (galaxy.get_particles(num_particles, rng), center)
+}
 
-    fn on_mouse_wheel_event(&mut self, delta: &MouseScrollDelta) {
-        let change = match delta {
-            MouseScrollDelta::LineDelta(_x, y) => *y,
-            MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
-        };
+/// Simulate the default galaxy on the CPU without opening a window, for machines without Vulkan.
+/// Prints the energy as csv every few steps, until `--steps=<n>` steps are done. The solver is
+/// picked with `--solver=` and one of `HEADLESS_SOLVERS`, Barnes-Hut by default. The particle mesh
+/// and TreePM are periodic, so the galaxy is centred in their box.
+fn run_headless() {
+    let steps = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--steps=")?.parse().ok())
+        .unwrap_or(u64::MAX);
+    let solver = std::env::args().find_map(|arg| arg.strip_prefix("--solver=").map(String::from));
+    let solver = match solver.as_deref() {
+        None | Some("barnes-hut") => CpuSolver::BarnesHut { theta: 0.5 },
+        Some("direct") => CpuSolver::Direct,
+        Some("fmm") => CpuSolver::Fmm {
+            order: 4,
+            theta: 0.5,
+        },
+        Some("pm") => CpuSolver::ParticleMesh {
+            resolution: 64,
+            box_size: 16.0,
+        },
+        Some("treepm") => CpuSolver::TreePm {
+            resolution: 64,
+            box_size: 16.0,
+            split: 1.25 * 16.0 / 64.0,
+            theta: 0.5,
+        },
+        Some(name) => {
+            eprintln!("unknown solver `{name}`, expected one of {HEADLESS_SOLVERS}");
+            std::process::exit(2);
+        }
+    };
 
-        self.camera.zoom(change);
+    let center = match solver.box_size() {
+        Some(box_size) => {
+            let half = box_size as f32 / 2.0;
+            Point3::new(half, half, half)
+        }
+        None => Point3::new(0.0, 4.0, 4.0),
+    };
+    let (particles, _) = default_galaxy(HEADLESS_PARTICLES, center, &mut thread_rng());
+    let mut simulation = CpuSimulation::new(
+        &particles,
+        Gravity::new(GRAVITATIONAL_CONSTANT, SOFTENING),
+        solver,
+        0.001,
+    );
+
+    let (kinetic, potential) = simulation.energy();
+    let initial = kinetic + potential;
+
+    println!("step,time,kinetic,potential,relative_error");
+    while simulation.steps < steps {
+        for _ in 0..HEADLESS_REPORT_INTERVAL.min(steps - simulation.steps) {
+            simulation.step();
+        }
+
+        let (kinetic, potential) = simulation.energy();
+        let error = (kinetic + potential - initial) / initial.abs();
+        println!(
+            "{},{:e},{:e},{:e},{:e}",
+            simulation.steps, simulation.time, kinetic, potential, error
+        );
     }
 }
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--cpu") {
+        run_headless();
+        return;
+    }
+
     let options = EngineOptions {
         window_options: WindowOptions::default(),
+        app_id: Some("newtonian_nbody"),
         features: Features::empty(),
+        // For the f64 precision mode of the integrator
+        optional_features: Features {
+            shader_float64: true,
+            ..Features::empty()
+        },
         ..EngineOptions::default()
     };
 
@@ -0,0 +1,69 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer PositionMass { vec4 data[]; } pos_mass;
+layout(set = 0, binding = 1) buffer Velocity { vec4 data[]; } vel;
+layout(set = 0, binding = 2) buffer Acceleration { vec4 data[]; } acc;
+layout(set = 0, binding = 3) buffer Attributes { vec4 data[]; } attributes;
+// Smallest and largest value as the bits of the floats, which order like the floats as all
+// quantities are positive. Reset by the CPU before every dispatch.
+layout(set = 0, binding = 4) buffer Range { uint min; uint max; } range;
+
+#define QUANTITY_SPEED 0
+#define QUANTITY_MASS 1
+#define QUANTITY_ACCELERATION 2
+
+layout(push_constant) uniform AttributeData {
+    uint buffer_size;
+    uint quantity;
+    // Leave zeros out of the range, for log normalization
+    uint positive_only;
+} ad;
+
+#define NO_MIN 0x7f800000u
+#define NO_MAX 0u
+
+shared uint _min[PARALLELISM];
+shared uint _max[PARALLELISM];
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+    uint li = gl_LocalInvocationID.x;
+
+    // Every invocation has to take part in the reduction, even past the end of the buffer
+    _min[li] = NO_MIN;
+    _max[li] = NO_MAX;
+
+    if (gi < ad.buffer_size) {
+        float value;
+        switch (ad.quantity) {
+            case QUANTITY_SPEED: value = length(vel.data[gi].xyz); break;
+            case QUANTITY_MASS: value = pos_mass.data[gi].w; break;
+            default: value = length(acc.data[gi].xyz); break;
+        }
+
+        attributes.data[gi] = vec4(value, 0.0, 0.0, 0.0);
+
+        if (!isinf(value) && !isnan(value) && (ad.positive_only == 0 || value > 0.0)) {
+            _min[li] = floatBitsToUint(value);
+            _max[li] = floatBitsToUint(value);
+        }
+    }
+    barrier();
+
+    for (uint stride = PARALLELISM / 2; stride > 0; stride /= 2) {
+        if (li < stride) {
+            _min[li] = min(_min[li], _min[li + stride]);
+            _max[li] = max(_max[li], _max[li + stride]);
+        }
+        barrier();
+    }
+
+    if (li == 0) {
+        atomicMin(range.min, _min[0]);
+        atomicMax(range.max, _max[0]);
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use hatchery::{
+    inspect::Inspect,
+    util::{
+        buffer::{AbstractBuffer, SharedBuffer},
+        compute::ComputeShader,
+        point_cloud::PointAttribute,
+        ConstructionContext,
+    },
+};
+use serde::{Deserialize, Serialize};
+use vulkano::{
+    buffer::BufferUsage, descriptor_set::WriteDescriptorSet, device::Device, shader::ShaderModule,
+};
+
+use super::SimulationBuffers;
+
+hatchery::compute! { "src/physics/attributes.glsl", attributes }
+
+/// Per-particle quantities which can be used to color the particles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Inspect)]
+pub enum Quantity {
+    #[default]
+    Speed,
+    Mass,
+    Acceleration,
+}
+
+impl Quantity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantity::Speed => "Speed",
+            Quantity::Mass => "Mass",
+            Quantity::Acceleration => "Acceleration",
+        }
+    }
+}
+
+/// `Range` of `attributes.glsl` before the reduction, the bits of infinity and zero
+const EMPTY_RANGE: [u32; 2] = [0x7f80_0000, 0];
+
+/// Fills a buffer of `PointAttribute`s with a scalar quantity for every particle, and reduces
+/// it to its range on the GPU so the values don't have to be read back
+pub struct AttributeCalculator {
+    data: Arc<SimulationBuffers>,
+    attributes: SharedBuffer<PointAttribute>,
+    range: SharedBuffer<u32>,
+    pub quantity: Quantity,
+    /// Only include positive values in the range, for log normalization
+    pub positive_only: bool,
+}
+
+impl AttributeCalculator {
+    pub fn new(data: Arc<SimulationBuffers>, context: &ConstructionContext) -> Self {
+        let num_particles = data.capacity;
+        Self {
+            data,
+            attributes: SharedBuffer::from_iter(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    vertex_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                vec![PointAttribute::default(); num_particles as usize],
+            ),
+            range: SharedBuffer::from_iter(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                EMPTY_RANGE,
+            ),
+            quantity: Quantity::Speed,
+            positive_only: false,
+        }
+    }
+
+    /// Empty the range before the next dispatch, which only widens it
+    pub fn reset_range(&self) {
+        self.range
+            .typed_buffer()
+            .write()
+            .unwrap()
+            .copy_from_slice(&EMPTY_RANGE);
+    }
+
+    pub fn attributes(&self) -> &SharedBuffer<PointAttribute> {
+        &self.attributes
+    }
+
+    /// Smallest and largest of the last computed values, if there were any
+    pub fn range(&self) -> Option<[f32; 2]> {
+        let range = self.range.typed_buffer().read().unwrap();
+        let [min, max] = [range[0], range[1]].map(f32::from_bits);
+        (min <= max).then_some([min, max])
+    }
+}
+
+impl ComputeShader for AttributeCalculator {
+    type Constants = attributes::ty::AttributeData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(Self::Constants {
+            buffer_size: self.data.num_particles(),
+            quantity: match self.quantity {
+                Quantity::Speed => 0,
+                Quantity::Mass => 1,
+                Quantity::Acceleration => 2,
+            },
+            positive_only: self.positive_only as u32,
+        })
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        attributes::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        [self.data.num_particles().div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.velocity.buffer()),
+            WriteDescriptorSet::buffer(2, self.data.acceleration.buffer()),
+            WriteDescriptorSet::buffer(3, self.attributes.buffer()),
+            WriteDescriptorSet::buffer(4, self.range.buffer()),
+        ]
+    }
+}
@@ -0,0 +1,381 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+// The tree is a binary radix tree over the sorted Morton keys of the particles (Karras 2012).
+// Every node covers a range of keys sharing a prefix, the nodes at prefixes of a multiple of 3
+// bits are exactly the cells of the octree. Internal nodes are stored at 0..N-1, the leaf of the
+// i-th sorted particle at N-1+i, so the root is always node 0.
+
+layout(set = 0, binding = 0) buffer PositionMass { vec4 data[]; } pos_mass;
+layout(set = 0, binding = 1) buffer Acceleration { vec4 data[]; } acc;
+layout(set = 0, binding = 2) buffer Keys { uint data[]; } keys;
+layout(set = 0, binding = 3) buffer Indices { uint data[]; } indices;
+layout(set = 0, binding = 4) buffer Children { uvec2 data[]; } children;
+layout(set = 0, binding = 5) buffer Parents { uint data[]; } parents;
+layout(set = 0, binding = 6) coherent buffer Visits { uint data[]; } visits;
+// Center of mass and total mass of every node
+layout(set = 0, binding = 7) coherent buffer NodeMass { vec4 data[]; } node_mass;
+layout(set = 0, binding = 8) coherent buffer NodeMin { vec4 data[]; } node_min;
+layout(set = 0, binding = 9) coherent buffer NodeMax { vec4 data[]; } node_max;
+layout(set = 0, binding = 10) buffer Bounds {
+    uint bounds_min[3];
+    uint bounds_max[3];
+} bounds;
+// Potential per unit mass, only written by the potential pass
+layout(set = 0, binding = 11) buffer Potential { float data[]; } potential;
+
+#define PASS_CLEAR 0
+#define PASS_BOUNDS 1
+#define PASS_MORTON 2
+#define PASS_SORT 3
+#define PASS_BUILD 4
+#define PASS_SUMMARIZE 5
+#define PASS_FORCES 6
+#define PASS_POTENTIAL 7
+
+#define NO_PARENT 0xffffffffu
+#define STACK_SIZE 64
+
+#define PI 3.14159265358979
+// Short range forces of TreePM are neglected beyond this many split radii
+#define CUTOFF 4.5
+
+layout(push_constant) uniform TreeData {
+    uint num_particles;
+    // Number of sorted keys, the next power of two
+    uint num_keys;
+    uint pass;
+    // Bitonic sort stage
+    uint block;
+    uint stride;
+    float theta;
+    float G;
+    float softening;
+    // Split radius of TreePM, if not 0 only the short range force is added to the accelerations,
+    // between the nearest periodic images in a box of `box_size`
+    float split;
+    float box_size;
+} td;
+
+// Maps floats onto uints with the same ordering, so bounds can be found with integer atomics
+uint order_preserving(float value) {
+    uint bits = floatBitsToUint(value);
+    return (bits & 0x80000000u) != 0 ? ~bits : bits | 0x80000000u;
+}
+
+float from_order_preserving(uint bits) {
+    return uintBitsToFloat((bits & 0x80000000u) != 0 ? bits & 0x7fffffffu : ~bits);
+}
+
+// Spread the lower 10 bits of `v` so there are two zeros between each of them
+uint expand_bits(uint v) {
+    v = (v * 0x00010001u) & 0xFF0000FFu;
+    v = (v * 0x00000101u) & 0x0F00F00Fu;
+    v = (v * 0x00000011u) & 0xC30C30C3u;
+    v = (v * 0x00000005u) & 0x49249249u;
+    return v;
+}
+
+// 30 bit Morton key of a point in the bounding cube of all particles
+uint morton(vec3 p) {
+    vec3 box_min, box_max;
+    for (int i = 0; i < 3; i++) {
+        box_min[i] = from_order_preserving(bounds.bounds_min[i]);
+        box_max[i] = from_order_preserving(bounds.bounds_max[i]);
+    }
+
+    // A cube keeps the cells of the tree cubic
+    float size = max(max(box_max.x - box_min.x, box_max.y - box_min.y), box_max.z - box_min.z);
+    vec3 q = clamp((p - box_min) / max(size, 1e-20) * 1024.0, vec3(0.0), vec3(1023.0));
+    uvec3 c = uvec3(q);
+    return (expand_bits(c.x) << 2) | (expand_bits(c.y) << 1) | expand_bits(c.z);
+}
+
+uint leading_zeros(uint v) {
+    return uint(31 - findMSB(v));
+}
+
+// Length of the common prefix of the keys at sorted positions `i` and `j`, duplicate keys are
+// told apart by their position so every key is unique
+int delta(int i, int j) {
+    if (j < 0 || j >= int(td.num_particles)) {
+        return -1;
+    }
+
+    uint ki = keys.data[i];
+    uint kj = keys.data[j];
+    if (ki == kj) {
+        return 32 + int(leading_zeros(uint(i ^ j)));
+    }
+    return int(leading_zeros(ki ^ kj));
+}
+
+uint leaf(uint i) {
+    return td.num_particles - 1 + i;
+}
+
+bool is_leaf(uint node) {
+    return node >= td.num_particles - 1;
+}
+
+void build(int i) {
+    // Direction of the range covered by the node
+    int d = delta(i, i + 1) - delta(i, i - 1) > 0 ? 1 : -1;
+
+    // Upper bound of the length of the range
+    int delta_min = delta(i, i - d);
+    int l_max = 2;
+    while (delta(i, i + l_max * d) > delta_min) {
+        l_max *= 2;
+    }
+
+    // Find the other end of the range with a binary search
+    int l = 0;
+    for (int t = l_max / 2; t >= 1; t /= 2) {
+        if (delta(i, i + (l + t) * d) > delta_min) {
+            l += t;
+        }
+    }
+    int j = i + l * d;
+
+    // Find where the range splits
+    int delta_node = delta(i, j);
+    int s = 0;
+    int t = l;
+    do {
+        t = (t + 1) / 2;
+        if (delta(i, i + (s + t) * d) > delta_node) {
+            s += t;
+        }
+    } while (t > 1);
+    int split = i + s * d + min(d, 0);
+
+    uint left = min(i, j) == split ? leaf(uint(split)) : uint(split);
+    uint right = max(i, j) == split + 1 ? leaf(uint(split + 1)) : uint(split + 1);
+
+    children.data[i] = uvec2(left, right);
+    parents.data[left] = uint(i);
+    parents.data[right] = uint(i);
+}
+
+// Walk up from a leaf, the second child to arrive at a node combines both children
+void summarize(uint i) {
+    vec4 particle = pos_mass.data[indices.data[i]];
+    uint node = leaf(i);
+    node_mass.data[node] = particle;
+    node_min.data[node] = vec4(particle.xyz, 0.0);
+    node_max.data[node] = vec4(particle.xyz, 0.0);
+
+    while (node != 0) {
+        node = parents.data[node];
+
+        memoryBarrierBuffer();
+        if (atomicAdd(visits.data[node], 1u) == 0u) {
+            return;
+        }
+
+        uvec2 c = children.data[node];
+        vec4 a = node_mass.data[c.x];
+        vec4 b = node_mass.data[c.y];
+        float mass = a.w + b.w;
+        vec3 center = mass > 0.0 ? (a.xyz * a.w + b.xyz * b.w) / mass : 0.5 * (a.xyz + b.xyz);
+
+        node_mass.data[node] = vec4(center, mass);
+        node_min.data[node] = min(node_min.data[c.x], node_min.data[c.y]);
+        node_max.data[node] = max(node_max.data[c.x], node_max.data[c.y]);
+    }
+}
+
+// Complementary error function, Abramowitz and Stegun 7.1.26
+float erfc_approx(float x) {
+    float t = 1.0 / (1.0 + 0.3275911 * x);
+    float poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741
+        + t * (-1.453152027 + t * 1.061405429))));
+    return poly * exp(-x * x);
+}
+
+// Nearest periodic image of a separation
+vec3 separation(vec3 diff) {
+    return td.split > 0.0 ? diff - td.box_size * round(diff / td.box_size) : diff;
+}
+
+// Fraction of the force which is short range under the Gaussian split
+float short_range(float r) {
+    if (td.split <= 0.0) {
+        return 1.0;
+    }
+    float x = r / (2.0 * td.split);
+    return erfc_approx(x) + 2.0 * x / sqrt(PI) * exp(-x * x);
+}
+
+vec3 calculate_accel(vec3 target, vec3 position) {
+    vec3 diff = separation(target - position);
+    float dist2 = dot(diff, diff) + (td.softening * td.softening);
+    return td.G * short_range(length(diff)) * diff / pow(dist2, 1.5);
+}
+
+// Depth first tree walk, nodes which are small compared to their distance are treated as a
+// single point mass at their center of mass
+void forces(uint i) {
+    uint index = indices.data[i];
+    vec3 p = pos_mass.data[index].xyz;
+    uint own = leaf(i);
+
+    uint stack[STACK_SIZE];
+    uint top = 0;
+    stack[top++] = 0;
+
+    vec3 a = vec3(0.0);
+    while (top > 0) {
+        uint node = stack[--top];
+        if (node == own) {
+            continue;
+        }
+
+        vec4 mass = node_mass.data[node];
+        if (is_leaf(node)) {
+            a += mass.w * calculate_accel(mass.xyz, p);
+            continue;
+        }
+
+        vec3 box_min = node_min.data[node].xyz;
+        vec3 box_max = node_max.data[node].xyz;
+        vec3 extent = box_max - box_min;
+        float size = max(max(extent.x, extent.y), extent.z);
+        vec3 diff = separation(mass.xyz - p);
+
+        // Distance from the particle to the bounding box of the node
+        vec3 gap = max(abs(separation(0.5 * (box_min + box_max) - p)) - 0.5 * extent, 0.0);
+        if (td.split > 0.0 && length(gap) > CUTOFF * td.split) {
+            continue;
+        }
+
+        // Nodes containing the particle are always opened, however far their center of mass is
+        bool inside = all(equal(gap, vec3(0.0)));
+        bool far = size * size < td.theta * td.theta * dot(diff, diff);
+
+        if ((far && !inside) || top + 2 > STACK_SIZE) {
+            a += mass.w * calculate_accel(mass.xyz, p);
+        } else {
+            uvec2 c = children.data[node];
+            stack[top++] = c.x;
+            stack[top++] = c.y;
+        }
+    }
+
+    // The long range part of TreePM is already there
+    acc.data[index].xyz = td.split > 0.0 ? acc.data[index].xyz + a : a;
+}
+
+// The same walk as `forces`, summing the softened potential of the particles and cells instead
+void potentials(uint i) {
+    uint index = indices.data[i];
+    vec3 p = pos_mass.data[index].xyz;
+    uint own = leaf(i);
+
+    uint stack[STACK_SIZE];
+    uint top = 0;
+    stack[top++] = 0;
+
+    float phi = 0.0;
+    while (top > 0) {
+        uint node = stack[--top];
+        if (node == own) {
+            continue;
+        }
+
+        vec4 mass = node_mass.data[node];
+        vec3 diff = mass.xyz - p;
+        float dist2 = dot(diff, diff) + (td.softening * td.softening);
+        if (is_leaf(node)) {
+            phi -= td.G * mass.w / sqrt(dist2);
+            continue;
+        }
+
+        vec3 box_min = node_min.data[node].xyz;
+        vec3 box_max = node_max.data[node].xyz;
+        vec3 extent = box_max - box_min;
+        float size = max(max(extent.x, extent.y), extent.z);
+
+        vec3 gap = max(abs(0.5 * (box_min + box_max) - p) - 0.5 * extent, 0.0);
+        bool inside = all(equal(gap, vec3(0.0)));
+        bool far = size * size < td.theta * td.theta * dot(diff, diff);
+
+        if ((far && !inside) || top + 2 > STACK_SIZE) {
+            phi -= td.G * mass.w / sqrt(dist2);
+        } else {
+            uvec2 c = children.data[node];
+            stack[top++] = c.x;
+            stack[top++] = c.y;
+        }
+    }
+
+    potential.data[index] = phi;
+}
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+    uint n = td.num_particles;
+
+    if (td.pass == PASS_CLEAR) {
+        if (gi == 0) {
+            for (int i = 0; i < 3; i++) {
+                bounds.bounds_min[i] = 0xffffffffu;
+                bounds.bounds_max[i] = 0u;
+            }
+            parents.data[0] = NO_PARENT;
+        }
+        if (gi + 1 < n) {
+            visits.data[gi] = 0u;
+        }
+    } else if (td.pass == PASS_BOUNDS) {
+        if (gi < n) {
+            vec4 particle = pos_mass.data[gi];
+            for (int i = 0; i < 3; i++) {
+                atomicMin(bounds.bounds_min[i], order_preserving(particle[i]));
+                atomicMax(bounds.bounds_max[i], order_preserving(particle[i]));
+            }
+        }
+    } else if (td.pass == PASS_MORTON) {
+        if (gi < td.num_keys) {
+            // Padding sorts to the end
+            keys.data[gi] = gi < n ? morton(pos_mass.data[gi].xyz) : 0xffffffffu;
+            indices.data[gi] = gi;
+        }
+    } else if (td.pass == PASS_SORT) {
+        // One compare and swap stage of a bitonic sort by key, then index
+        uint other = gi ^ td.stride;
+        if (gi < td.num_keys && other > gi) {
+            uvec2 a = uvec2(keys.data[gi], indices.data[gi]);
+            uvec2 b = uvec2(keys.data[other], indices.data[other]);
+            bool greater = a.x > b.x || (a.x == b.x && a.y > b.y);
+            bool ascending = (gi & td.block) == 0;
+            if (greater == ascending) {
+                keys.data[gi] = b.x;
+                indices.data[gi] = b.y;
+                keys.data[other] = a.x;
+                indices.data[other] = a.y;
+            }
+        }
+    } else if (td.pass == PASS_BUILD) {
+        if (gi + 1 < n) {
+            build(int(gi));
+        }
+    } else if (td.pass == PASS_SUMMARIZE) {
+        if (gi < n) {
+            summarize(gi);
+        }
+    } else if (td.pass == PASS_FORCES) {
+        // Neighbouring invocations walk similar paths, since particles are in Morton order
+        if (gi < n) {
+            forces(gi);
+        }
+    } else if (td.pass == PASS_POTENTIAL) {
+        if (gi < n) {
+            potentials(gi);
+        }
+    }
+}
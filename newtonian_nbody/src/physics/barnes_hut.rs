@@ -0,0 +1,234 @@
+use std::sync::Arc;
+
+use hatchery::{
+    inspect::Inspect,
+    util::{
+        buffer::{AbstractBuffer, DeviceBuffer},
+        compute::{ComputeShader, ComputeShaderExecutor},
+        ConstructionContext,
+    },
+};
+use vulkano::{
+    buffer::BufferUsage,
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::WriteDescriptorSet,
+    device::Device,
+    shader::ShaderModule,
+};
+
+use super::{ForceCalculator, SimulationBuffers};
+
+hatchery::compute! { "src/physics/barnes_hut.glsl", barnes_hut }
+
+// Passes of `barnes_hut.glsl`
+const PASS_CLEAR: u32 = 0;
+const PASS_BOUNDS: u32 = 1;
+const PASS_MORTON: u32 = 2;
+const PASS_SORT: u32 = 3;
+const PASS_BUILD: u32 = 4;
+const PASS_SUMMARIZE: u32 = 5;
+const PASS_FORCES: u32 = 6;
+const PASS_POTENTIAL: u32 = 7;
+
+struct TreeShader {
+    data: Arc<SimulationBuffers>,
+    keys: DeviceBuffer<u32>,
+    indices: DeviceBuffer<u32>,
+    children: DeviceBuffer<[u32; 2]>,
+    parents: DeviceBuffer<u32>,
+    visits: DeviceBuffer<u32>,
+    node_mass: DeviceBuffer<[f32; 4]>,
+    node_min: DeviceBuffer<[f32; 4]>,
+    node_max: DeviceBuffer<[f32; 4]>,
+    bounds: DeviceBuffer<u32>,
+    constants: barnes_hut::ty::TreeData,
+}
+
+impl ComputeShader for TreeShader {
+    type Constants = barnes_hut::ty::TreeData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        barnes_hut::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        let threads = match self.constants.pass {
+            PASS_MORTON | PASS_SORT => self.constants.num_keys,
+            _ => self.constants.num_particles,
+        };
+        [threads.div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.acceleration.buffer()),
+            WriteDescriptorSet::buffer(2, self.keys.buffer()),
+            WriteDescriptorSet::buffer(3, self.indices.buffer()),
+            WriteDescriptorSet::buffer(4, self.children.buffer()),
+            WriteDescriptorSet::buffer(5, self.parents.buffer()),
+            WriteDescriptorSet::buffer(6, self.visits.buffer()),
+            WriteDescriptorSet::buffer(7, self.node_mass.buffer()),
+            WriteDescriptorSet::buffer(8, self.node_min.buffer()),
+            WriteDescriptorSet::buffer(9, self.node_max.buffer()),
+            WriteDescriptorSet::buffer(10, self.bounds.buffer()),
+            WriteDescriptorSet::buffer(11, self.data.potential.buffer()),
+        ]
+    }
+}
+
+/// O(N log N) tree code, rebuilt from scratch every step. Particles are sorted along a Morton
+/// curve and a radix tree is built over the sorted keys, which groups them into the cells of an
+/// octree. Each cell stores its center of mass and bounding box, and is treated as a single mass
+/// if it is smaller than `theta` times its distance.
+#[derive(Inspect)]
+pub struct BarnesHut {
+    #[inspect(skip)]
+    tree: ComputeShaderExecutor<TreeShader>,
+    /// Opening angle, 0 opens every cell and gives the exact direct sum
+    #[inspect(label = "Opening angle", range = 0.0..=1.5, speed = 0.01)]
+    theta: f32,
+}
+
+impl BarnesHut {
+    pub fn new(context: &ConstructionContext, data: Arc<SimulationBuffers>, theta: f32) -> Self {
+        let num_particles = data.capacity;
+        // The bitonic sort works on a power of two
+        let num_keys = num_particles.next_power_of_two();
+        let num_nodes = 2 * num_particles as u64 - 1;
+
+        let storage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        };
+        let tree = TreeShader {
+            data,
+            keys: DeviceBuffer::new(context, storage, num_keys as u64),
+            indices: DeviceBuffer::new(context, storage, num_keys as u64),
+            // A single particle has no internal nodes, but buffers can't be empty
+            children: DeviceBuffer::new(context, storage, (num_particles as u64 - 1).max(1)),
+            parents: DeviceBuffer::new(context, storage, num_nodes),
+            visits: DeviceBuffer::new(context, storage, (num_particles as u64 - 1).max(1)),
+            node_mass: DeviceBuffer::new(context, storage, num_nodes),
+            node_min: DeviceBuffer::new(context, storage, num_nodes),
+            node_max: DeviceBuffer::new(context, storage, num_nodes),
+            bounds: DeviceBuffer::new(context, storage, 6),
+            constants: barnes_hut::ty::TreeData {
+                num_particles,
+                num_keys,
+                pass: PASS_CLEAR,
+                block: 0,
+                stride: 0,
+                theta,
+                G: 0.0,
+                softening: 0.0,
+                split: 0.0,
+                box_size: 0.0,
+            },
+        };
+
+        Self {
+            tree: ComputeShaderExecutor::new(context, tree),
+            theta,
+        }
+    }
+
+    pub fn theta(&self) -> f32 {
+        self.theta
+    }
+
+    pub fn set_theta(&mut self, theta: f32) {
+        self.theta = theta;
+    }
+
+    /// Add only the short range part of the TreePM force split to the accelerations, between the
+    /// nearest periodic images. Cells further than a few split radii are skipped entirely.
+    pub fn record_short_range(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        softening: f32,
+        split: f32,
+        box_size: f32,
+    ) {
+        self.tree.constants.split = split;
+        self.tree.constants.box_size = box_size;
+        self.record_tree(builder, g, softening, PASS_FORCES);
+    }
+
+    /// Build the tree at the current positions and walk it for the potential of every particle
+    /// into `SimulationBuffers::potential`, leaving the accelerations alone. Always the full
+    /// potential without periodic images, also when the tree is used by TreePM.
+    pub fn record_potential(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        softening: f32,
+    ) {
+        self.tree.constants.split = 0.0;
+        self.record_tree(builder, g, softening, PASS_POTENTIAL);
+    }
+
+    /// Build the tree and finish with `walk`, which is either the forces or the potential pass
+    fn record_tree(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        softening: f32,
+        walk: u32,
+    ) {
+        let num_particles = self.tree.data.num_particles();
+        let constants = &mut self.tree.constants;
+        constants.num_particles = num_particles;
+        constants.theta = self.theta;
+        constants.G = g;
+        constants.softening = softening;
+
+        for pass in [PASS_CLEAR, PASS_BOUNDS, PASS_MORTON] {
+            self.record_pass(builder, pass);
+        }
+
+        // Bitonic sort, every stage is its own dispatch
+        let num_keys = self.tree.constants.num_keys;
+        let mut block = 2;
+        while block <= num_keys {
+            let mut stride = block / 2;
+            while stride > 0 {
+                self.tree.constants.block = block;
+                self.tree.constants.stride = stride;
+                self.record_pass(builder, PASS_SORT);
+                stride /= 2;
+            }
+            block *= 2;
+        }
+
+        for pass in [PASS_BUILD, PASS_SUMMARIZE, walk] {
+            self.record_pass(builder, pass);
+        }
+    }
+
+    fn record_pass(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pass: u32,
+    ) {
+        self.tree.constants.pass = pass;
+        self.tree.record(builder);
+    }
+}
+
+impl ForceCalculator for BarnesHut {
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        softening: f32,
+    ) {
+        self.tree.constants.split = 0.0;
+        self.record_tree(builder, g, softening, PASS_FORCES);
+    }
+}
@@ -0,0 +1,170 @@
+#version 450
+
+#define PARALLELISM 128
+#define MAX_LEVELS 16
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+#include "precision.glsl"
+
+layout(set = 0, binding = 0) buffer Points { vec4 data[]; } points;
+layout(set = 0, binding = 1) buffer PositionMass { vec4 data[]; } pos_mass;
+layout(set = 0, binding = 2) buffer Velocity { vec4 data[]; } vel;
+layout(set = 0, binding = 3) buffer Acceleration { vec4 data[]; } acc;
+// Timestep level of every particle, its step is `dt / 2^level`
+layout(set = 0, binding = 4) buffer Levels { uint data[]; } levels;
+// Acceleration at the start of the particle's step, for the finite difference of the jerk
+layout(set = 0, binding = 5) buffer OpeningAcceleration { vec4 data[]; } opening_acc;
+layout(set = 0, binding = 6) buffer ActiveIndices { uint data[]; } active_set;
+layout(set = 0, binding = 7) buffer ActiveCount { uint value; } active_count;
+layout(set = 0, binding = 8) buffer Dispatch { uint x; uint y; uint z; } dispatch;
+layout(set = 0, binding = 9) buffer Statistics {
+    // Deepest level any particle asked for, the next step is subdivided down to it
+    uint deepest;
+    // Number of particles kicked at the end of their step
+    uint updates;
+    uint histogram[MAX_LEVELS];
+} stats;
+layout(set = 0, binding = 10) buffer PositionLow { vec4 data[]; } pos_low;
+
+#define PASS_RESET 0
+#define PASS_OPEN 1
+#define PASS_DRIFT 2
+#define PASS_COMPACT_CLEAR 3
+#define PASS_COMPACT 4
+#define PASS_COMPACT_DISPATCH 5
+#define PASS_CLOSE 6
+#define PASS_CLOSE_OPEN 7
+#define PASS_HISTOGRAM 8
+
+#define CRITERION_ACCELERATION 0
+#define CRITERION_JERK 1
+
+layout(push_constant) uniform BlockData {
+    uint buffer_size;
+    uint pass;
+    // Substep of the largest step, in units of the step of the deepest level
+    uint substep;
+    // Deepest level of the current step
+    uint depth;
+    // Deepest level a particle may ask for
+    uint max_level;
+    uint criterion;
+    // Largest step, of level 0
+    float dt;
+    float eta;
+    float softening;
+    // Positions wrap around a periodic box spanning [0, box_size), unless it is 0
+    float box_size;
+    // One of the PRECISION_ modes for the positions
+    uint precision_mode;
+} bd;
+
+float level_dt(uint level) {
+    return bd.dt / float(1u << level);
+}
+
+// Level whose step is the largest power of two fraction of `dt` below the criterion, either
+// sqrt(2 eta softening / |a|) as in GADGET or eta |a| / |j| with the jerk from the change of the
+// acceleration over the last step
+uint requested_level(uint i, uint level) {
+    vec3 a = acc.data[i].xyz;
+    float a_mag = length(a);
+    float step = sqrt(2.0 * bd.eta * bd.softening / max(a_mag, 1e-30));
+
+    if (bd.criterion == CRITERION_JERK) {
+        vec3 jerk = (a - opening_acc.data[i].xyz) / level_dt(level);
+        float j_mag = length(jerk);
+        if (j_mag > 0.0) {
+            step = bd.eta * a_mag / j_mag;
+        }
+    }
+
+    float level_f = ceil(log2(bd.dt / step));
+    return uint(clamp(level_f, 0.0, float(bd.max_level)));
+}
+
+// Particles can only move to a larger step at a time which is synchronized with it
+uint allowed_level(uint requested) {
+    uint aligned = bd.substep == 0 ? 0 : bd.depth - min(findLSB(bd.substep), bd.depth);
+    return clamp(requested, aligned, bd.depth);
+}
+
+bool is_active(uint i) {
+    uint period = 1u << (bd.depth - min(levels.data[i], bd.depth));
+    return bd.substep % period == 0;
+}
+
+void kick(uint i, uint level) {
+    vel.data[i].xyz += acc.data[i].xyz * level_dt(level) / 2.0;
+}
+
+void open_step(uint i, uint level) {
+    levels.data[i] = level;
+    opening_acc.data[i] = acc.data[i];
+    kick(i, level);
+}
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+
+    if (bd.pass == PASS_RESET) {
+        if (gi == 0) {
+            stats.deepest = 0;
+            stats.updates = 0;
+        }
+        if (gi < MAX_LEVELS) {
+            stats.histogram[gi] = 0;
+        }
+    } else if (bd.pass == PASS_OPEN) {
+        // Start of the largest step, every particle picks its level
+        if (gi < bd.buffer_size) {
+            open_step(gi, allowed_level(requested_level(gi, levels.data[gi])));
+        }
+    } else if (bd.pass == PASS_DRIFT) {
+        // Every particle drifts by the step of the deepest level
+        if (gi < bd.buffer_size) {
+            vec3 low = pos_low.data[gi].xyz;
+            vec3 increment = vel.data[gi].xyz * level_dt(bd.depth);
+            vec3 p = add_position(pos_mass.data[gi].xyz, low, increment, bd.precision_mode);
+            if (bd.box_size > 0.0) {
+                p = mod(p, bd.box_size);
+            }
+            pos_mass.data[gi].xyz = p;
+            pos_low.data[gi].xyz = low;
+            points.data[gi].xyz = p;
+        }
+    } else if (bd.pass == PASS_COMPACT_CLEAR) {
+        if (gi == 0) {
+            active_count.value = 0;
+        }
+    } else if (bd.pass == PASS_COMPACT) {
+        if (gi < bd.buffer_size && is_active(gi)) {
+            active_set.data[atomicAdd(active_count.value, 1)] = gi;
+        }
+    } else if (bd.pass == PASS_COMPACT_DISPATCH) {
+        if (gi == 0) {
+            dispatch.x = (active_count.value + PARALLELISM - 1) / PARALLELISM;
+            dispatch.y = 1;
+            dispatch.z = 1;
+            stats.updates += active_count.value;
+        }
+    } else if (bd.pass == PASS_CLOSE || bd.pass == PASS_CLOSE_OPEN) {
+        // Dispatched for the active set only, which is at the end of its step
+        if (gi < active_count.value) {
+            uint i = active_set.data[gi];
+            uint level = levels.data[i];
+            kick(i, level);
+
+            uint requested = requested_level(i, level);
+            atomicMax(stats.deepest, requested);
+            if (bd.pass == PASS_CLOSE_OPEN) {
+                open_step(i, allowed_level(requested));
+            }
+        }
+    } else if (bd.pass == PASS_HISTOGRAM) {
+        if (gi < bd.buffer_size) {
+            atomicAdd(stats.histogram[min(levels.data[gi], MAX_LEVELS - 1)], 1);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use std::sync::Arc;
+
+use hatchery::{
+    inspect::Inspect,
+    util::{
+        buffer::{AbstractBuffer, DeviceBuffer},
+        compute::{ComputeShader, ComputeShaderExecutor},
+        ConstructionContext,
+    },
+};
+use vulkano::{
+    buffer::{BufferUsage, DeviceLocalBuffer},
+    command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand, PrimaryAutoCommandBuffer},
+    descriptor_set::WriteDescriptorSet,
+    device::Device,
+    shader::ShaderModule,
+};
+
+use super::{forces::Forces, has_float64, Precision, SimulationBuffers};
+
+hatchery::compute! { "src/physics/block.glsl", block }
+hatchery::compute! { "src/physics/block.glsl", block_f64, ("DOUBLE", "1") }
+
+// Passes of `block.glsl`
+const PASS_RESET: u32 = 0;
+const PASS_OPEN: u32 = 1;
+const PASS_DRIFT: u32 = 2;
+const PASS_COMPACT_CLEAR: u32 = 3;
+const PASS_COMPACT: u32 = 4;
+const PASS_COMPACT_DISPATCH: u32 = 5;
+const PASS_CLOSE: u32 = 6;
+const PASS_CLOSE_OPEN: u32 = 7;
+const PASS_HISTOGRAM: u32 = 8;
+
+/// Length of the histogram in `block.glsl`
+const MAX_LEVELS: usize = 16;
+
+const ETA: f32 = 0.025;
+const MAX_LEVEL: u32 = 6;
+
+/// How the particles pick their timestep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Inspect)]
+pub enum Criterion {
+    /// `sqrt(2 η ε / |a|)` from the softening length, as in GADGET
+    #[default]
+    Acceleration,
+    /// `η |a| / |j|`, with the jerk from the change of the acceleration over the last step
+    Jerk,
+}
+
+impl Criterion {
+    fn index(&self) -> u32 {
+        match self {
+            Criterion::Acceleration => 0,
+            Criterion::Jerk => 1,
+        }
+    }
+}
+
+struct BlockShader {
+    data: Arc<SimulationBuffers>,
+    /// Load the variant with f64 positions, which also handles the other precisions
+    double: bool,
+    levels: DeviceBuffer<u32>,
+    opening_acceleration: DeviceBuffer<[f32; 4]>,
+    /// Deepest requested level, the number of updates and the histogram of the levels
+    statistics: DeviceBuffer<u32>,
+    constants: block::ty::BlockData,
+}
+
+impl ComputeShader for BlockShader {
+    type Constants = block::ty::BlockData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        block::load(device).unwrap()
+    }
+
+    fn load_variant(&self, device: Arc<Device>) -> Arc<ShaderModule> {
+        if self.double {
+            block_f64::load(device).unwrap()
+        } else {
+            Self::load_module(device)
+        }
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        match self.constants.pass {
+            PASS_RESET | PASS_COMPACT_CLEAR | PASS_COMPACT_DISPATCH => [1, 1, 1],
+            _ => [self.constants.buffer_size.div_ceil(128), 1, 1],
+        }
+    }
+
+    fn indirect_dispatch(&self) -> Option<Arc<DeviceLocalBuffer<[DispatchIndirectCommand]>>> {
+        matches!(self.constants.pass, PASS_CLOSE | PASS_CLOSE_OPEN)
+            .then(|| self.data.active.dispatch.typed_buffer())
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.points.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(2, self.data.velocity.buffer()),
+            WriteDescriptorSet::buffer(3, self.data.acceleration.buffer()),
+            WriteDescriptorSet::buffer(4, self.levels.buffer()),
+            WriteDescriptorSet::buffer(5, self.opening_acceleration.buffer()),
+            WriteDescriptorSet::buffer(6, self.data.active.indices.buffer()),
+            WriteDescriptorSet::buffer(7, self.data.active.count.buffer()),
+            WriteDescriptorSet::buffer(8, self.data.active.dispatch.buffer()),
+            WriteDescriptorSet::buffer(9, self.statistics.buffer()),
+            WriteDescriptorSet::buffer(10, self.data.position_low.buffer()),
+        ]
+    }
+}
+
+/// Timestep levels of the last step
+#[derive(Debug, Clone, Default)]
+pub struct LevelStatistics {
+    /// Particles on each level, the step of level `l` is `dt / 2^l`
+    pub histogram: Vec<u32>,
+    /// Deepest level the step was subdivided to
+    pub depth: u32,
+    /// Deepest level asked for at the end of the step, the next one is subdivided down to it
+    pub requested: u32,
+    /// Particles kicked at the end of their step, `N 2^depth` would be the cost of a global step
+    /// at the deepest level
+    pub updates: u32,
+}
+
+/// Hierarchical block timesteps, every particle advances with the largest power of two fraction
+/// of `dt` its criterion allows. The step is split into substeps of the deepest level, after each
+/// one only the particles at the end of their step are compacted into
+/// `SimulationBuffers::active`, get their forces and are kicked. All particles drift every
+/// substep, which is cheap next to the forces.
+#[derive(Inspect)]
+pub struct BlockTimesteps {
+    /// Replaces the scheme of the integrator with kick-drift-kick leapfrog on the levels
+    enabled: bool,
+    criterion: Criterion,
+    #[inspect(label = "Accuracy (η)", range = 1e-4..=1.0, log)]
+    eta: f32,
+    /// Smallest step is `dt / 2^max_level`
+    #[inspect(label = "Deepest level", range = 0.0..=12.0)]
+    max_level: u32,
+    #[inspect(skip)]
+    shader: ComputeShaderExecutor<BlockShader>,
+    /// Levels have been picked, and the accelerations are from the end of the last step
+    #[inspect(skip)]
+    started: bool,
+    #[inspect(skip)]
+    statistics: LevelStatistics,
+}
+
+impl BlockTimesteps {
+    pub fn new(context: &ConstructionContext, data: Arc<SimulationBuffers>) -> Self {
+        let count = data.capacity as u64;
+        let storage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        };
+
+        let shader = BlockShader {
+            constants: block::ty::BlockData {
+                buffer_size: data.num_particles(),
+                pass: PASS_RESET,
+                substep: 0,
+                depth: 0,
+                max_level: MAX_LEVEL,
+                criterion: Criterion::default().index(),
+                dt: 0.0,
+                eta: ETA,
+                softening: 0.0,
+                box_size: 0.0,
+                precision_mode: Precision::default().mode(),
+            },
+            double: has_float64(context),
+            data,
+            levels: DeviceBuffer::new(context, storage, count),
+            opening_acceleration: DeviceBuffer::new(context, storage, count),
+            statistics: DeviceBuffer::new(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    transfer_src: true,
+                    ..BufferUsage::empty()
+                },
+                2 + MAX_LEVELS as u64,
+            ),
+        };
+
+        Self {
+            enabled: false,
+            criterion: Criterion::default(),
+            eta: ETA,
+            max_level: MAX_LEVEL,
+            shader: ComputeShaderExecutor::new(context, shader),
+            started: false,
+            statistics: LevelStatistics::default(),
+        }
+    }
+
+    /// Record a step of length `dt`, subdivided down to the deepest level requested at the end
+    /// of the last one. The first step after `restart` calculates the forces of all particles
+    /// and subdivides down to `max_level`.
+    pub fn record(
+        &mut self,
+        dt: f32,
+        forces: &mut Forces,
+        context: &ConstructionContext,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        let max_level = self.max_level.min(MAX_LEVELS as u32 - 1);
+        let depth = if self.started {
+            self.statistics.requested.min(max_level)
+        } else {
+            forces.record(context, builder);
+            max_level
+        };
+
+        let num_particles = self.shader.data.num_particles();
+        let constants = &mut self.shader.constants;
+        constants.buffer_size = num_particles;
+        constants.dt = dt;
+        constants.depth = depth;
+        constants.max_level = max_level;
+        constants.eta = self.eta;
+        constants.softening = forces.softening();
+        constants.box_size = forces.wrap_size();
+        constants.substep = 0;
+        // There is no last step to take the jerk from
+        constants.criterion = if self.started {
+            self.criterion.index()
+        } else {
+            Criterion::Acceleration.index()
+        };
+
+        self.pass(builder, PASS_RESET);
+        self.pass(builder, PASS_OPEN);
+        self.shader.constants.criterion = self.criterion.index();
+
+        let substeps = 1 << depth;
+        for substep in 1..=substeps {
+            self.shader.constants.substep = substep;
+            self.pass(builder, PASS_DRIFT);
+
+            self.pass(builder, PASS_COMPACT_CLEAR);
+            self.pass(builder, PASS_COMPACT);
+            self.pass(builder, PASS_COMPACT_DISPATCH);
+
+            forces.record_active(context, builder);
+
+            let close = if substep == substeps {
+                PASS_CLOSE
+            } else {
+                PASS_CLOSE_OPEN
+            };
+            self.pass(builder, close);
+        }
+
+        self.pass(builder, PASS_HISTOGRAM);
+        self.started = true;
+    }
+
+    /// Read back the statistics of the step recorded last, after it was submitted
+    pub fn read_statistics(&mut self, context: &ConstructionContext) {
+        let statistics = self
+            .shader
+            .statistics
+            .read_back(context, 0, 2 + MAX_LEVELS as u64);
+        let depth = self.shader.constants.depth;
+
+        self.statistics = LevelStatistics {
+            histogram: statistics[2..3 + depth as usize].to_vec(),
+            depth,
+            requested: statistics[0],
+            updates: statistics[1],
+        };
+    }
+
+    /// Precision of the drifts, must be available on the device
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.shader.constants.precision_mode = precision.mode();
+    }
+
+    /// Pick the levels again and recalculate the forces of all particles in the next step, after
+    /// another integrator moved them
+    pub fn restart(&mut self) {
+        self.started = false;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn statistics(&self) -> &LevelStatistics {
+        &self.statistics
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn set_criterion(&mut self, criterion: Criterion) {
+        self.criterion = criterion;
+    }
+
+    pub fn set_eta(&mut self, eta: f32) {
+        self.eta = eta;
+    }
+
+    pub fn set_max_level(&mut self, max_level: u32) {
+        self.max_level = max_level;
+    }
+
+    fn pass(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pass: u32,
+    ) {
+        self.shader.constants.pass = pass;
+        self.shader.record(builder);
+    }
+}
@@ -0,0 +1,340 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+// Neighbours are found with a hashed uniform grid, with cells as large as the largest radius so
+// only the 27 cells around a particle have to be searched. The particles are counted into the
+// buckets of the hash table, the counts are scanned into offsets and the particles scattered into
+// `sorted`, so the particles of a bucket are contiguous.
+//
+// After the mergers are applied the donors are removed by compacting the particle buffers. The
+// live particles are scanned into their new indices in `destination`, gathered into `scratch` in
+// that order and written back, so they stay in the order they were in.
+
+layout(set = 0, binding = 0) buffer Points { vec4 data[]; } points;
+layout(set = 0, binding = 1) buffer PositionMass { vec4 data[]; } pos_mass;
+layout(set = 0, binding = 2) buffer Velocity { vec4 data[]; } vel;
+layout(set = 0, binding = 3) buffer Acceleration { vec4 data[]; } acc;
+layout(set = 0, binding = 4) buffer CellCount { uint data[]; } cell_count;
+layout(set = 0, binding = 5) buffer CellStart { uint data[]; } cell_start;
+layout(set = 0, binding = 6) buffer Sorted { uint data[]; } sorted;
+// Particle every particle wants to merge with, sinks are marked with SINK_BIT
+layout(set = 0, binding = 7) buffer Nearest { uint data[]; } nearest;
+
+struct Event {
+    uint receiver;
+    uint donor;
+    float receiver_mass;
+    float donor_mass;
+};
+
+layout(set = 0, binding = 8) buffer Events { Event data[]; } events;
+layout(set = 0, binding = 9) buffer EventCount { uint value; } event_count;
+// Rounding error of the positions, merged particles move so theirs is reset
+layout(set = 0, binding = 10) buffer PositionLow { vec4 data[]; } pos_low;
+layout(set = 0, binding = 11) buffer Jerk { vec4 data[]; } jerk;
+// Index every particle moves to, REMOVED for the donors
+layout(set = 0, binding = 12) buffer Destination { uint data[]; } destination;
+
+// Everything a particle keeps between steps
+struct Compacted {
+    vec4 point;
+    vec4 pos_mass;
+    vec4 pos_low;
+    vec4 vel;
+    vec4 acc;
+    vec4 jerk;
+};
+
+layout(set = 0, binding = 13) buffer Scratch { Compacted data[]; } scratch;
+
+#define PASS_CLEAR 0
+#define PASS_COUNT 1
+#define PASS_SCAN 2
+#define PASS_SCATTER 3
+#define PASS_NEAREST 4
+#define PASS_EVENTS 5
+#define PASS_APPLY 6
+#define PASS_KEEP 7
+#define PASS_REMOVE 8
+#define PASS_COMPACT_SCAN 9
+#define PASS_GATHER 10
+#define PASS_WRITE_BACK 11
+
+#define NO_PARTNER 0xffffffffu
+#define SINK_BIT 0x80000000u
+#define REMOVED 0xffffffffu
+
+layout(push_constant) uniform CollisionData {
+    uint buffer_size;
+    // Buckets of the hash table, a power of two
+    uint table_size;
+    uint pass;
+    // Length of the event buffer, further mergers wait for the next step
+    uint capacity;
+    float cell_size;
+    // Particles closer than this merge, 0 disables mergers
+    float capture_radius;
+    // Sinks accrete the particles closer than this, 0 disables sinks
+    float sink_radius;
+    // Particles at least this heavy are sinks
+    float sink_mass;
+} cd;
+
+bool is_sink(float mass) {
+    return cd.sink_radius > 0.0 && mass >= cd.sink_mass;
+}
+
+uint bucket(ivec3 cell) {
+    uvec3 c = uvec3(cell);
+    return ((c.x * 73856093u) ^ (c.y * 19349663u) ^ (c.z * 83492791u)) & (cd.table_size - 1u);
+}
+
+ivec3 cell_of(vec3 p) {
+    return ivec3(floor(p / cd.cell_size));
+}
+
+// Neighbouring cells can share a bucket, which only means particles are looked at twice
+void find_nearest(uint i) {
+    vec4 particle = pos_mass.data[i];
+    if (is_sink(particle.w)) {
+        nearest.data[i] = NO_PARTNER;
+        return;
+    }
+
+    float best_sink = cd.sink_radius * cd.sink_radius;
+    float best = cd.capture_radius * cd.capture_radius;
+    uint sink = NO_PARTNER;
+    uint partner = NO_PARTNER;
+
+    ivec3 cell = cell_of(particle.xyz);
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            for (int z = -1; z <= 1; z++) {
+                uint b = bucket(cell + ivec3(x, y, z));
+                uint start = cell_start.data[b];
+                for (uint k = start; k < start + cell_count.data[b]; k++) {
+                    uint j = sorted.data[k];
+                    if (j == i) {
+                        continue;
+                    }
+                    vec4 other = pos_mass.data[j];
+                    vec3 diff = other.xyz - particle.xyz;
+                    float dist2 = dot(diff, diff);
+
+                    if (is_sink(other.w)) {
+                        if (dist2 < best_sink) {
+                            best_sink = dist2;
+                            sink = j;
+                        }
+                    } else if (dist2 < best) {
+                        best = dist2;
+                        partner = j;
+                    }
+                }
+            }
+        }
+    }
+
+    // Accretion by a sink goes first
+    nearest.data[i] = sink != NO_PARTNER ? sink | SINK_BIT : partner;
+}
+
+// Particles are accreted by their nearest sink, pairs merge if they are each other's nearest
+// neighbour, the lighter one into the heavier
+void record_event(uint i) {
+    uint n = nearest.data[i];
+    if (n == NO_PARTNER) {
+        return;
+    }
+
+    float mass = pos_mass.data[i].w;
+    uint receiver = n & ~SINK_BIT;
+    if ((n & SINK_BIT) == 0u) {
+        float other = pos_mass.data[receiver].w;
+        bool lighter = mass < other || (mass == other && i > receiver);
+        if (nearest.data[receiver] != i || !lighter) {
+            return;
+        }
+    }
+
+    uint e = atomicAdd(event_count.value, 1u);
+    if (e < cd.capacity) {
+        events.data[e] = Event(receiver, i, pos_mass.data[receiver].w, mass);
+    }
+}
+
+// Run for the first event of every receiver, which takes all of its donors at once. Receivers
+// are never donors, so the donors are still untouched.
+void apply_events(uint e, uint count) {
+    uint receiver = events.data[e].receiver;
+    for (uint k = 0; k < e; k++) {
+        if (events.data[k].receiver == receiver) {
+            return;
+        }
+    }
+
+    vec4 particle = pos_mass.data[receiver];
+    float mass = particle.w;
+    // Relative to the receiver, to keep the precision of its position
+    vec3 shift = vec3(0.0);
+    vec3 momentum = mass * vel.data[receiver].xyz;
+    for (uint k = e; k < count; k++) {
+        if (events.data[k].receiver != receiver) {
+            continue;
+        }
+        uint donor = events.data[k].donor;
+        vec4 other = pos_mass.data[donor];
+        mass += other.w;
+        shift += other.w * (other.xyz - particle.xyz);
+        momentum += other.w * vel.data[donor].xyz;
+    }
+
+    vec3 p = particle.xyz + shift / mass;
+    pos_mass.data[receiver] = vec4(p, mass);
+    pos_low.data[receiver] = vec4(0.0);
+    points.data[receiver].xyz = p;
+    vel.data[receiver].xyz = momentum / mass;
+}
+
+shared uint _totals[PARALLELISM];
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+    uint li = gl_LocalInvocationID.x;
+    uint n = cd.buffer_size;
+
+    if (cd.pass == PASS_CLEAR) {
+        if (gi < cd.table_size) {
+            cell_count.data[gi] = 0u;
+        }
+        if (gi == 0) {
+            event_count.value = 0u;
+        }
+    } else if (cd.pass == PASS_COUNT) {
+        if (gi < n) {
+            atomicAdd(cell_count.data[bucket(cell_of(pos_mass.data[gi].xyz))], 1u);
+        }
+    } else if (cd.pass == PASS_SCAN) {
+        // A single workgroup, every invocation scans a contiguous chunk of the buckets. The
+        // counts are reset, as the scatter pass counts again.
+        uint chunk = (cd.table_size + PARALLELISM - 1) / PARALLELISM;
+        uint begin = min(li * chunk, cd.table_size);
+        uint end = min(begin + chunk, cd.table_size);
+
+        uint sum = 0u;
+        for (uint k = begin; k < end; k++) {
+            sum += cell_count.data[k];
+        }
+        _totals[li] = sum;
+        barrier();
+
+        if (li == 0) {
+            uint offset = 0u;
+            for (int k = 0; k < PARALLELISM; k++) {
+                uint total = _totals[k];
+                _totals[k] = offset;
+                offset += total;
+            }
+        }
+        barrier();
+
+        uint offset = _totals[li];
+        for (uint k = begin; k < end; k++) {
+            cell_start.data[k] = offset;
+            offset += cell_count.data[k];
+            cell_count.data[k] = 0u;
+        }
+    } else if (cd.pass == PASS_SCATTER) {
+        if (gi < n) {
+            uint b = bucket(cell_of(pos_mass.data[gi].xyz));
+            sorted.data[cell_start.data[b] + atomicAdd(cell_count.data[b], 1u)] = gi;
+        }
+    } else if (cd.pass == PASS_NEAREST) {
+        if (gi < n) {
+            find_nearest(gi);
+        }
+    } else if (cd.pass == PASS_EVENTS) {
+        if (gi < n) {
+            record_event(gi);
+        }
+    } else if (cd.pass == PASS_APPLY) {
+        uint count = min(event_count.value, cd.capacity);
+        if (gi < count) {
+            apply_events(gi, count);
+        }
+    } else if (cd.pass == PASS_KEEP) {
+        if (gi < n) {
+            destination.data[gi] = 1u;
+        }
+    } else if (cd.pass == PASS_REMOVE) {
+        uint count = min(event_count.value, cd.capacity);
+        if (gi < count) {
+            destination.data[events.data[gi].donor] = 0u;
+        }
+    } else if (cd.pass == PASS_COMPACT_SCAN) {
+        // The same scan as PASS_SCAN, over the flags of the particles which are kept
+        uint chunk = (n + PARALLELISM - 1) / PARALLELISM;
+        uint begin = min(li * chunk, n);
+        uint end = min(begin + chunk, n);
+
+        uint sum = 0u;
+        for (uint k = begin; k < end; k++) {
+            sum += destination.data[k];
+        }
+        _totals[li] = sum;
+        barrier();
+
+        if (li == 0) {
+            uint offset = 0u;
+            for (int k = 0; k < PARALLELISM; k++) {
+                uint total = _totals[k];
+                _totals[k] = offset;
+                offset += total;
+            }
+        }
+        barrier();
+
+        uint offset = _totals[li];
+        for (uint k = begin; k < end; k++) {
+            if (destination.data[k] != 0u) {
+                destination.data[k] = offset++;
+            } else {
+                destination.data[k] = REMOVED;
+            }
+        }
+    } else if (cd.pass == PASS_GATHER) {
+        if (gi < n && destination.data[gi] != REMOVED) {
+            scratch.data[destination.data[gi]] = Compacted(
+                points.data[gi],
+                pos_mass.data[gi],
+                pos_low.data[gi],
+                vel.data[gi],
+                acc.data[gi],
+                jerk.data[gi]
+            );
+        }
+    } else if (cd.pass == PASS_WRITE_BACK) {
+        // Every event removes its donor
+        uint live = n - min(event_count.value, cd.capacity);
+        if (gi < live) {
+            Compacted particle = scratch.data[gi];
+            points.data[gi] = particle.point;
+            pos_mass.data[gi] = particle.pos_mass;
+            pos_low.data[gi] = particle.pos_low;
+            vel.data[gi] = particle.vel;
+            acc.data[gi] = particle.acc;
+            jerk.data[gi] = particle.jerk;
+        } else if (gi < n) {
+            // The freed entries have no mass, and a negative size hides them
+            points.data[gi] = vec4(0.0, 0.0, 0.0, -1.0);
+            pos_mass.data[gi] = vec4(0.0);
+            pos_low.data[gi] = vec4(0.0);
+            vel.data[gi] = vec4(0.0);
+            acc.data[gi] = vec4(0.0);
+            jerk.data[gi] = vec4(0.0);
+        }
+    }
+}
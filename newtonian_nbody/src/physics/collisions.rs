@@ -0,0 +1,377 @@
+use std::{fmt::Write as _, fs, io, path::Path, sync::Arc};
+
+use bytemuck::{Pod, Zeroable};
+use hatchery::{
+    inspect::Inspect,
+    util::{
+        buffer::{AbstractBuffer, DeviceBuffer},
+        compute::{self, ComputeShader, ComputeShaderExecutor},
+        ConstructionContext,
+    },
+};
+use vulkano::{
+    buffer::BufferUsage,
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::WriteDescriptorSet,
+    device::Device,
+    shader::ShaderModule,
+};
+
+use super::SimulationBuffers;
+
+hatchery::compute! { "src/physics/collisions.glsl", collisions }
+
+// Passes of `collisions.glsl`
+const PASS_CLEAR: u32 = 0;
+const PASS_COUNT: u32 = 1;
+const PASS_SCAN: u32 = 2;
+const PASS_SCATTER: u32 = 3;
+const PASS_NEAREST: u32 = 4;
+const PASS_EVENTS: u32 = 5;
+const PASS_APPLY: u32 = 6;
+const PASS_KEEP: u32 = 7;
+const PASS_REMOVE: u32 = 8;
+const PASS_COMPACT_SCAN: u32 = 9;
+const PASS_GATHER: u32 = 10;
+const PASS_WRITE_BACK: u32 = 11;
+
+/// Mergers recorded per step, further ones wait for the next step
+const CAPACITY: u32 = 4096;
+
+const CAPTURE_RADIUS: f32 = 0.01;
+const SINK_RADIUS: f32 = 0.05;
+/// Heavier than the stars of the default galaxy, so only its central black hole is a sink
+const SINK_MASS: f32 = 1.0;
+
+/// `Event` in `collisions.glsl`
+#[repr(C)]
+#[derive(Default, Pod, Zeroable, Clone, Copy)]
+struct GpuEvent {
+    receiver: u32,
+    donor: u32,
+    receiver_mass: f32,
+    donor_mass: f32,
+}
+
+/// `Compacted` in `collisions.glsl`, the point, position, rounding error, velocity, acceleration
+/// and jerk of a particle
+type Compacted = [[f32; 4]; 6];
+
+struct CollisionShader {
+    data: Arc<SimulationBuffers>,
+    cell_count: DeviceBuffer<u32>,
+    cell_start: DeviceBuffer<u32>,
+    sorted: DeviceBuffer<u32>,
+    nearest: DeviceBuffer<u32>,
+    events: DeviceBuffer<GpuEvent>,
+    event_count: DeviceBuffer<u32>,
+    destination: DeviceBuffer<u32>,
+    scratch: DeviceBuffer<Compacted>,
+    constants: collisions::ty::CollisionData,
+}
+
+impl ComputeShader for CollisionShader {
+    type Constants = collisions::ty::CollisionData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        collisions::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        let threads = match self.constants.pass {
+            PASS_CLEAR => self.constants.table_size,
+            PASS_SCAN | PASS_COMPACT_SCAN => 1,
+            PASS_APPLY | PASS_REMOVE => self.constants.capacity,
+            _ => self.constants.buffer_size,
+        };
+        [threads.div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.points.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(2, self.data.velocity.buffer()),
+            WriteDescriptorSet::buffer(3, self.data.acceleration.buffer()),
+            WriteDescriptorSet::buffer(4, self.cell_count.buffer()),
+            WriteDescriptorSet::buffer(5, self.cell_start.buffer()),
+            WriteDescriptorSet::buffer(6, self.sorted.buffer()),
+            WriteDescriptorSet::buffer(7, self.nearest.buffer()),
+            WriteDescriptorSet::buffer(8, self.events.buffer()),
+            WriteDescriptorSet::buffer(9, self.event_count.buffer()),
+            WriteDescriptorSet::buffer(10, self.data.position_low.buffer()),
+            WriteDescriptorSet::buffer(11, self.data.jerk.buffer()),
+            WriteDescriptorSet::buffer(12, self.destination.buffer()),
+            WriteDescriptorSet::buffer(13, self.scratch.buffer()),
+        ]
+    }
+}
+
+/// A particle merged into another one, or accreted by a sink. Particles are identified by their
+/// index when the simulation started, the buffers are compacted after every merger so the
+/// particles move to lower indices.
+#[derive(Debug, Clone, Copy)]
+pub struct MergerEvent {
+    pub time: f32,
+    pub receiver: u32,
+    /// Removed particle
+    pub donor: u32,
+    /// Masses before the step, the receiver can take several donors at once
+    pub receiver_mass: f32,
+    pub donor_mass: f32,
+    /// The receiver is a sink
+    pub sink: bool,
+}
+
+/// Close encounters, checked after every step. Particles closer than the capture radius which
+/// are each other's nearest neighbour merge, and sinks accrete all lighter particles within the
+/// sink radius. Mass and momentum are conserved, the merged particle sits at the center of mass.
+/// Mergers are inelastic, so the energy drops by the kinetic energy of the relative motion.
+///
+/// Neighbours are found on the GPU with a hashed grid, the mergers are compacted into an event
+/// buffer which is applied on the GPU and read back for the log. The donors are then removed by
+/// compacting the particle buffers, which shrinks `SimulationBuffers::num_particles` so the
+/// solvers only see the particles which are left. Indices kept elsewhere have to be updated with
+/// `remap`.
+#[derive(Inspect)]
+pub struct Collisions {
+    /// Merge close pairs of particles
+    mergers: bool,
+    #[inspect(label = "Capture radius", range = 1e-5..=1.0, log)]
+    capture_radius: f32,
+    /// Let heavy particles accrete the light ones around them
+    sinks: bool,
+    #[inspect(label = "Sink radius", range = 1e-4..=10.0, log)]
+    sink_radius: f32,
+    /// Particles at least this heavy are sinks
+    #[inspect(label = "Sink mass", range = 1e-6..=1e6, log)]
+    sink_mass: f32,
+    #[inspect(skip)]
+    shader: ComputeShaderExecutor<CollisionShader>,
+    #[inspect(skip)]
+    events: Vec<MergerEvent>,
+    /// Index of every particle when the simulation started
+    #[inspect(skip)]
+    ids: Vec<u32>,
+    /// Donors of the last mergers and their receivers, sorted by donor
+    #[inspect(skip)]
+    donors: Vec<(u32, u32)>,
+}
+
+impl Collisions {
+    pub fn new(context: &ConstructionContext, data: Arc<SimulationBuffers>) -> Self {
+        let num_particles = data.capacity;
+        let table_size = num_particles.next_power_of_two();
+        let storage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        };
+        let readable = BufferUsage {
+            storage_buffer: true,
+            transfer_src: true,
+            ..BufferUsage::empty()
+        };
+
+        let shader = CollisionShader {
+            data,
+            cell_count: DeviceBuffer::new(context, storage, table_size as u64),
+            cell_start: DeviceBuffer::new(context, storage, table_size as u64),
+            sorted: DeviceBuffer::new(context, storage, num_particles as u64),
+            nearest: DeviceBuffer::new(context, storage, num_particles as u64),
+            events: DeviceBuffer::new(context, readable, CAPACITY as u64),
+            event_count: DeviceBuffer::new(context, readable, 1),
+            destination: DeviceBuffer::new(context, storage, num_particles as u64),
+            scratch: DeviceBuffer::new(context, storage, num_particles as u64),
+            constants: collisions::ty::CollisionData {
+                buffer_size: num_particles,
+                table_size,
+                pass: PASS_CLEAR,
+                capacity: CAPACITY,
+                cell_size: 0.0,
+                capture_radius: 0.0,
+                sink_radius: 0.0,
+                sink_mass: SINK_MASS,
+            },
+        };
+
+        Self {
+            mergers: false,
+            capture_radius: CAPTURE_RADIUS,
+            sinks: false,
+            sink_radius: SINK_RADIUS,
+            sink_mass: SINK_MASS,
+            shader: ComputeShaderExecutor::new(context, shader),
+            events: Vec::new(),
+            ids: (0..num_particles).collect(),
+            donors: Vec::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mergers || self.sinks
+    }
+
+    /// Merge the particles which are close at `time`, remove the donors and add the mergers to
+    /// the log. Returns the number of removed particles, if there are any the forces are out of
+    /// date and the indices of the particles have changed. Waits for the GPU.
+    pub fn resolve(&mut self, context: &ConstructionContext, time: f32) -> usize {
+        self.donors.clear();
+        if !self.enabled() {
+            return 0;
+        }
+
+        let capture_radius = if self.mergers {
+            self.capture_radius
+        } else {
+            0.0
+        };
+        let sink_radius = if self.sinks { self.sink_radius } else { 0.0 };
+
+        let num_particles = self.shader.data.num_particles();
+        let constants = &mut self.shader.constants;
+        constants.buffer_size = num_particles;
+        constants.capture_radius = capture_radius;
+        constants.sink_radius = sink_radius;
+        constants.sink_mass = self.sink_mass;
+        constants.cell_size = capture_radius.max(sink_radius);
+
+        let mut builder = compute::begin(context);
+        for pass in [
+            PASS_CLEAR,
+            PASS_COUNT,
+            PASS_SCAN,
+            PASS_SCATTER,
+            PASS_NEAREST,
+            PASS_EVENTS,
+            PASS_APPLY,
+        ] {
+            self.record_pass(&mut builder, pass);
+        }
+        compute::submit(context, builder);
+
+        let count = self.shader.event_count.read_back(context, 0, 1)[0].min(CAPACITY);
+        if count == 0 {
+            return 0;
+        }
+
+        let mut builder = compute::begin(context);
+        for pass in [
+            PASS_KEEP,
+            PASS_REMOVE,
+            PASS_COMPACT_SCAN,
+            PASS_GATHER,
+            PASS_WRITE_BACK,
+        ] {
+            self.record_pass(&mut builder, pass);
+        }
+        compute::submit(context, builder);
+        self.shader.data.set_num_particles(num_particles - count);
+
+        let sink_mass = self.sinks.then_some(self.sink_mass);
+        let events = self.shader.events.read_back(context, 0, count as u64);
+        self.events.extend(events.iter().map(|event| MergerEvent {
+            time,
+            receiver: self.ids[event.receiver as usize],
+            donor: self.ids[event.donor as usize],
+            receiver_mass: event.receiver_mass,
+            donor_mass: event.donor_mass,
+            sink: sink_mass.map_or(false, |mass| event.receiver_mass >= mass),
+        }));
+
+        self.donors = events
+            .iter()
+            .map(|event| (event.donor, event.receiver))
+            .collect();
+        self.donors.sort_unstable();
+        self.ids = self
+            .ids
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| self.receiver(index as u32).is_none())
+            .map(|(_, &id)| id)
+            .collect();
+
+        count as usize
+    }
+
+    /// Index after the last `resolve` of the particle which was at `index` before it. Donors
+    /// follow the particle they merged into.
+    pub fn remap(&self, index: u32) -> u32 {
+        let index = self.receiver(index).unwrap_or(index);
+        // Receivers are never donors, every donor before them moves them down by one
+        index - self.donors.partition_point(|&(donor, _)| donor < index) as u32
+    }
+
+    /// The particle the one at `index` merged into, if it was a donor of the last mergers
+    fn receiver(&self, index: u32) -> Option<u32> {
+        self.donors
+            .binary_search_by_key(&index, |&(donor, _)| donor)
+            .ok()
+            .map(|k| self.donors[k].1)
+    }
+
+    fn record_pass(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pass: u32,
+    ) {
+        self.shader.constants.pass = pass;
+        self.shader.record(builder);
+    }
+
+    /// All mergers so far, in the order they happened
+    pub fn events(&self) -> &[MergerEvent] {
+        &self.events
+    }
+
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+
+    /// The log as `time,receiver,donor,receiver_mass,donor_mass,sink` rows
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("time,receiver,donor,receiver_mass,donor_mass,sink\n");
+        for event in &self.events {
+            writeln!(
+                csv,
+                "{:e},{},{},{:e},{:e},{}",
+                event.time,
+                event.receiver,
+                event.donor,
+                event.receiver_mass,
+                event.donor_mass,
+                event.sink
+            )
+            .unwrap();
+        }
+        csv
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_csv())
+    }
+
+    pub fn set_mergers(&mut self, mergers: bool) {
+        self.mergers = mergers;
+    }
+
+    pub fn set_capture_radius(&mut self, capture_radius: f32) {
+        self.capture_radius = capture_radius;
+    }
+
+    pub fn set_sinks(&mut self, sinks: bool) {
+        self.sinks = sinks;
+    }
+
+    pub fn set_sink_radius(&mut self, sink_radius: f32) {
+        self.sink_radius = sink_radius;
+    }
+
+    pub fn set_sink_mass(&mut self, sink_mass: f32) {
+        self.sink_mass = sink_mass;
+    }
+}
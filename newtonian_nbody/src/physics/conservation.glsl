@@ -0,0 +1,94 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer PositionMass { vec4 data[]; } pos_mass;
+layout(set = 0, binding = 1) buffer Velocity { vec4 data[]; } vel;
+// Sums of every workgroup of the partial pass, `SUMS` floats each
+layout(set = 0, binding = 2) buffer Partials { float data[]; } partials;
+layout(set = 0, binding = 3) buffer Totals { float data[]; } totals;
+
+#define PASS_PARTIAL 0
+#define PASS_TOTAL 1
+
+// Mass, mass weighted position (3), momentum (3), angular momentum about the origin (3), sum of
+// the magnitudes of the momenta and angular momenta, sum of m x² and of m v²
+#define SUMS 14
+
+layout(push_constant) uniform ConservationData {
+    uint buffer_size;
+    uint pass;
+    // Workgroups of the partial pass
+    uint num_groups;
+} cd;
+
+shared float _sums[SUMS * PARALLELISM];
+
+void store(uint li, vec4 particle, vec3 v) {
+    float m = particle.w;
+    vec3 x = particle.xyz;
+    vec3 l = m * cross(x, v);
+
+    _sums[0 * PARALLELISM + li] = m;
+    for (int k = 0; k < 3; k++) {
+        _sums[(1 + k) * PARALLELISM + li] = m * x[k];
+        _sums[(4 + k) * PARALLELISM + li] = m * v[k];
+        _sums[(7 + k) * PARALLELISM + li] = l[k];
+    }
+    _sums[10 * PARALLELISM + li] = m * length(v);
+    _sums[11 * PARALLELISM + li] = length(l);
+    _sums[12 * PARALLELISM + li] = m * dot(x, x);
+    _sums[13 * PARALLELISM + li] = m * dot(v, v);
+}
+
+// Tree reduction of the shared sums, the totals end up at index 0
+void reduce(uint li) {
+    barrier();
+    for (uint stride = PARALLELISM / 2; stride > 0; stride /= 2) {
+        if (li < stride) {
+            for (int k = 0; k < SUMS; k++) {
+                _sums[k * PARALLELISM + li] += _sums[k * PARALLELISM + li + stride];
+            }
+        }
+        barrier();
+    }
+}
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+    uint li = gl_LocalInvocationID.x;
+
+    if (cd.pass == PASS_PARTIAL) {
+        // Padding has no mass, so it doesn't contribute
+        if (gi < cd.buffer_size) {
+            store(li, pos_mass.data[gi], vel.data[gi].xyz);
+        } else {
+            store(li, vec4(0.0), vec3(0.0));
+        }
+        reduce(li);
+
+        if (li == 0) {
+            for (int k = 0; k < SUMS; k++) {
+                partials.data[gl_WorkGroupID.x * SUMS + k] = _sums[k * PARALLELISM];
+            }
+        }
+    } else if (cd.pass == PASS_TOTAL) {
+        // A single workgroup, every invocation first sums a strided subset of the partials
+        for (int k = 0; k < SUMS; k++) {
+            float sum = 0.0;
+            for (uint group = li; group < cd.num_groups; group += PARALLELISM) {
+                sum += partials.data[group * SUMS + k];
+            }
+            _sums[k * PARALLELISM + li] = sum;
+        }
+        reduce(li);
+
+        if (li == 0) {
+            for (int k = 0; k < SUMS; k++) {
+                totals.data[k] = _sums[k * PARALLELISM];
+            }
+        }
+    }
+}
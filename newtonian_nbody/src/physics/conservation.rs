@@ -0,0 +1,373 @@
+use std::sync::Arc;
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+use hatchery::{
+    inspect::Inspect,
+    util::{
+        buffer::{AbstractBuffer, DeviceBuffer, SharedBuffer},
+        compute::{self, ComputeShader, ComputeShaderExecutor},
+        ConstructionContext,
+    },
+};
+use vulkano::{
+    buffer::BufferUsage, descriptor_set::WriteDescriptorSet, device::Device, shader::ShaderModule,
+};
+
+use super::{energy::Energy, SimulationBuffers};
+
+hatchery::compute! { "src/physics/conservation.glsl", conservation }
+
+// Passes of `conservation.glsl`
+const PASS_PARTIAL: u32 = 0;
+const PASS_TOTAL: u32 = 1;
+
+/// Floats summed by `conservation.glsl`
+const SUMS: usize = 14;
+
+const INTERVAL: u32 = 10;
+
+/// Names of the drifts, in the order of `Drift::values`
+pub const QUANTITIES: [&str; 6] = [
+    "Energy",
+    "Momentum",
+    "Angular momentum",
+    "Center of mass",
+    "Center of mass velocity",
+    "Virial ratio",
+];
+
+struct ConservationShader {
+    data: Arc<SimulationBuffers>,
+    partials: DeviceBuffer<f32>,
+    totals: SharedBuffer<f32>,
+    constants: conservation::ty::ConservationData,
+}
+
+impl ComputeShader for ConservationShader {
+    type Constants = conservation::ty::ConservationData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        conservation::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        match self.constants.pass {
+            PASS_TOTAL => [1, 1, 1],
+            _ => [self.constants.num_groups, 1, 1],
+        }
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.velocity.buffer()),
+            WriteDescriptorSet::buffer(2, self.partials.buffer()),
+            WriteDescriptorSet::buffer(3, self.totals.buffer()),
+        ]
+    }
+}
+
+/// Conserved quantities of all particles, together with the scales their drifts are measured in
+#[derive(Debug, Clone, Copy)]
+pub struct Moments {
+    pub mass: f32,
+    pub center_of_mass: Point3<f32>,
+    pub center_of_mass_velocity: Vector3<f32>,
+    pub momentum: Vector3<f32>,
+    /// About the origin
+    pub angular_momentum: Vector3<f32>,
+    /// Sum of the magnitudes of the momenta of the particles
+    pub momentum_scale: f32,
+    /// Sum of the magnitudes of the angular momenta of the particles
+    pub angular_momentum_scale: f32,
+    /// Root mean square distance from the center of mass
+    pub radius: f32,
+    /// Root mean square velocity relative to the center of mass
+    pub velocity_dispersion: f32,
+}
+
+impl Moments {
+    fn from_sums(sums: &[f32]) -> Self {
+        let vector = |i: usize| Vector3::new(sums[i], sums[i + 1], sums[i + 2]);
+        let mass = sums[0];
+        let center_of_mass = Point3::from_vec(vector(1) / mass);
+        let momentum = vector(4);
+        let velocity = momentum / mass;
+
+        Self {
+            mass,
+            center_of_mass,
+            center_of_mass_velocity: velocity,
+            momentum,
+            angular_momentum: vector(7),
+            momentum_scale: sums[10],
+            angular_momentum_scale: sums[11],
+            radius: (sums[12] / mass - center_of_mass.to_vec().magnitude2())
+                .max(0.0)
+                .sqrt(),
+            velocity_dispersion: (sums[13] / mass - velocity.magnitude2()).max(0.0).sqrt(),
+        }
+    }
+}
+
+/// Moments and energy at one time
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub time: f32,
+    pub moments: Moments,
+    pub energy: Energy,
+}
+
+impl Measurement {
+    /// `2K / |W|`, 1 in virial equilibrium
+    pub fn virial_ratio(&self) -> f64 {
+        ratio(2.0 * self.energy.kinetic, self.energy.potential.abs())
+    }
+}
+
+/// How far the conserved quantities moved since the first measurement, each relative to a scale
+/// of the system so they can be compared against fixed thresholds
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Drift {
+    /// `|E - E₀| / |E₀|`
+    pub energy: f64,
+    /// `|P - P₀|` over the sum of the magnitudes of the momenta, as the total is usually close
+    /// to zero
+    pub momentum: f64,
+    /// `|L - L₀|` over the sum of the magnitudes of the angular momenta
+    pub angular_momentum: f64,
+    /// Distance of the center of mass from its uniform motion at the initial velocity, over the
+    /// root mean square radius
+    pub center_of_mass: f64,
+    /// `|V - V₀|` over the velocity dispersion
+    pub center_of_mass_velocity: f64,
+    /// `|Q - Q₀| / Q₀` of the virial ratio `Q = 2K / |W|`. Not conserved, but a system which
+    /// starts in equilibrium should stay close to it.
+    pub virial_ratio: f64,
+}
+
+impl Drift {
+    fn between(initial: &Measurement, current: &Measurement) -> Self {
+        let (a, b) = (&initial.moments, &current.moments);
+        let elapsed = current.time - initial.time;
+        let expected = a.center_of_mass + a.center_of_mass_velocity * elapsed;
+
+        Self {
+            energy: ratio(
+                (current.energy.total() - initial.energy.total()).abs(),
+                initial.energy.total().abs(),
+            ),
+            momentum: ratio(
+                (b.momentum - a.momentum).magnitude() as f64,
+                b.momentum_scale as f64,
+            ),
+            angular_momentum: ratio(
+                (b.angular_momentum - a.angular_momentum).magnitude() as f64,
+                b.angular_momentum_scale as f64,
+            ),
+            center_of_mass: ratio(
+                (b.center_of_mass - expected).magnitude() as f64,
+                b.radius as f64,
+            ),
+            center_of_mass_velocity: ratio(
+                (b.center_of_mass_velocity - a.center_of_mass_velocity).magnitude() as f64,
+                b.velocity_dispersion as f64,
+            ),
+            virial_ratio: ratio(
+                (current.virial_ratio() - initial.virial_ratio()).abs(),
+                initial.virial_ratio(),
+            ),
+        }
+    }
+
+    /// The drifts in the order of `QUANTITIES`
+    pub fn values(&self) -> [f64; 6] {
+        [
+            self.energy,
+            self.momentum,
+            self.angular_momentum,
+            self.center_of_mass,
+            self.center_of_mass_velocity,
+            self.virial_ratio,
+        ]
+    }
+}
+
+/// Largest drifts which are still fine
+#[derive(Debug, Clone, Copy, Inspect)]
+pub struct Thresholds {
+    #[inspect(range = 1e-8..=1.0, log)]
+    pub energy: f64,
+    #[inspect(range = 1e-8..=1.0, log)]
+    pub momentum: f64,
+    #[inspect(label = "Angular momentum", range = 1e-8..=1.0, log)]
+    pub angular_momentum: f64,
+    #[inspect(label = "Center of mass", range = 1e-8..=1.0, log)]
+    pub center_of_mass: f64,
+    #[inspect(label = "Center of mass velocity", range = 1e-8..=1.0, log)]
+    pub center_of_mass_velocity: f64,
+    #[inspect(label = "Virial ratio", range = 1e-8..=1.0, log)]
+    pub virial_ratio: f64,
+}
+
+impl Thresholds {
+    /// The thresholds in the order of `QUANTITIES`
+    pub fn values(&self) -> [f64; 6] {
+        [
+            self.energy,
+            self.momentum,
+            self.angular_momentum,
+            self.center_of_mass,
+            self.center_of_mass_velocity,
+            self.virial_ratio,
+        ]
+    }
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            energy: 1e-3,
+            momentum: 1e-3,
+            angular_momentum: 1e-3,
+            center_of_mass: 1e-3,
+            center_of_mass_velocity: 1e-3,
+            virial_ratio: 0.1,
+        }
+    }
+}
+
+/// Tracks the conserved quantities, measured with GPU reductions every few steps. Drifts are
+/// relative to the first measurement after `restart`. Momentum and angular momentum are only
+/// conserved by solvers with symmetric forces, and none of them in a periodic box.
+#[derive(Inspect)]
+pub struct Conservation {
+    /// Steps between the measurements, the energy is calculated with the same interval
+    #[inspect(label = "Interval (steps)", range = 1.0..=1000.0)]
+    interval: u32,
+    /// Warn when a drift exceeds its threshold
+    thresholds: Thresholds,
+    #[inspect(skip)]
+    shader: ComputeShaderExecutor<ConservationShader>,
+    #[inspect(skip)]
+    initial: Option<Measurement>,
+    #[inspect(skip)]
+    last: Option<Measurement>,
+    #[inspect(skip)]
+    drift: Drift,
+}
+
+impl Conservation {
+    pub fn new(context: &ConstructionContext, data: Arc<SimulationBuffers>) -> Self {
+        let num_particles = data.capacity;
+        let num_groups = num_particles.div_ceil(128);
+        let storage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        };
+
+        let shader = ConservationShader {
+            data,
+            partials: DeviceBuffer::new(context, storage, num_groups as u64 * SUMS as u64),
+            totals: SharedBuffer::from_iter(context, storage, vec![0.0; SUMS]),
+            constants: conservation::ty::ConservationData {
+                buffer_size: num_particles,
+                pass: PASS_PARTIAL,
+                num_groups,
+            },
+        };
+
+        Self {
+            interval: INTERVAL,
+            thresholds: Thresholds::default(),
+            shader: ComputeShaderExecutor::new(context, shader),
+            initial: None,
+            last: None,
+            drift: Drift::default(),
+        }
+    }
+
+    /// Whether to measure before the step with this number
+    pub fn due(&self, steps: u64) -> bool {
+        steps % self.interval.max(1) as u64 == 0
+    }
+
+    /// Sum the moments of the particles at `time` and compare them with the first measurement.
+    /// Waits for the GPU.
+    pub fn measure(&mut self, context: &ConstructionContext, time: f32, energy: Energy) -> Drift {
+        let num_particles = self.shader.data.num_particles();
+        let constants = &mut self.shader.constants;
+        constants.buffer_size = num_particles;
+        constants.num_groups = num_particles.div_ceil(128);
+
+        let mut builder = compute::begin(context);
+        for pass in [PASS_PARTIAL, PASS_TOTAL] {
+            self.shader.constants.pass = pass;
+            self.shader.record(&mut builder);
+        }
+        compute::submit(context, builder);
+
+        let sums = self.shader.totals.typed_buffer().read().unwrap();
+        let current = Measurement {
+            time,
+            moments: Moments::from_sums(&sums),
+            energy,
+        };
+
+        let initial = *self.initial.get_or_insert(current);
+        self.drift = Drift::between(&initial, &current);
+        self.last = Some(current);
+        self.drift
+    }
+
+    /// Measure the drifts from the next measurement on
+    pub fn restart(&mut self) {
+        self.initial = None;
+        self.last = None;
+        self.drift = Drift::default();
+    }
+
+    /// Quantities whose drift is above its threshold, with the drift and the threshold
+    pub fn warnings(&self) -> Vec<(&'static str, f64, f64)> {
+        QUANTITIES
+            .into_iter()
+            .zip(self.drift.values())
+            .zip(self.thresholds.values())
+            .filter(|((_, drift), threshold)| drift > threshold)
+            .map(|((name, drift), threshold)| (name, drift, threshold))
+            .collect()
+    }
+
+    pub fn last(&self) -> Option<&Measurement> {
+        self.last.as_ref()
+    }
+
+    pub fn drift(&self) -> Drift {
+        self.drift
+    }
+
+    pub fn thresholds(&self) -> &Thresholds {
+        &self.thresholds
+    }
+
+    pub fn set_interval(&mut self, interval: u32) {
+        self.interval = interval;
+    }
+
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) {
+        self.thresholds = thresholds;
+    }
+}
+
+/// `value / scale`, 0 for a system without that scale
+fn ratio(value: f64, scale: f64) -> f64 {
+    if scale > 0.0 {
+        value / scale
+    } else {
+        0.0
+    }
+}
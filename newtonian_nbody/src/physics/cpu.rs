@@ -0,0 +1,842 @@
+use std::f64::consts::PI;
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
+use rayon::prelude::*;
+
+use super::{fmm::Fmm, pm::Mesh, Particle};
+
+/// Double precision particle used by the CPU solvers
+#[derive(Debug, Clone, Copy)]
+pub struct Body {
+    pub position: Point3<f64>,
+    pub velocity: Vector3<f64>,
+    pub mass: f64,
+}
+
+impl From<&Particle> for Body {
+    fn from(particle: &Particle) -> Self {
+        Self {
+            position: particle.position.cast().unwrap(),
+            velocity: particle.velocity.cast().unwrap(),
+            mass: particle.mass as f64,
+        }
+    }
+}
+
+impl From<&Body> for Particle {
+    fn from(body: &Body) -> Self {
+        Particle::new(
+            body.position.cast().unwrap(),
+            body.velocity.cast().unwrap(),
+            body.mass as f32,
+        )
+    }
+}
+
+/// Gravitational constant and Plummer softening, the same parameters as `Forces`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gravity {
+    pub g: f64,
+    pub softening: f64,
+}
+
+impl Gravity {
+    pub fn new(g: f32, softening: f32) -> Self {
+        Self {
+            g: g as f64,
+            softening: softening as f64,
+        }
+    }
+
+    /// Acceleration at `target` caused by `mass` at `source`
+    pub(super) fn acceleration(
+        &self,
+        target: Point3<f64>,
+        source: Point3<f64>,
+        mass: f64,
+    ) -> Vector3<f64> {
+        let diff = source - target;
+        let dist2 = diff.magnitude2() + self.softening * self.softening;
+        diff * (self.g * mass / (dist2 * dist2.sqrt()))
+    }
+}
+
+/// Short range forces of TreePM are neglected beyond this many split radii
+const CUTOFF: f64 = 4.5;
+
+/// Gaussian force split of TreePM, a tree only sums the short range part, between the nearest
+/// periodic images in a box spanning `[0, box_size)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForceSplit {
+    pub radius: f64,
+    pub box_size: f64,
+}
+
+impl ForceSplit {
+    /// Nearest periodic image of a separation
+    fn separation(&self, diff: Vector3<f64>) -> Vector3<f64> {
+        diff.map(|x| x - self.box_size * (x / self.box_size).round())
+    }
+
+    /// Fraction of the force which is short range at distance `r`
+    fn short_range(&self, r: f64) -> f64 {
+        let x = r / (2.0 * self.radius);
+        erfc(x) + 2.0 * x / PI.sqrt() * (-x * x).exp()
+    }
+
+    /// Short range acceleration at `target` caused by `mass` at `source`
+    fn acceleration(
+        &self,
+        gravity: Gravity,
+        target: Point3<f64>,
+        source: Point3<f64>,
+        mass: f64,
+    ) -> Vector3<f64> {
+        let diff = self.separation(source - target);
+        let dist2 = diff.magnitude2() + gravity.softening * gravity.softening;
+        diff * (gravity.g * mass * self.short_range(diff.magnitude()) / (dist2 * dist2.sqrt()))
+    }
+}
+
+/// Complementary error function, Abramowitz and Stegun 7.1.26 which is accurate to 1.5e-7 like
+/// the approximation in `barnes_hut.glsl`
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    poly * (-x * x).exp()
+}
+
+/// Maximum number of bodies in an octree leaf, unless given otherwise
+const LEAF_SIZE: usize = 8;
+/// Deeper cells are made leaves whatever their size, only reached with coincident bodies
+const MAX_DEPTH: u32 = 48;
+pub(super) const NO_CHILD: u32 = u32::MAX;
+
+pub(super) struct Node {
+    pub(super) center: Point3<f64>,
+    pub(super) half_size: f64,
+    pub(super) mass: f64,
+    pub(super) center_of_mass: Point3<f64>,
+    pub(super) children: [u32; 8],
+    /// Range of `Octree::order` holding the bodies of the cell
+    pub(super) bodies: (usize, usize),
+}
+
+impl Node {
+    pub(super) fn is_leaf(&self) -> bool {
+        self.children.iter().all(|&child| child == NO_CHILD)
+    }
+
+    fn contains(&self, point: Point3<f64>) -> bool {
+        (0..3).all(|i| (point[i] - self.center[i]).abs() <= self.half_size)
+    }
+}
+
+/// Octree over a set of bodies storing the mass and center of mass of every cell
+pub struct Octree {
+    /// Children always come after their parent
+    pub(super) nodes: Vec<Node>,
+    /// Indices of the bodies, grouped by leaf
+    pub(super) order: Vec<usize>,
+    leaf_size: usize,
+}
+
+impl Octree {
+    pub fn new(bodies: &[Body]) -> Self {
+        Self::with_leaf_size(bodies, LEAF_SIZE)
+    }
+
+    /// Octree with up to `leaf_size` bodies in a leaf
+    pub fn with_leaf_size(bodies: &[Body], leaf_size: usize) -> Self {
+        let (min, max) = bodies.iter().fold(
+            (
+                Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |(min, max), body| {
+                let p = body.position;
+                (
+                    Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                    Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+                )
+            },
+        );
+        let extent = max - min;
+        let half_size = 0.5 * extent.x.max(extent.y).max(extent.z).max(1e-12);
+
+        let mut tree = Self {
+            nodes: Vec::with_capacity(2 * bodies.len() / leaf_size + 1),
+            order: (0..bodies.len()).collect(),
+            leaf_size,
+        };
+        if !bodies.is_empty() {
+            tree.build(bodies, 0, bodies.len(), min.midpoint(max), half_size, 0);
+        }
+        tree
+    }
+
+    fn build(
+        &mut self,
+        bodies: &[Body],
+        start: usize,
+        end: usize,
+        center: Point3<f64>,
+        half_size: f64,
+        depth: u32,
+    ) -> u32 {
+        let index = self.nodes.len() as u32;
+        let (mass, weighted) =
+            self.order[start..end]
+                .iter()
+                .fold((0.0, Vector3::zero()), |(mass, weighted), &i| {
+                    let body = &bodies[i];
+                    (
+                        mass + body.mass,
+                        weighted + body.position.to_vec() * body.mass,
+                    )
+                });
+        let center_of_mass = if mass > 0.0 {
+            Point3::from_vec(weighted / mass)
+        } else {
+            center
+        };
+
+        self.nodes.push(Node {
+            center,
+            half_size,
+            mass,
+            center_of_mass,
+            children: [NO_CHILD; 8],
+            bodies: (start, end),
+        });
+
+        if end - start <= self.leaf_size || depth >= MAX_DEPTH {
+            return index;
+        }
+
+        // Group the bodies by octant, then build a child for every non empty one
+        let octant = |i: &usize| {
+            let p = bodies[*i].position;
+            (p.x > center.x) as usize
+                | ((p.y > center.y) as usize) << 1
+                | ((p.z > center.z) as usize) << 2
+        };
+        self.order[start..end].sort_unstable_by_key(octant);
+
+        let quarter = 0.5 * half_size;
+        let mut child_start = start;
+        for octant_index in 0..8 {
+            let child_end = child_start
+                + self.order[child_start..end]
+                    .iter()
+                    .take_while(|i| octant(i) == octant_index)
+                    .count();
+            if child_end > child_start {
+                let offset = Vector3::new(
+                    if octant_index & 1 != 0 {
+                        quarter
+                    } else {
+                        -quarter
+                    },
+                    if octant_index & 2 != 0 {
+                        quarter
+                    } else {
+                        -quarter
+                    },
+                    if octant_index & 4 != 0 {
+                        quarter
+                    } else {
+                        -quarter
+                    },
+                );
+                let child = self.build(
+                    bodies,
+                    child_start,
+                    child_end,
+                    center + offset,
+                    quarter,
+                    depth + 1,
+                );
+                self.nodes[index as usize].children[octant_index] = child;
+            }
+            child_start = child_end;
+        }
+
+        index
+    }
+
+    /// Acceleration of body `target`, cells smaller than `theta` times their distance are
+    /// treated as a single mass
+    pub fn acceleration(
+        &self,
+        bodies: &[Body],
+        target: usize,
+        gravity: Gravity,
+        theta: f64,
+    ) -> Vector3<f64> {
+        let position = bodies[target].position;
+        let mut acceleration = Vector3::zero();
+        if self.nodes.is_empty() {
+            return acceleration;
+        }
+
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if node.is_leaf() {
+                let (start, end) = node.bodies;
+                for &i in &self.order[start..end] {
+                    if i != target {
+                        let body = &bodies[i];
+                        acceleration += gravity.acceleration(position, body.position, body.mass);
+                    }
+                }
+                continue;
+            }
+
+            let size = 2.0 * node.half_size;
+            let distance2 = (node.center_of_mass - position).magnitude2();
+            if size * size < theta * theta * distance2 && !node.contains(position) {
+                acceleration += gravity.acceleration(position, node.center_of_mass, node.mass);
+            } else {
+                stack.extend(node.children.iter().filter(|&&child| child != NO_CHILD));
+            }
+        }
+
+        acceleration
+    }
+
+    /// Short range acceleration of body `target` under a TreePM force split, cells beyond the
+    /// cutoff are skipped
+    pub fn short_range_acceleration(
+        &self,
+        bodies: &[Body],
+        target: usize,
+        gravity: Gravity,
+        theta: f64,
+        split: ForceSplit,
+    ) -> Vector3<f64> {
+        let position = bodies[target].position;
+        let mut acceleration = Vector3::zero();
+        if self.nodes.is_empty() {
+            return acceleration;
+        }
+
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if node.is_leaf() {
+                let (start, end) = node.bodies;
+                for &i in &self.order[start..end] {
+                    if i != target {
+                        let body = &bodies[i];
+                        acceleration +=
+                            split.acceleration(gravity, position, body.position, body.mass);
+                    }
+                }
+                continue;
+            }
+
+            // Distance from the body to the cell
+            let gap = split
+                .separation(node.center - position)
+                .map(|x| (x.abs() - node.half_size).max(0.0));
+            if gap.magnitude() > CUTOFF * split.radius {
+                continue;
+            }
+
+            let size = 2.0 * node.half_size;
+            let distance2 = split
+                .separation(node.center_of_mass - position)
+                .magnitude2();
+            if size * size < theta * theta * distance2 && !gap.is_zero() {
+                acceleration +=
+                    split.acceleration(gravity, position, node.center_of_mass, node.mass);
+            } else {
+                stack.extend(node.children.iter().filter(|&&child| child != NO_CHILD));
+            }
+        }
+
+        acceleration
+    }
+}
+
+/// Gravity solvers running on the CPU in double precision, the ground truth for the GPU solvers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuSolver {
+    /// Exact sum over all pairs
+    Direct,
+    BarnesHut {
+        theta: f64,
+    },
+    Fmm {
+        order: usize,
+        theta: f64,
+    },
+    /// Periodic box spanning `[0, box_size)`
+    ParticleMesh {
+        resolution: usize,
+        box_size: f64,
+    },
+    /// Particle mesh for the long range force and Barnes-Hut for the short range, split at
+    /// `split` in world units
+    TreePm {
+        resolution: usize,
+        box_size: f64,
+        split: f64,
+        theta: f64,
+    },
+}
+
+impl CpuSolver {
+    /// Size of the periodic box the particles live in, `None` for the open solvers
+    pub fn box_size(&self) -> Option<f64> {
+        match *self {
+            CpuSolver::ParticleMesh { box_size, .. } | CpuSolver::TreePm { box_size, .. } => {
+                Some(box_size)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn accelerations(&self, bodies: &[Body], gravity: Gravity) -> Vec<Vector3<f64>> {
+        let indices: Vec<usize> = (0..bodies.len()).collect();
+        self.accelerations_of(bodies, gravity, &indices)
+    }
+
+    /// Accelerations of only the bodies at `indices`, caused by all bodies
+    pub fn accelerations_of(
+        &self,
+        bodies: &[Body],
+        gravity: Gravity,
+        indices: &[usize],
+    ) -> Vec<Vector3<f64>> {
+        match *self {
+            CpuSolver::Direct => indices
+                .par_iter()
+                .map(|&target| {
+                    let position = bodies[target].position;
+                    bodies
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| *i != target)
+                        .fold(Vector3::zero(), |acceleration, (_, body)| {
+                            acceleration + gravity.acceleration(position, body.position, body.mass)
+                        })
+                })
+                .collect(),
+            CpuSolver::BarnesHut { theta } => {
+                let tree = Octree::new(bodies);
+                indices
+                    .par_iter()
+                    .map(|&target| tree.acceleration(bodies, target, gravity, theta))
+                    .collect()
+            }
+            // The expansions and the mesh are shared by all bodies, so there is nothing to save
+            CpuSolver::Fmm { order, theta } => {
+                let accelerations = Fmm::new(order, theta).accelerations(bodies, gravity);
+                indices.iter().map(|&i| accelerations[i]).collect()
+            }
+            CpuSolver::ParticleMesh {
+                resolution,
+                box_size,
+            } => {
+                let accelerations = Mesh::new(resolution, box_size).accelerations(bodies, gravity);
+                indices.iter().map(|&i| accelerations[i]).collect()
+            }
+            CpuSolver::TreePm {
+                resolution,
+                box_size,
+                split,
+                theta,
+            } => {
+                let long_range = Mesh::new(resolution, box_size)
+                    .with_split(split)
+                    .accelerations(bodies, gravity);
+                let tree = Octree::new(bodies);
+                let split = ForceSplit {
+                    radius: split,
+                    box_size,
+                };
+                indices
+                    .par_iter()
+                    .map(|&target| {
+                        long_range[target]
+                            + tree.short_range_acceleration(bodies, target, gravity, theta, split)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Relative error of a set of accelerations against a reference
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    /// `|a - a_ref| / |a_ref|` of every particle
+    pub errors: Vec<f64>,
+    pub max: f64,
+    pub mean: f64,
+    pub rms: f64,
+    /// Position in `errors` of the largest error
+    pub worst: usize,
+}
+
+/// Compare accelerations, usually read back from the GPU, against a CPU reference
+pub fn compare(accelerations: &[Vector3<f32>], reference: &[Vector3<f64>]) -> Comparison {
+    let errors: Vec<f64> = accelerations
+        .iter()
+        .zip(reference)
+        .map(|(acceleration, reference)| {
+            let error = (acceleration.cast().unwrap() - reference).magnitude();
+            let scale = reference.magnitude();
+            if scale > 0.0 {
+                error / scale
+            } else {
+                error
+            }
+        })
+        .collect();
+
+    if errors.is_empty() {
+        return Comparison::default();
+    }
+
+    let count = errors.len() as f64;
+    let (worst, max) =
+        errors
+            .iter()
+            .copied()
+            .enumerate()
+            .fold((0, 0.0), |(worst, max), (i, error)| {
+                if error > max {
+                    (i, error)
+                } else {
+                    (worst, max)
+                }
+            });
+
+    Comparison {
+        max,
+        mean: errors.iter().sum::<f64>() / count,
+        rms: (errors.iter().map(|error| error * error).sum::<f64>() / count).sqrt(),
+        worst,
+        errors,
+    }
+}
+
+/// Kick-drift-kick leapfrog on the CPU, the same scheme as `KickDriftKick`. Slow, but runs
+/// without a GPU. Positions are wrapped back into the box after each drift when the solver is
+/// periodic.
+pub struct CpuSimulation {
+    pub bodies: Vec<Body>,
+    accelerations: Vec<Vector3<f64>>,
+    pub gravity: Gravity,
+    pub solver: CpuSolver,
+    pub dt: f64,
+    pub time: f64,
+    pub steps: u64,
+}
+
+impl CpuSimulation {
+    pub fn new(particles: &[Particle], gravity: Gravity, solver: CpuSolver, dt: f64) -> Self {
+        let bodies: Vec<Body> = particles.iter().map(Body::from).collect();
+        let accelerations = solver.accelerations(&bodies, gravity);
+
+        Self {
+            bodies,
+            accelerations,
+            gravity,
+            solver,
+            dt,
+            time: 0.0,
+            steps: 0,
+        }
+    }
+
+    pub fn step(&mut self) {
+        let dt = self.dt;
+        let box_size = self.solver.box_size();
+        self.bodies
+            .par_iter_mut()
+            .zip(&self.accelerations)
+            .for_each(|(body, acceleration)| {
+                body.velocity += acceleration * (0.5 * dt);
+                body.position += body.velocity * dt;
+                if let Some(box_size) = box_size {
+                    body.position = body.position.map(|x| x.rem_euclid(box_size));
+                }
+            });
+
+        self.accelerations = self.solver.accelerations(&self.bodies, self.gravity);
+
+        self.bodies
+            .par_iter_mut()
+            .zip(&self.accelerations)
+            .for_each(|(body, acceleration)| {
+                body.velocity += acceleration * (0.5 * dt);
+            });
+
+        self.time += dt;
+        self.steps += 1;
+    }
+
+    /// Kinetic energy and exact softened pairwise potential energy, O(N²). In a periodic box
+    /// pairs are separated by their nearest image.
+    pub fn energy(&self) -> (f64, f64) {
+        let kinetic = self
+            .bodies
+            .par_iter()
+            .map(|body| 0.5 * body.mass * body.velocity.magnitude2())
+            .sum();
+
+        let Gravity { g, softening } = self.gravity;
+        let box_size = self.solver.box_size();
+        let potential = (0..self.bodies.len())
+            .into_par_iter()
+            .map(|i| {
+                let a = &self.bodies[i];
+                self.bodies[i + 1..]
+                    .iter()
+                    .map(|b| {
+                        let mut diff = a.position - b.position;
+                        if let Some(box_size) = box_size {
+                            diff = diff.map(|x| x - box_size * (x / box_size).round());
+                        }
+                        let dist2 = diff.magnitude2() + softening * softening;
+                        -g * a.mass * b.mass / dist2.sqrt()
+                    })
+                    .sum::<f64>()
+            })
+            .sum();
+
+        (kinetic, potential)
+    }
+
+    pub fn particles(&self) -> Vec<Particle> {
+        self.bodies.iter().map(Particle::from).collect()
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use rand_distr::{Distribution, UnitBall, UnitSphere};
+
+    use super::*;
+
+    /// `count` bodies at rest, uniform in a ball of radius `radius` around `center`
+    pub(in crate::physics) fn cluster(
+        count: usize,
+        center: Point3<f64>,
+        radius: f64,
+        seed: u64,
+    ) -> Vec<Body> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                let [x, y, z]: [f64; 3] = UnitBall.sample(&mut rng);
+                Body {
+                    position: center + Vector3::new(x, y, z) * radius,
+                    velocity: Vector3::zero(),
+                    mass: rng.gen_range(0.5..1.5) / count as f64,
+                }
+            })
+            .collect()
+    }
+
+    /// Largest `|a - b| / |b|`
+    pub(in crate::physics) fn max_error(a: &[Vector3<f64>], b: &[Vector3<f64>]) -> f64 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).magnitude() / b.magnitude())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn octree_without_opening_matches_direct() {
+        let bodies = cluster(1000, Point3::origin(), 1.0, 1);
+        let gravity = Gravity::new(1.0, 0.01);
+
+        let direct = CpuSolver::Direct.accelerations(&bodies, gravity);
+        let tree = CpuSolver::BarnesHut { theta: 0.0 }.accelerations(&bodies, gravity);
+
+        assert!(max_error(&tree, &direct) < 1e-12);
+    }
+
+    #[test]
+    fn compare_errors() {
+        let reference = [
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 4.0, 0.0),
+            Vector3::new(0.0, 0.0, 2.0),
+        ];
+        let accelerations = [
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 5.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ];
+
+        let comparison = compare(&accelerations, &reference);
+        assert_eq!(comparison.errors, [0.0, 0.25, 0.5]);
+        assert_eq!(comparison.max, 0.5);
+        assert_eq!(comparison.worst, 2);
+        assert_eq!(comparison.mean, 0.25);
+        assert!((comparison.rms - (0.3125f64 / 3.0).sqrt()).abs() < 1e-15);
+    }
+
+    #[test]
+    fn two_body_energy_is_bounded() {
+        // Equal masses on a circular orbit with a period of 2π / √2
+        let speed = 0.5f32.sqrt();
+        let particles = [
+            Particle::new(
+                Point3::new(-0.5, 0.0, 0.0),
+                Vector3::new(0.0, -speed, 0.0),
+                1.0,
+            ),
+            Particle::new(
+                Point3::new(0.5, 0.0, 0.0),
+                Vector3::new(0.0, speed, 0.0),
+                1.0,
+            ),
+        ];
+        let mut simulation =
+            CpuSimulation::new(&particles, Gravity::new(1.0, 0.0), CpuSolver::Direct, 0.01);
+
+        let energy = |simulation: &CpuSimulation| {
+            let (kinetic, potential) = simulation.energy();
+            kinetic + potential
+        };
+        let initial = energy(&simulation);
+
+        // About 20 orbits
+        let mut drift: f64 = 0.0;
+        for _ in 0..9000 {
+            simulation.step();
+            drift = drift.max(((energy(&simulation) - initial) / initial).abs());
+        }
+        assert!(drift < 1e-6, "energy drifted by {drift:e}");
+    }
+
+    #[test]
+    fn periodic_positions_stay_in_the_box() {
+        let particles = [
+            Particle::new(
+                Point3::new(0.05, 8.0, 8.0),
+                Vector3::new(-1.0, 0.0, 0.0),
+                1.0,
+            ),
+            Particle::new(
+                Point3::new(15.95, 8.0, 8.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                1.0,
+            ),
+        ];
+        let solver = CpuSolver::ParticleMesh {
+            resolution: 16,
+            box_size: 16.0,
+        };
+        let mut simulation = CpuSimulation::new(&particles, Gravity::new(0.0, 0.1), solver, 0.1);
+
+        for _ in 0..10 {
+            simulation.step();
+        }
+        for body in &simulation.bodies {
+            let position: [f64; 3] = body.position.into();
+            assert!(
+                position.iter().all(|x| (0.0..16.0).contains(x)),
+                "{position:?}"
+            );
+        }
+        assert!((simulation.bodies[0].position.x - 15.05).abs() < 1e-5);
+        assert!((simulation.bodies[1].position.x - 0.95).abs() < 1e-5);
+    }
+
+    /// Acceleration towards a unit mass at `diff` in a periodic box, with `G = 1`, by Ewald
+    /// summation. The neutralizing background of the mesh solvers is included.
+    pub(in crate::physics) fn periodic_acceleration(
+        diff: Vector3<f64>,
+        box_size: f64,
+    ) -> Vector3<f64> {
+        let alpha = 2.0 / box_size;
+        let mut acceleration = Vector3::zero();
+
+        for x in -2..=2 {
+            for y in -2..=2 {
+                for z in -2..=2 {
+                    let r = diff + Vector3::new(x, y, z).cast().unwrap() * box_size;
+                    let distance = r.magnitude();
+                    let ar = alpha * distance;
+                    let fraction = erfc(ar) + 2.0 * ar / PI.sqrt() * (-ar * ar).exp();
+                    acceleration += r * (fraction / distance.powi(3));
+                }
+            }
+        }
+
+        for x in -5..=5 {
+            for y in -5..=5 {
+                for z in -5..=5 {
+                    if (x, y, z) == (0, 0, 0) {
+                        continue;
+                    }
+                    let k = Vector3::new(x, y, z).cast().unwrap() * (2.0 * PI / box_size);
+                    let k2 = k.magnitude2();
+                    let weight =
+                        4.0 * PI / box_size.powi(3) / k2 * (-k2 / (4.0 * alpha * alpha)).exp();
+                    acceleration += k * (weight * k.dot(diff).sin());
+                }
+            }
+        }
+
+        acceleration
+    }
+
+    /// Pairs of unit masses at `separation`, in random places and directions of the unit box
+    pub(in crate::physics) fn pairs(separation: f64, count: usize, seed: u64) -> Vec<[Body; 2]> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                let position = Point3::new(rng.gen(), rng.gen(), rng.gen());
+                let [x, y, z]: [f64; 3] = UnitSphere.sample(&mut rng);
+                let body = |position| Body {
+                    position,
+                    velocity: Vector3::zero(),
+                    mass: 1.0,
+                };
+                [
+                    body(position),
+                    body(position + Vector3::new(x, y, z) * separation),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn force_split_adds_up_to_newtonian() {
+        // The split radius of `Forces`, 1.25 cells
+        let resolution = 64;
+        let split = ForceSplit {
+            radius: 1.25 / resolution as f64,
+            box_size: 1.0,
+        };
+        let mesh = Mesh::new(resolution, 1.0).with_split(split.radius);
+        let gravity = Gravity::new(1.0, 0.0);
+
+        // Well inside the split radius the short range force dominates, well outside it the mesh
+        for separation in [0.005, 0.15, 0.25] {
+            for bodies in pairs(separation, 3, 3) {
+                let [a, b] = bodies;
+                let long_range = mesh.accelerations(&bodies, gravity)[0];
+                let short_range = split.acceleration(gravity, a.position, b.position, 1.0);
+                let expected = periodic_acceleration(b.position - a.position, 1.0);
+
+                let error =
+                    (long_range + short_range - expected).magnitude() / expected.magnitude();
+                assert!(error < 0.01, "error of {error:e} at {separation}");
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+#include "precision.glsl"
+
+layout(set = 0, binding = 0) buffer PositionMass { vec4 data[]; } pos_mass;
+layout(set = 0, binding = 1) buffer Acceleration { vec4 data[]; } acc;
+layout(set = 0, binding = 2) buffer Velocity { vec4 data[]; } vel;
+layout(set = 0, binding = 3) buffer Jerk { vec4 data[]; } jerk;
+layout(set = 0, binding = 4) buffer ActiveIndices { uint data[]; } active_set;
+layout(set = 0, binding = 5) buffer ActiveCount { uint value; } active_count;
+layout(set = 0, binding = 6) buffer PositionLow { vec4 data[]; } pos_low;
+
+layout(push_constant) uniform ForceData {
+    uint buffer_size;
+    float G;
+    float softening;
+    // Also calculate the jerk, the time derivative of the acceleration
+    uint with_jerk;
+    // Only calculate the accelerations of the active set of the block timesteps
+    uint active_only;
+    // One of the PRECISION_ modes for the sum of the accelerations
+    uint precision_mode;
+} fd;
+
+vec3 calculate_accel(vec3 diff) {
+    float dist2 = dot(diff, diff) + (fd.softening * fd.softening);
+    return fd.G * diff / pow(dist2, 1.5);
+}
+
+#ifdef DOUBLE
+dvec3 calculate_accel_double(dvec3 diff) {
+    double dist2 = dot(diff, diff) + double(fd.softening * fd.softening);
+    return double(fd.G) * diff / (dist2 * sqrt(dist2));
+}
+#endif
+
+vec3 calculate_jerk(vec3 target, vec3 position, vec3 dv) {
+    vec3 diff = target - position;
+    float dist2 = dot(diff, diff) + (fd.softening * fd.softening);
+    return fd.G * (dv - 3.0 * dot(diff, dv) / dist2 * diff) / pow(dist2, 1.5);
+}
+
+shared vec4 _pos_mass[PARALLELISM];
+shared vec4 _vel[PARALLELISM];
+shared vec4 _low[PARALLELISM];
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+    uint li = gl_LocalInvocationID.x;
+
+    uint count = fd.active_only != 0 ? active_count.value : fd.buffer_size;
+    uint index = fd.active_only != 0 ? active_set.data[min(gi, count - 1)] : gi;
+
+    // Every invocation has to take part in loading the tiles, even past the end of the buffer
+    vec3 p = pos_mass.data[min(index, fd.buffer_size - 1)].xyz;
+    vec3 v = vel.data[min(index, fd.buffer_size - 1)].xyz;
+    // Separations are built from both parts of the positions unless the precision is single
+    bool with_low = fd.precision_mode != PRECISION_SINGLE;
+    vec3 p_low = with_low ? pos_low.data[min(index, fd.buffer_size - 1)].xyz : vec3(0.0);
+
+    vec3 a = vec3(0.0, 0.0, 0.0);
+    CompensatedSum a_compensated = CompensatedSum(vec3(0.0), vec3(0.0));
+#ifdef DOUBLE
+    dvec3 a_double = dvec3(0.0);
+#endif
+    vec3 j = vec3(0.0, 0.0, 0.0);
+    for (uint i = 0; i < fd.buffer_size; i += PARALLELISM) {
+        // Padding has no mass, so it doesn't contribute
+        _pos_mass[li] = i + li < fd.buffer_size ? pos_mass.data[i + li] : vec4(0.0);
+        if (fd.with_jerk != 0) {
+            _vel[li] = i + li < fd.buffer_size ? vel.data[i + li] : vec4(0.0);
+        }
+        if (with_low) {
+            _low[li] = i + li < fd.buffer_size ? pos_low.data[i + li] : vec4(0.0);
+        }
+        barrier();
+
+        if (fd.precision_mode == PRECISION_COMPENSATED) {
+            for (int k = 0; k < PARALLELISM; k++) {
+                // The difference of the low parts adds what the high parts lost to rounding
+                vec3 diff = (_pos_mass[k].xyz - p) + (_low[k].xyz - p_low);
+                compensated_add(a_compensated, _pos_mass[k].w * calculate_accel(diff));
+            }
+        }
+#ifdef DOUBLE
+        else if (fd.precision_mode == PRECISION_DOUBLE) {
+            dvec3 target = dvec3(p) + dvec3(p_low);
+            for (int k = 0; k < PARALLELISM; k++) {
+                dvec3 position = dvec3(_pos_mass[k].xyz) + dvec3(_low[k].xyz);
+                a_double += double(_pos_mass[k].w) * calculate_accel_double(position - target);
+            }
+        }
+#endif
+        else {
+            for (int k = 0; k < PARALLELISM; k++) {
+                a += _pos_mass[k].w * calculate_accel(_pos_mass[k].xyz - p);
+            }
+        }
+        if (fd.with_jerk != 0) {
+            for (int k = 0; k < PARALLELISM; k++) {
+                j += _pos_mass[k].w * calculate_jerk(_pos_mass[k].xyz, p, _vel[k].xyz - v);
+            }
+        }
+
+        barrier();
+    }
+
+    if (fd.precision_mode == PRECISION_COMPENSATED) {
+        a = a_compensated.sum;
+    }
+#ifdef DOUBLE
+    if (fd.precision_mode == PRECISION_DOUBLE) {
+        a = vec3(a_double);
+    }
+#endif
+
+    if (gi < count) {
+        acc.data[index].xyz = a;
+        if (fd.with_jerk != 0) {
+            jerk.data[index].xyz = j;
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use hatchery::util::{
+    buffer::AbstractBuffer,
+    compute::{ComputeShader, ComputeShaderExecutor},
+    ConstructionContext,
+};
+use vulkano::{
+    buffer::DeviceLocalBuffer,
+    command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand, PrimaryAutoCommandBuffer},
+    descriptor_set::WriteDescriptorSet,
+    device::Device,
+    shader::ShaderModule,
+};
+
+use super::{has_float64, ForceCalculator, Precision, SimulationBuffers};
+
+hatchery::compute! { "src/physics/direct.glsl", direct }
+hatchery::compute! { "src/physics/direct.glsl", direct_f64, ("DOUBLE", "1") }
+
+struct DirectShader {
+    data: Arc<SimulationBuffers>,
+    /// Load the variant with f64 summation, which also handles the other precisions
+    double: bool,
+    constants: direct::ty::ForceData,
+}
+
+impl ComputeShader for DirectShader {
+    type Constants = direct::ty::ForceData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        direct::load(device).unwrap()
+    }
+
+    fn load_variant(&self, device: Arc<Device>) -> Arc<ShaderModule> {
+        if self.double {
+            direct_f64::load(device).unwrap()
+        } else {
+            Self::load_module(device)
+        }
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        [self.constants.buffer_size.div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.acceleration.buffer()),
+            WriteDescriptorSet::buffer(2, self.data.velocity.buffer()),
+            WriteDescriptorSet::buffer(3, self.data.jerk.buffer()),
+            WriteDescriptorSet::buffer(4, self.data.active.indices.buffer()),
+            WriteDescriptorSet::buffer(5, self.data.active.count.buffer()),
+            WriteDescriptorSet::buffer(6, self.data.position_low.buffer()),
+        ]
+    }
+
+    fn indirect_dispatch(&self) -> Option<Arc<DeviceLocalBuffer<[DispatchIndirectCommand]>>> {
+        (self.constants.active_only != 0).then(|| self.data.active.dispatch.typed_buffer())
+    }
+}
+
+/// Exact O(N²) summation over all pairs, tiled through shared memory. Unless the precision is
+/// single, the separations include `SimulationBuffers::position_low`.
+pub struct DirectSummation {
+    shader: ComputeShaderExecutor<DirectShader>,
+}
+
+impl DirectSummation {
+    pub fn new(context: &ConstructionContext, data: Arc<SimulationBuffers>) -> Self {
+        let constants = direct::ty::ForceData {
+            buffer_size: data.num_particles(),
+            G: 0.0,
+            softening: 0.0,
+            with_jerk: 0,
+            active_only: 0,
+            precision_mode: Precision::default().mode(),
+        };
+        let shader = DirectShader {
+            data,
+            double: has_float64(context),
+            constants,
+        };
+
+        Self {
+            shader: ComputeShaderExecutor::new(context, shader),
+        }
+    }
+
+    /// Precision of the sums, must be available on the device
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.shader.constants.precision_mode = precision.mode();
+    }
+
+    /// Also calculate the jerk into `SimulationBuffers::jerk`, for the Hermite integrator
+    pub fn record_with_jerk(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        softening: f32,
+    ) {
+        self.shader.constants.with_jerk = 1;
+        self.record(builder, g, softening);
+        self.shader.constants.with_jerk = 0;
+    }
+
+    /// Only calculate the accelerations of `SimulationBuffers::active`, dispatched for the size of
+    /// the active set after its compaction
+    pub fn record_active(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        softening: f32,
+    ) {
+        self.shader.constants.active_only = 1;
+        self.record(builder, g, softening);
+        self.shader.constants.active_only = 0;
+    }
+}
+
+impl ForceCalculator for DirectSummation {
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        softening: f32,
+    ) {
+        let num_particles = self.shader.data.num_particles();
+        let constants = &mut self.shader.constants;
+        constants.buffer_size = num_particles;
+        constants.G = g;
+        constants.softening = softening;
+        self.shader.record(builder);
+    }
+}
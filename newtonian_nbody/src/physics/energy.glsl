@@ -6,25 +6,87 @@ layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
 
 layout(set = 0, binding = 0) buffer PositionMass { vec4 data[]; } pos_mass;
 layout(set = 0, binding = 1) buffer Velocity { vec4 data[]; } vel;
-layout(set = 0, binding = 2) buffer Acceleration { vec4 data[]; } acc;
-layout(set = 0, binding = 3) buffer EnergyOutput { float data[]; } energy;
+layout(set = 0, binding = 2) buffer Potential { float data[]; } potential;
+// Kinetic and potential energy of every particle, half of every pair is attributed to either side
+layout(set = 0, binding = 3) buffer EnergyOutput { vec2 data[]; } energy;
 
-layout(push_constant) uniform SimulationData {
+layout(push_constant) uniform EnergyData {
     uint buffer_size;
-} sd;
+    float G;
+    float softening;
+    // Take the potential from `potential`, calculated with the tree, instead of summing all pairs
+    uint from_tree;
+    // Only sum the potential at this particle into `potential`, with a single workgroup
+    uint target;
+} ed;
+
+#define ALL_PARTICLES 0xffffffffu
+
+shared vec4 _pos_mass[PARALLELISM];
+shared float _phi[PARALLELISM];
+
+void potential_at(uint target, uint li) {
+    vec3 p = pos_mass.data[target].xyz;
+
+    float phi = 0.0;
+    for (uint j = li; j < ed.buffer_size; j += PARALLELISM) {
+        vec4 other = pos_mass.data[j];
+        vec3 diff = other.xyz - p;
+        float dist2 = dot(diff, diff) + (ed.softening * ed.softening);
+        phi -= j == target ? 0.0 : other.w / sqrt(dist2);
+    }
+    _phi[li] = phi;
+    barrier();
+
+    for (uint stride = PARALLELISM / 2; stride > 0; stride /= 2) {
+        if (li < stride) {
+            _phi[li] += _phi[li + stride];
+        }
+        barrier();
+    }
+
+    if (li == 0) {
+        potential.data[target] = ed.G * _phi[0];
+    }
+}
 
 void main() {
     uint gi = gl_GlobalInvocationID.x;
     uint li = gl_LocalInvocationID.x;
 
-    if (gi >= sd.buffer_size) {
+    if (ed.target != ALL_PARTICLES) {
+        potential_at(ed.target, li);
         return;
     }
 
-    vec3 p = pos_mass.data[gi].xyz;
-    float m = pos_mass.data[gi].w;
-    vec3 v = vel.data[gi].xyz;
-    vec3 a = acc.data[gi].xyz;
+    // Every invocation has to take part in loading the tiles, even past the end of the buffer
+    vec3 p = pos_mass.data[min(gi, ed.buffer_size - 1)].xyz;
+
+    float phi = 0.0;
+    if (ed.from_tree != 0) {
+        phi = gi < ed.buffer_size ? potential.data[gi] : 0.0;
+    } else {
+        for (uint i = 0; i < ed.buffer_size; i += PARALLELISM) {
+            // Padding has no mass, so it doesn't contribute
+            _pos_mass[li] = i + li < ed.buffer_size ? pos_mass.data[i + li] : vec4(0.0);
+            barrier();
+
+            for (uint k = 0; k < PARALLELISM; k++) {
+                vec3 diff = _pos_mass[k].xyz - p;
+                float dist2 = dot(diff, diff) + (ed.softening * ed.softening);
+                // The softened self interaction isn't zero
+                float m = i + k == gi ? 0.0 : _pos_mass[k].w;
+                phi -= m / sqrt(dist2);
+            }
 
-    energy.data[gi] = 0.5 * m * dot(v, v) - 0.5 * m * dot(a, p);
+            barrier();
+        }
+        phi *= ed.G;
+    }
+
+    if (gi < ed.buffer_size) {
+        float m = pos_mass.data[gi].w;
+        vec3 v = vel.data[gi].xyz;
+        energy.data[gi] = vec2(0.5 * m * dot(v, v), 0.5 * m * phi);
+    }
 }
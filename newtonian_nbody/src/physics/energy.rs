@@ -2,26 +2,81 @@ use std::sync::Arc;
 
 use hatchery::util::{
     buffer::{AbstractBuffer, SharedBuffer},
-    compute::ComputeShader,
+    compute::{self, ComputeShader, ComputeShaderExecutor},
     ConstructionContext,
 };
 use vulkano::{
     buffer::BufferUsage, descriptor_set::WriteDescriptorSet, device::Device, shader::ShaderModule,
 };
 
-use super::SimulationBuffers;
+use super::{integrator::TimeIntegrator, SimulationBuffers};
 
 hatchery::compute! { "src/physics/energy.glsl", energy }
 
-pub struct EnergyCalculator {
+/// `target` of `energy.glsl` which calculates the energy of all particles
+const ALL_PARTICLES: u32 = u32::MAX;
+
+struct EnergyShader {
     data: Arc<SimulationBuffers>,
-    energy: SharedBuffer<f32>,
+    /// Kinetic and potential energy of every particle
+    energy: SharedBuffer<[f32; 2]>,
+    constants: energy::ty::EnergyData,
+}
+
+impl ComputeShader for EnergyShader {
+    type Constants = energy::ty::EnergyData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        energy::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        match self.constants.target {
+            ALL_PARTICLES => [self.constants.buffer_size.div_ceil(128), 1, 1],
+            _ => [1, 1, 1],
+        }
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.velocity.buffer()),
+            WriteDescriptorSet::buffer(2, self.data.potential.buffer()),
+            WriteDescriptorSet::buffer(3, self.energy.buffer()),
+        ]
+    }
+}
+
+/// Kinetic and softened potential energy of all particles
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Energy {
+    pub kinetic: f64,
+    pub potential: f64,
+}
+
+impl Energy {
+    pub fn total(&self) -> f64 {
+        self.kinetic + self.potential
+    }
+}
+
+/// Calculates the energy of the simulation. The potential is the exact softened sum over all
+/// pairs `-G m_i m_j / sqrt(r² + ε²)` in O(N²), or a walk of the Barnes-Hut tree in O(N log N)
+/// if the force solver builds one, with the same opening angle as the forces. The energies of the
+/// particles are summed on the CPU in f64.
+pub struct EnergyCalculator {
+    shader: ComputeShaderExecutor<EnergyShader>,
+    energy: Energy,
 }
 
 impl EnergyCalculator {
-    pub fn new(data: Arc<SimulationBuffers>, context: &ConstructionContext) -> Self {
-        let num_particles = data.num_particles;
-        Self {
+    pub fn new(context: &ConstructionContext, data: Arc<SimulationBuffers>) -> Self {
+        let num_particles = data.capacity;
+        let shader = EnergyShader {
             data,
             energy: SharedBuffer::from_iter(
                 context,
@@ -29,43 +84,79 @@ impl EnergyCalculator {
                     storage_buffer: true,
                     ..BufferUsage::empty()
                 },
-                vec![0.0; num_particles as usize],
+                vec![[0.0; 2]; num_particles as usize],
             ),
+            constants: energy::ty::EnergyData {
+                buffer_size: num_particles,
+                G: 0.0,
+                softening: 0.0,
+                from_tree: 0,
+                target: ALL_PARTICLES,
+            },
+        };
+
+        Self {
+            shader: ComputeShaderExecutor::new(context, shader),
+            energy: Energy::default(),
         }
     }
 
-    pub fn get_total_energy(&self) -> f32 {
-        self.energy.typed_buffer().read().unwrap().iter().sum()
-    }
-}
+    /// Calculate the energy at the current positions and velocities, with the gravity of the
+    /// integrator. Waits for the GPU.
+    pub fn calculate(
+        &mut self,
+        context: &ConstructionContext,
+        integrator: &mut TimeIntegrator,
+    ) -> Energy {
+        let mut builder = compute::begin(context);
 
-impl ComputeShader for EnergyCalculator {
-    type Constants = energy::ty::SimulationData;
+        let from_tree = integrator.record_potential(&mut builder);
+        let num_particles = self.shader.data.num_particles();
+        let constants = &mut self.shader.constants;
+        constants.buffer_size = num_particles;
+        constants.G = integrator.g();
+        constants.softening = integrator.softening();
+        constants.from_tree = from_tree as u32;
+        self.shader.record(&mut builder);
 
-    fn push_constants(&self) -> Option<Self::Constants> {
-        Some(Self::Constants {
-            buffer_size: self.data.num_particles,
-        })
-    }
+        compute::submit(context, builder);
 
-    fn entry_point() -> &'static str {
-        "main"
+        let energies = self.shader.energy.typed_buffer().read().unwrap();
+        let energies = &energies[..num_particles as usize];
+        self.energy = energies
+            .iter()
+            .fold(Energy::default(), |sum, [k, p]| Energy {
+                kinetic: sum.kinetic + *k as f64,
+                potential: sum.potential + *p as f64,
+            });
+        self.energy
     }
 
-    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
-        energy::load(device).unwrap()
-    }
+    /// Sum the softened potential per unit mass at the particle at `index` directly, into
+    /// `SimulationBuffers::potential`. Waits for the GPU.
+    pub fn potential_at(
+        &mut self,
+        context: &ConstructionContext,
+        index: u32,
+        g: f32,
+        softening: f32,
+    ) {
+        let mut builder = compute::begin(context);
 
-    fn dispatch_size(&self) -> [u32; 3] {
-        [self.data.num_particles.div_ceil(128), 1, 1]
+        let num_particles = self.shader.data.num_particles();
+        let constants = &mut self.shader.constants;
+        constants.buffer_size = num_particles;
+        constants.G = g;
+        constants.softening = softening;
+        constants.target = index;
+        self.shader.record(&mut builder);
+        self.shader.constants.target = ALL_PARTICLES;
+
+        compute::submit(context, builder);
     }
 
-    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
-        vec![
-            WriteDescriptorSet::buffer(0, self.data.position_mass.buffer()),
-            WriteDescriptorSet::buffer(1, self.data.velocity.buffer()),
-            WriteDescriptorSet::buffer(2, self.data.acceleration.buffer()),
-            WriteDescriptorSet::buffer(3, self.energy.buffer()),
-        ]
+    /// Energy of the last calculation
+    pub fn energy(&self) -> Energy {
+        self.energy
     }
 }
@@ -0,0 +1,434 @@
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Point3, Vector3, Zero};
+use hatchery::{inspect::Inspect, util::ConstructionContext};
+use rayon::prelude::*;
+
+use super::{
+    cpu::{Body, Gravity, Octree, NO_CHILD},
+    SimulationBuffers,
+};
+
+/// Cartesian multi-indices `k = (kx, ky, kz)` with `|k| <= order`, sorted by degree, and the
+/// coefficient tables of the translation operators
+struct Terms {
+    exponents: Vec<[usize; 3]>,
+    /// Index of `k - e_i` for every term and axis
+    lowered: Vec<[Option<usize>; 3]>,
+    /// `(k, l, k - l, C(k, l))` for every `l <= k`, shared by M2M and L2L
+    shifts: Vec<(usize, usize, usize, f64)>,
+    /// `(k, n, k + n, (-1)^|k| C(k + n, n))` for every `|k| + |n| <= order`, used by M2L
+    transfers: Vec<(usize, usize, usize, f64)>,
+}
+
+impl Terms {
+    fn new(order: usize) -> Self {
+        let mut exponents = Vec::new();
+        for degree in 0..=order {
+            for x in (0..=degree).rev() {
+                for y in (0..=degree - x).rev() {
+                    exponents.push([x, y, degree - x - y]);
+                }
+            }
+        }
+
+        let index = |k: [usize; 3]| exponents.iter().position(|&e| e == k);
+        let lowered = exponents
+            .iter()
+            .map(|&k| {
+                [0, 1, 2].map(|axis| {
+                    let mut lower = k;
+                    lower[axis] = k[axis].checked_sub(1)?;
+                    index(lower)
+                })
+            })
+            .collect();
+
+        // Pascal's triangle, sums of two exponents are at most twice the order
+        let mut binomials = vec![vec![1.0; 2 * order + 1]; 2 * order + 1];
+        for n in 1..=2 * order {
+            for k in 1..n {
+                binomials[n][k] = binomials[n - 1][k - 1] + binomials[n - 1][k];
+            }
+        }
+        let binomial = |n: [usize; 3], k: [usize; 3]| -> f64 {
+            (0..3).map(|axis| binomials[n[axis]][k[axis]]).product()
+        };
+
+        let mut shifts = Vec::new();
+        let mut transfers = Vec::new();
+        for (ki, &k) in exponents.iter().enumerate() {
+            for (li, &l) in exponents.iter().enumerate() {
+                if (0..3).all(|axis| l[axis] <= k[axis]) {
+                    let difference = index([0, 1, 2].map(|axis| k[axis] - l[axis])).unwrap();
+                    shifts.push((ki, li, difference, binomial(k, l)));
+                }
+
+                let sum = [0, 1, 2].map(|axis| k[axis] + l[axis]);
+                if let Some(sum_index) = index(sum) {
+                    let sign = (-1.0f64).powi(degree(k) as i32);
+                    transfers.push((ki, li, sum_index, sign * binomial(sum, l)));
+                }
+            }
+        }
+
+        Self {
+            exponents,
+            lowered,
+            shifts,
+            transfers,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.exponents.len()
+    }
+
+    /// `d^k` for every term
+    fn monomials(&self, d: Vector3<f64>) -> Vec<f64> {
+        let mut monomials = vec![1.0; self.len()];
+        for k in 1..self.len() {
+            let axis = (0..3).find(|&axis| self.exponents[k][axis] > 0).unwrap();
+            monomials[k] = monomials[self.lowered[k][axis].unwrap()] * d[axis];
+        }
+        monomials
+    }
+
+    /// Taylor coefficients `D^k (|r|² + ε²)^(-1/2) / k!` of the softened kernel for every term,
+    /// with the recurrence of Lindsay and Krasny (2001)
+    fn derivatives(&self, r: Vector3<f64>, softening: f64) -> Vec<f64> {
+        let r2 = r.magnitude2() + softening * softening;
+        let mut derivatives = vec![0.0; self.len()];
+        derivatives[0] = 1.0 / r2.sqrt();
+
+        for k in 1..self.len() {
+            let n = degree(self.exponents[k]) as f64;
+            let mut sum = 0.0;
+            for axis in 0..3 {
+                if let Some(lower) = self.lowered[k][axis] {
+                    sum += (2.0 * n - 1.0) * r[axis] * derivatives[lower];
+                    if let Some(lower) = self.lowered[lower][axis] {
+                        sum += (n - 1.0) * derivatives[lower];
+                    }
+                }
+            }
+            derivatives[k] = -sum / (n * r2);
+        }
+
+        derivatives
+    }
+
+    /// P2M, moments `sum m d^k` of bodies around `center`
+    fn p2m(&self, bodies: &[Body], indices: &[usize], center: Point3<f64>) -> Vec<f64> {
+        let mut multipole = vec![0.0; self.len()];
+        for &i in indices {
+            let body = &bodies[i];
+            let monomials = self.monomials(body.position - center);
+            for (moment, monomial) in multipole.iter_mut().zip(monomials) {
+                *moment += body.mass * monomial;
+            }
+        }
+        multipole
+    }
+
+    /// M2M, add the multipole of a child offset by `shift` from its parent to the parent
+    fn m2m(&self, child: &[f64], shift: Vector3<f64>, parent: &mut [f64]) {
+        let monomials = self.monomials(shift);
+        for &(k, l, difference, binomial) in &self.shifts {
+            parent[k] += binomial * monomials[difference] * child[l];
+        }
+    }
+
+    /// M2L, add the field of a multipole at `offset` from the center of a local expansion
+    fn m2l(&self, multipole: &[f64], offset: Vector3<f64>, softening: f64, local: &mut [f64]) {
+        let derivatives = self.derivatives(offset, softening);
+        for &(k, n, sum, coefficient) in &self.transfers {
+            local[n] += coefficient * multipole[k] * derivatives[sum];
+        }
+    }
+
+    /// L2L, add the local expansion of a parent to a child offset by `shift` from it
+    fn l2l(&self, parent: &[f64], shift: Vector3<f64>, child: &mut [f64]) {
+        let monomials = self.monomials(shift);
+        for &(k, l, difference, binomial) in &self.shifts {
+            child[l] += binomial * monomials[difference] * parent[k];
+        }
+    }
+
+    /// L2P, gradient of the local expansion at `offset` from its center
+    fn l2p(&self, local: &[f64], offset: Vector3<f64>) -> Vector3<f64> {
+        let monomials = self.monomials(offset);
+        let mut gradient = Vector3::zero();
+        for (n, lowered) in self.lowered.iter().enumerate() {
+            for axis in 0..3 {
+                if let Some(lower) = lowered[axis] {
+                    gradient[axis] += self.exponents[n][axis] as f64 * local[n] * monomials[lower];
+                }
+            }
+        }
+        gradient
+    }
+}
+
+/// Larger leaves than Barnes-Hut, which trades cheap P2P for fewer M2L
+const LEAF_SIZE: usize = 32;
+
+fn degree(k: [usize; 3]) -> usize {
+    k.iter().sum()
+}
+
+/// Fast multipole method in Cartesian Taylor expansions, O(N) for a fixed order. Cells of an
+/// octree interact through their expansions if they are well separated by the multipole
+/// acceptance criterion `r_a + r_b < theta * d`, and body by body otherwise. The softened kernel
+/// is expanded, so the results match the direct sum even for cells closer than the softening.
+pub struct Fmm {
+    terms: Terms,
+    theta: f64,
+}
+
+impl Fmm {
+    pub fn new(order: usize, theta: f64) -> Self {
+        Self {
+            terms: Terms::new(order.max(1)),
+            theta,
+        }
+    }
+
+    pub fn accelerations(&self, bodies: &[Body], gravity: Gravity) -> Vec<Vector3<f64>> {
+        let mut accelerations = vec![Vector3::zero(); bodies.len()];
+        let tree = Octree::with_leaf_size(bodies, LEAF_SIZE);
+        if tree.nodes.is_empty() {
+            return accelerations;
+        }
+
+        let terms = &self.terms;
+        let nodes = &tree.nodes;
+        let indices = |node: usize| {
+            let (start, end) = nodes[node].bodies;
+            &tree.order[start..end]
+        };
+        let children = |node: usize| {
+            nodes[node]
+                .children
+                .into_iter()
+                .filter(|&child| child != NO_CHILD)
+                .map(|child| child as usize)
+        };
+
+        // Expansions are centered on the center of mass, the radius bounds the bodies around it
+        let centers: Vec<Point3<f64>> = nodes.iter().map(|node| node.center_of_mass).collect();
+        let radii: Vec<f64> = (0..nodes.len())
+            .into_par_iter()
+            .map(|node| {
+                indices(node)
+                    .iter()
+                    .map(|&i| (bodies[i].position - centers[node]).magnitude())
+                    .fold(0.0, f64::max)
+            })
+            .collect();
+
+        let (m2l, p2p) = self.interactions(&tree, &centers, &radii);
+
+        // P2M at the leaves, M2M up to the root. Children always come after their parent.
+        let mut multipoles: Vec<Vec<f64>> = (0..nodes.len())
+            .into_par_iter()
+            .map(|node| match nodes[node].is_leaf() {
+                true => terms.p2m(bodies, indices(node), centers[node]),
+                false => Vec::new(),
+            })
+            .collect();
+        for node in (0..nodes.len()).rev() {
+            if !nodes[node].is_leaf() {
+                let mut multipole = vec![0.0; terms.len()];
+                for child in children(node) {
+                    let shift = centers[child] - centers[node];
+                    terms.m2m(&multipoles[child], shift, &mut multipole);
+                }
+                multipoles[node] = multipole;
+            }
+        }
+
+        // M2L into every cell, L2L down to the leaves
+        let mut locals: Vec<Vec<f64>> = m2l
+            .par_iter()
+            .enumerate()
+            .map(|(node, sources)| {
+                let mut local = vec![0.0; terms.len()];
+                for &source in sources {
+                    let offset = centers[node] - centers[source];
+                    terms.m2l(&multipoles[source], offset, gravity.softening, &mut local);
+                }
+                local
+            })
+            .collect();
+        for node in 0..nodes.len() {
+            for child in children(node) {
+                let (parents, rest) = locals.split_at_mut(child);
+                let shift = centers[child] - centers[node];
+                terms.l2l(&parents[node], shift, &mut rest[0]);
+            }
+        }
+
+        // L2P and P2P for the bodies of every leaf
+        let leaf_accelerations: Vec<(usize, Vector3<f64>)> = p2p
+            .par_iter()
+            .enumerate()
+            .filter(|(node, _)| nodes[*node].is_leaf())
+            .flat_map_iter(|(node, sources)| {
+                let locals = &locals;
+                let centers = &centers;
+                indices(node).iter().map(move |&target| {
+                    let position = bodies[target].position;
+                    let far = terms.l2p(&locals[node], position - centers[node]) * gravity.g;
+                    let near = sources
+                        .iter()
+                        .flat_map(|&source| indices(source))
+                        .filter(|&&i| i != target)
+                        .fold(Vector3::zero(), |acceleration, &i| {
+                            let body = &bodies[i];
+                            acceleration + gravity.acceleration(position, body.position, body.mass)
+                        });
+                    (target, far + near)
+                })
+            })
+            .collect();
+
+        for (target, acceleration) in leaf_accelerations {
+            accelerations[target] = acceleration;
+        }
+        accelerations
+    }
+
+    /// Dual tree walk from the root, returns the cells every cell gets multipoles from and the
+    /// leaves every leaf interacts with directly
+    fn interactions(
+        &self,
+        tree: &Octree,
+        centers: &[Point3<f64>],
+        radii: &[f64],
+    ) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        let nodes = &tree.nodes;
+        let children = |node: usize| {
+            nodes[node]
+                .children
+                .into_iter()
+                .filter(|&child| child != NO_CHILD)
+                .map(|child| child as usize)
+        };
+
+        let mut m2l = vec![Vec::new(); nodes.len()];
+        let mut p2p = vec![Vec::new(); nodes.len()];
+        let mut stack = vec![(0, 0)];
+        while let Some((target, source)) = stack.pop() {
+            let distance = (centers[target] - centers[source]).magnitude();
+            if target != source && radii[target] + radii[source] < self.theta * distance {
+                m2l[target].push(source);
+                continue;
+            }
+
+            match (nodes[target].is_leaf(), nodes[source].is_leaf()) {
+                (true, true) => p2p[target].push(source),
+                // Cells interacting with themselves split into all pairs of their children
+                _ if target == source => {
+                    for a in children(target) {
+                        stack.extend(children(target).map(|b| (a, b)));
+                    }
+                }
+                // Otherwise split the larger cell
+                (false, source_leaf) if source_leaf || radii[target] >= radii[source] => {
+                    stack.extend(children(target).map(|child| (child, source)));
+                }
+                _ => stack.extend(children(source).map(|child| (target, child))),
+            }
+        }
+
+        (m2l, p2p)
+    }
+}
+
+/// Runs `Fmm` on the CPU, between the drift and kick of a step. The drifted positions are read
+/// back and the accelerations uploaded to `SimulationBuffers::acceleration`, so the integrator
+/// has to split the step into two submissions.
+#[derive(Inspect)]
+pub struct FastMultipole {
+    #[inspect(skip)]
+    data: Arc<SimulationBuffers>,
+    /// Highest power of the expansions, the error falls roughly like `theta^(order + 1)`
+    #[inspect(label = "Expansion order", range = 1.0..=8.0)]
+    order: usize,
+    /// Multipole acceptance criterion, larger values let closer cells use their expansions
+    #[inspect(label = "Acceptance", range = 0.1..=1.0, speed = 0.01)]
+    theta: f32,
+}
+
+impl FastMultipole {
+    pub fn new(data: Arc<SimulationBuffers>, order: usize, theta: f32) -> Self {
+        Self { data, order, theta }
+    }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn theta(&self) -> f32 {
+        self.theta
+    }
+
+    pub fn set_order(&mut self, order: usize) {
+        self.order = order;
+    }
+
+    pub fn set_theta(&mut self, theta: f32) {
+        self.theta = theta;
+    }
+
+    /// Calculate the accelerations from the current positions, which blocks until done
+    pub fn compute(&self, context: &ConstructionContext, g: f32, softening: f32) {
+        let bodies: Vec<Body> = self
+            .data
+            .particles(context)
+            .iter()
+            .map(Body::from)
+            .collect();
+        let accelerations = Fmm::new(self.order, self.theta as f64)
+            .accelerations(&bodies, Gravity::new(g, softening));
+
+        self.data.write_accelerations(
+            context,
+            accelerations
+                .iter()
+                .map(|acceleration| acceleration.cast().unwrap()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::EuclideanSpace;
+
+    use super::*;
+    use crate::physics::cpu::{
+        tests::{cluster, max_error},
+        CpuSolver,
+    };
+
+    #[test]
+    fn error_falls_with_order() {
+        let bodies = cluster(2000, Point3::origin(), 1.0, 2);
+        let gravity = Gravity::new(1.0, 0.01);
+        let direct = CpuSolver::Direct.accelerations(&bodies, gravity);
+
+        let errors: Vec<f64> = (1..=6)
+            .map(|order| {
+                max_error(
+                    &Fmm::new(order, 0.5).accelerations(&bodies, gravity),
+                    &direct,
+                )
+            })
+            .collect();
+
+        for pair in errors.windows(2) {
+            assert!(pair[1] < pair[0], "errors by order {errors:?}");
+        }
+    }
+}
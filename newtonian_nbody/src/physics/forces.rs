@@ -0,0 +1,273 @@
+use std::sync::Arc;
+
+use hatchery::{
+    inspect::Inspect,
+    util::{compute, ConstructionContext},
+};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+
+use super::{
+    barnes_hut::BarnesHut,
+    cpu::CpuSolver,
+    direct::DirectSummation,
+    fmm::FastMultipole,
+    pm::{MeshResolution, ParticleMesh},
+    ForceCalculator, ForceSolver, Precision, SimulationBuffers,
+};
+
+/// Opening angle the Barnes-Hut solver starts with
+const THETA: f32 = 0.5;
+const FMM_ORDER: usize = 4;
+const FMM_THETA: f32 = 0.5;
+const BOX_SIZE: f32 = 16.0;
+/// Split scale of TreePM in mesh cells, as in GADGET
+const SPLIT: f32 = 1.25;
+
+/// Everything the accelerations depend on besides the particles, the integrator calculates them
+/// again when any of these change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForceParameters {
+    pub solver: ForceSolver,
+    pub g: f32,
+    pub softening: f32,
+    pub periodic: bool,
+    pub box_size: f32,
+}
+
+/// The force stage of the integrators, calculates the accelerations with the selected solver
+#[derive(Inspect)]
+pub struct Forces {
+    #[inspect(label = "Gravity", range = 0.0..=1.0, speed = 0.001)]
+    g: f32,
+    #[inspect(range = 1e-4..=1.0, log)]
+    softening: f32,
+    /// Wrap the positions around the box of the particle mesh and TreePM solvers. The other
+    /// solvers don't see the periodic images, the periodic ones always wrap.
+    #[inspect(label = "Periodic box", on_change = "require_periodic")]
+    periodic: bool,
+    #[inspect(label = "Box size", range = 0.1..=1000.0, log)]
+    box_size: f32,
+    #[inspect(label = "Force solver", on_change = "require_periodic")]
+    solver: ForceSolver,
+    #[inspect(skip)]
+    direct: DirectSummation,
+    #[inspect(label = "Barnes-Hut")]
+    barnes_hut: BarnesHut,
+    #[inspect(label = "Fast multipole")]
+    fmm: FastMultipole,
+    #[inspect(label = "Particle mesh")]
+    pm: ParticleMesh,
+}
+
+impl Forces {
+    pub fn new(
+        context: &ConstructionContext,
+        data: Arc<SimulationBuffers>,
+        g: f32,
+        softening: f32,
+    ) -> Self {
+        Self {
+            g,
+            softening,
+            periodic: false,
+            box_size: BOX_SIZE,
+            solver: ForceSolver::default(),
+            direct: DirectSummation::new(context, data.clone()),
+            barnes_hut: BarnesHut::new(context, data.clone(), THETA),
+            fmm: FastMultipole::new(data.clone(), FMM_ORDER, FMM_THETA),
+            pm: ParticleMesh::new(context, data, MeshResolution::default(), BOX_SIZE, SPLIT),
+        }
+    }
+
+    /// Record the accelerations at the current positions. The fast multipole solver needs them on
+    /// the CPU, so it submits everything recorded so far and starts a new builder.
+    pub fn record(
+        &mut self,
+        context: &ConstructionContext,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        match self.solver {
+            ForceSolver::Direct => self.direct.record(builder, self.g, self.softening),
+            ForceSolver::BarnesHut => self.barnes_hut.record(builder, self.g, self.softening),
+            ForceSolver::ParticleMesh => {
+                self.pm.set_box_size(self.box_size);
+                self.pm.record(builder, self.g, self.softening);
+            }
+            ForceSolver::TreePm => {
+                self.pm.set_box_size(self.box_size);
+                self.pm.record_long_range(builder, self.g);
+                let split = self.pm.split_radius();
+                self.barnes_hut.record_short_range(
+                    builder,
+                    self.g,
+                    self.softening,
+                    split,
+                    self.box_size,
+                );
+            }
+            ForceSolver::FastMultipole => {
+                let recorded = std::mem::replace(builder, compute::begin(context));
+                compute::submit(context, recorded);
+                self.fmm.compute(context, self.g, self.softening);
+            }
+        }
+    }
+
+    /// Record the accelerations of the active set of the block timesteps. Only direct summation
+    /// skips the inactive particles, the other solvers calculate all of them.
+    pub fn record_active(
+        &mut self,
+        context: &ConstructionContext,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        match self.solver {
+            ForceSolver::Direct => self.direct.record_active(builder, self.g, self.softening),
+            _ => self.record(context, builder),
+        }
+    }
+
+    /// Record the accelerations and their time derivatives. Only direct summation calculates the
+    /// jerk, so this ignores the selected solver.
+    pub fn record_with_jerk(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        self.direct
+            .record_with_jerk(builder, self.g, self.softening);
+    }
+
+    /// Record the potential of every particle with the tree, if the selected solver has one.
+    /// Returns whether it did, the caller sums the potential directly otherwise.
+    pub fn record_potential(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> bool {
+        match self.solver {
+            ForceSolver::BarnesHut | ForceSolver::TreePm => {
+                self.barnes_hut
+                    .record_potential(builder, self.g, self.softening);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The CPU solver for the same force law as the selected solver, summed exactly where the
+    /// GPU approximates. Direct summation without a box, the mesh of the same resolution with
+    /// an exact short range sum in a periodic box.
+    pub fn reference_solver(&self) -> CpuSolver {
+        let resolution = self.pm.resolution().cells() as usize;
+        let box_size = self.box_size as f64;
+
+        match self.solver {
+            ForceSolver::Direct | ForceSolver::BarnesHut | ForceSolver::FastMultipole => {
+                CpuSolver::Direct
+            }
+            ForceSolver::ParticleMesh => CpuSolver::ParticleMesh {
+                resolution,
+                box_size,
+            },
+            ForceSolver::TreePm => {
+                // The split radius in world units depends on the box
+                let cell = box_size / resolution as f64;
+                CpuSolver::TreePm {
+                    resolution,
+                    box_size,
+                    split: self.pm.split() as f64 * cell,
+                    theta: 0.0,
+                }
+            }
+        }
+    }
+
+    /// Precision of direct summation, the other solvers always sum in f32
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.direct.set_precision(precision);
+    }
+
+    /// Size of the box the positions wrap around, 0 if they don't
+    pub fn wrap_size(&self) -> f32 {
+        if self.periodic {
+            self.box_size
+        } else {
+            0.0
+        }
+    }
+
+    pub fn parameters(&self) -> ForceParameters {
+        ForceParameters {
+            solver: self.solver,
+            g: self.g,
+            softening: self.softening,
+            periodic: self.periodic,
+            box_size: self.box_size,
+        }
+    }
+
+    pub fn g(&self) -> f32 {
+        self.g
+    }
+
+    pub fn softening(&self) -> f32 {
+        self.softening
+    }
+
+    pub fn solver(&self) -> ForceSolver {
+        self.solver
+    }
+
+    pub fn periodic(&self) -> bool {
+        self.periodic
+    }
+
+    pub fn box_size(&self) -> f32 {
+        self.box_size
+    }
+
+    pub fn set_g(&mut self, g: f32) {
+        self.g = g;
+    }
+
+    pub fn set_softening(&mut self, softening: f32) {
+        self.softening = softening;
+    }
+
+    pub fn set_periodic(&mut self, periodic: bool) {
+        self.periodic = periodic;
+        self.require_periodic();
+    }
+
+    pub fn set_box_size(&mut self, box_size: f32) {
+        self.box_size = box_size;
+    }
+
+    pub fn set_solver(&mut self, solver: ForceSolver) {
+        self.solver = solver;
+        self.require_periodic();
+    }
+
+    /// The particle mesh and TreePM solvers only see the particles inside their box
+    fn require_periodic(&mut self) {
+        self.periodic |= self.solver.periodic();
+    }
+
+    pub fn set_theta(&mut self, theta: f32) {
+        self.barnes_hut.set_theta(theta);
+    }
+
+    pub fn set_fmm_order(&mut self, order: usize) {
+        self.fmm.set_order(order);
+    }
+
+    pub fn set_fmm_theta(&mut self, theta: f32) {
+        self.fmm.set_theta(theta);
+    }
+
+    pub fn set_mesh_resolution(&mut self, resolution: MeshResolution) {
+        self.pm.set_resolution(resolution);
+    }
+
+    pub fn set_split(&mut self, split: f32) {
+        self.pm.set_split(split);
+    }
+}
@@ -0,0 +1,419 @@
+use std::sync::Arc;
+
+use hatchery::{
+    inspect::Inspect,
+    util::{compute, ConstructionContext},
+};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+
+use super::{
+    block::{BlockTimesteps, LevelStatistics},
+    cpu::CpuSolver,
+    forces::{ForceParameters, Forces},
+    pm::MeshResolution,
+    stages::Stages,
+    ForceSolver, Precision, SimulationBuffers,
+};
+
+type Builder = AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>;
+
+/// A time integration scheme, built from the GPU stages and the force stage in between
+pub trait Integrator {
+    /// Record the forces a step starts from, before the first step and after switching from
+    /// another scheme. Schemes ending their steps with a force stage can rely on it afterwards.
+    fn initial_forces(
+        &self,
+        forces: &mut Forces,
+        context: &ConstructionContext,
+        builder: &mut Builder,
+    ) {
+        forces.record(context, builder);
+    }
+
+    /// Whether the last force stage of a step sees the positions the step ends on, so the
+    /// accelerations read back after it belong to them
+    fn synchronized_forces(&self) -> bool {
+        true
+    }
+
+    /// Record one step of length `dt`
+    fn record(
+        &self,
+        dt: f32,
+        stages: &mut Stages,
+        forces: &mut Forces,
+        context: &ConstructionContext,
+        builder: &mut Builder,
+    );
+}
+
+/// Leapfrog with a half kick on either side of the drift, the velocity Verlet scheme
+pub struct KickDriftKick;
+
+impl Integrator for KickDriftKick {
+    fn record(
+        &self,
+        dt: f32,
+        stages: &mut Stages,
+        forces: &mut Forces,
+        context: &ConstructionContext,
+        builder: &mut Builder,
+    ) {
+        stages.kick(builder, dt / 2.0);
+        stages.drift(builder, dt);
+        forces.record(context, builder);
+        stages.kick(builder, dt / 2.0);
+    }
+}
+
+/// Leapfrog with a half drift on either side of the kick, the position Verlet scheme
+pub struct DriftKickDrift;
+
+impl Integrator for DriftKickDrift {
+    /// The forces are calculated in the middle of the step
+    fn initial_forces(&self, _: &mut Forces, _: &ConstructionContext, _: &mut Builder) {}
+
+    fn synchronized_forces(&self) -> bool {
+        false
+    }
+
+    fn record(
+        &self,
+        dt: f32,
+        stages: &mut Stages,
+        forces: &mut Forces,
+        context: &ConstructionContext,
+        builder: &mut Builder,
+    ) {
+        stages.drift(builder, dt / 2.0);
+        forces.record(context, builder);
+        stages.kick(builder, dt);
+        stages.drift(builder, dt / 2.0);
+    }
+}
+
+/// The classical fourth order Runge-Kutta method. Four force evaluations per step and not
+/// symplectic, so the energy drifts steadily instead of oscillating.
+pub struct RungeKutta4;
+
+impl Integrator for RungeKutta4 {
+    fn record(
+        &self,
+        dt: f32,
+        stages: &mut Stages,
+        forces: &mut Forces,
+        context: &ConstructionContext,
+        builder: &mut Builder,
+    ) {
+        stages.save(builder);
+        stages.runge_kutta(builder, dt / 2.0, 1.0 / 6.0);
+        forces.record(context, builder);
+        stages.runge_kutta(builder, dt / 2.0, 2.0 / 6.0);
+        forces.record(context, builder);
+        stages.runge_kutta(builder, dt, 2.0 / 6.0);
+        forces.record(context, builder);
+        stages.runge_kutta_finish(builder, dt, 1.0 / 6.0);
+        forces.record(context, builder);
+    }
+}
+
+/// Fourth order symplectic scheme of Yoshida (1990), three leapfrog steps of which the middle
+/// one goes backwards in time
+pub struct Yoshida4;
+
+impl Integrator for Yoshida4 {
+    fn record(
+        &self,
+        dt: f32,
+        stages: &mut Stages,
+        forces: &mut Forces,
+        context: &ConstructionContext,
+        builder: &mut Builder,
+    ) {
+        let w1 = 1.0 / (2.0 - 2f32.cbrt());
+        let w0 = -2f32.cbrt() * w1;
+
+        stages.kick(builder, w1 / 2.0 * dt);
+        stages.drift(builder, w1 * dt);
+        forces.record(context, builder);
+        stages.kick(builder, (w0 + w1) / 2.0 * dt);
+        stages.drift(builder, w0 * dt);
+        forces.record(context, builder);
+        stages.kick(builder, (w0 + w1) / 2.0 * dt);
+        stages.drift(builder, w1 * dt);
+        forces.record(context, builder);
+        stages.kick(builder, w1 / 2.0 * dt);
+    }
+}
+
+/// Fourth order Hermite predictor-corrector (Makino & Aarseth 1992), the standard of collisional
+/// codes. Needs the jerk, so the forces always come from direct summation.
+pub struct Hermite4;
+
+impl Integrator for Hermite4 {
+    fn initial_forces(&self, forces: &mut Forces, _: &ConstructionContext, builder: &mut Builder) {
+        forces.record_with_jerk(builder);
+    }
+
+    /// The forces are calculated at the predicted positions, before the correction
+    fn synchronized_forces(&self) -> bool {
+        false
+    }
+
+    fn record(
+        &self,
+        dt: f32,
+        stages: &mut Stages,
+        forces: &mut Forces,
+        context: &ConstructionContext,
+        builder: &mut Builder,
+    ) {
+        stages.save(builder);
+        stages.predict(builder, dt);
+        forces.record_with_jerk(builder);
+        stages.correct(builder, dt);
+    }
+}
+
+/// Which `Integrator` advances the simulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Inspect)]
+pub enum Scheme {
+    /// Second order and symplectic, one force evaluation per step
+    #[default]
+    #[inspect(label = "Leapfrog (KDK)")]
+    KickDriftKick,
+    #[inspect(label = "Leapfrog (DKD)")]
+    DriftKickDrift,
+    /// Fourth order, four force evaluations per step
+    #[inspect(label = "Runge-Kutta 4")]
+    RungeKutta4,
+    /// Fourth order and symplectic, three force evaluations per step
+    #[inspect(label = "Yoshida 4")]
+    Yoshida4,
+    /// Fourth order with one force and jerk evaluation per step, always uses direct summation
+    #[inspect(label = "Hermite 4")]
+    Hermite4,
+}
+
+impl Scheme {
+    pub fn integrator(&self) -> &'static dyn Integrator {
+        match self {
+            Scheme::KickDriftKick => &KickDriftKick,
+            Scheme::DriftKickDrift => &DriftKickDrift,
+            Scheme::RungeKutta4 => &RungeKutta4,
+            Scheme::Yoshida4 => &Yoshida4,
+            Scheme::Hermite4 => &Hermite4,
+        }
+    }
+}
+
+/// Advances the simulation with the selected scheme and force solver. A whole step is recorded
+/// into a single submission, unless the solver runs on the CPU.
+#[derive(Inspect)]
+pub struct TimeIntegrator {
+    #[inspect(label = "Integrator")]
+    scheme: Scheme,
+    #[inspect(label = "Time step", range = 1e-5..=0.1, log)]
+    dt: f32,
+    /// Precision of the positions and of direct summation
+    precision: Precision,
+    forces: Forces,
+    #[inspect(label = "Block timesteps")]
+    block: BlockTimesteps,
+    #[inspect(skip)]
+    stages: Stages,
+    /// Scheme of the last step, the forces have to be recalculated when it changes
+    #[inspect(skip)]
+    last_scheme: Option<Scheme>,
+    /// Force parameters of the last step, the forces have to be recalculated when they change
+    #[inspect(skip)]
+    last_parameters: Option<ForceParameters>,
+}
+
+impl TimeIntegrator {
+    pub fn new(
+        context: &ConstructionContext,
+        data: Arc<SimulationBuffers>,
+        dt: f32,
+        g: f32,
+        softening: f32,
+    ) -> Self {
+        Self {
+            scheme: Scheme::default(),
+            dt,
+            precision: Precision::default(),
+            forces: Forces::new(context, data.clone(), g, softening),
+            block: BlockTimesteps::new(context, data.clone()),
+            stages: Stages::new(context, data),
+            last_scheme: None,
+            last_parameters: None,
+        }
+    }
+
+    /// Advance the simulation by one time step
+    pub fn step(&mut self, context: &ConstructionContext) {
+        let mut builder = compute::begin(context);
+
+        let precision = self.precision.available(context);
+        self.forces.set_precision(precision);
+        self.stages.set_precision(precision);
+        self.block.set_precision(precision);
+
+        let parameters = Some(self.forces.parameters());
+        let stale = self.last_parameters != parameters;
+        self.last_parameters = parameters;
+
+        if self.block.enabled() {
+            if self.last_scheme.take().is_some() || stale {
+                self.block.restart();
+            }
+            self.block
+                .record(self.dt, &mut self.forces, context, &mut builder);
+            compute::submit(context, builder);
+            self.block.read_statistics(context);
+            return;
+        }
+
+        let integrator = self.scheme.integrator();
+        self.stages.set_box_size(self.forces.wrap_size());
+        if stale || self.last_scheme != Some(self.scheme) {
+            integrator.initial_forces(&mut self.forces, context, &mut builder);
+            self.last_scheme = Some(self.scheme);
+        }
+
+        integrator.record(
+            self.dt,
+            &mut self.stages,
+            &mut self.forces,
+            context,
+            &mut builder,
+        );
+
+        compute::submit(context, builder);
+    }
+
+    /// Calculate the forces of all particles again at the start of the next step, after the
+    /// particles were changed outside of the integrator
+    pub fn invalidate_forces(&mut self) {
+        self.last_scheme = None;
+        self.block.restart();
+    }
+
+    /// Calculate the accelerations at the current positions with the selected solver, for reading
+    /// them back. The next step calculates the forces it starts from again.
+    pub fn calculate_forces(&mut self, context: &ConstructionContext) {
+        let mut builder = compute::begin(context);
+        self.forces.record(context, &mut builder);
+        compute::submit(context, builder);
+        self.invalidate_forces();
+    }
+
+    /// Whether the accelerations in the buffers belong to the current positions. Block timesteps
+    /// and some schemes leave them from the middle of the step.
+    pub fn synchronized_forces(&self) -> bool {
+        !self.block.enabled() && self.scheme.integrator().synchronized_forces()
+    }
+
+    /// Record the potential of every particle into `SimulationBuffers::potential` with the tree of
+    /// the force solver, if it has one
+    pub fn record_potential(&mut self, builder: &mut Builder) -> bool {
+        self.forces.record_potential(builder)
+    }
+
+    /// Timestep levels of the last step, if it used block timesteps
+    pub fn level_statistics(&self) -> Option<&LevelStatistics> {
+        self.block.enabled().then(|| self.block.statistics())
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    pub fn dt(&self) -> f32 {
+        self.dt
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    pub fn g(&self) -> f32 {
+        self.forces.g()
+    }
+
+    pub fn softening(&self) -> f32 {
+        self.forces.softening()
+    }
+
+    pub fn solver(&self) -> ForceSolver {
+        self.forces.solver()
+    }
+
+    pub fn periodic(&self) -> bool {
+        self.forces.periodic()
+    }
+
+    pub fn box_size(&self) -> f32 {
+        self.forces.box_size()
+    }
+
+    pub fn reference_solver(&self) -> CpuSolver {
+        self.forces.reference_solver()
+    }
+
+    pub fn set_block_timesteps(&mut self, enabled: bool) {
+        self.block.set_enabled(enabled);
+    }
+
+    pub fn set_scheme(&mut self, scheme: Scheme) {
+        self.scheme = scheme;
+    }
+
+    pub fn set_dt(&mut self, dt: f32) {
+        self.dt = dt;
+    }
+
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.precision = precision;
+    }
+
+    pub fn set_g(&mut self, g: f32) {
+        self.forces.set_g(g);
+    }
+
+    pub fn set_softening(&mut self, softening: f32) {
+        self.forces.set_softening(softening);
+    }
+
+    pub fn set_periodic(&mut self, periodic: bool) {
+        self.forces.set_periodic(periodic);
+    }
+
+    pub fn set_box_size(&mut self, box_size: f32) {
+        self.forces.set_box_size(box_size);
+    }
+
+    pub fn set_solver(&mut self, solver: ForceSolver) {
+        self.forces.set_solver(solver);
+    }
+
+    pub fn set_theta(&mut self, theta: f32) {
+        self.forces.set_theta(theta);
+    }
+
+    pub fn set_fmm_order(&mut self, order: usize) {
+        self.forces.set_fmm_order(order);
+    }
+
+    pub fn set_fmm_theta(&mut self, theta: f32) {
+        self.forces.set_fmm_theta(theta);
+    }
+
+    pub fn set_mesh_resolution(&mut self, resolution: MeshResolution) {
+        self.forces.set_mesh_resolution(resolution);
+    }
+
+    pub fn set_split(&mut self, split: f32) {
+        self.forces.set_split(split);
+    }
+}
@@ -1,16 +1,118 @@
-use std::sync::Arc;
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
 
 use bytemuck::{Pod, Zeroable};
-use cgmath::{Point3, Vector3};
-use hatchery::util::{
-    buffer::{AbstractBuffer, DeviceBuffer, SharedBuffer},
-    point_cloud::RenderPoint,
-    ConstructionContext,
+use cgmath::{InnerSpace, Point3, Vector3};
+use hatchery::{
+    inspect::Inspect,
+    util::{
+        buffer::{AbstractBuffer, DeviceBuffer, SharedBuffer},
+        point_cloud::RenderPoint,
+        ConstructionContext,
+    },
+};
+use vulkano::{
+    buffer::BufferUsage,
+    command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand, PrimaryAutoCommandBuffer},
+    impl_vertex,
 };
-use vulkano::{buffer::BufferUsage, impl_vertex};
 
+pub mod attributes;
+pub mod barnes_hut;
+pub mod block;
+pub mod collisions;
+pub mod conservation;
+pub mod cpu;
+pub mod direct;
 pub mod energy;
-pub mod verlet;
+pub mod fmm;
+pub mod forces;
+pub mod integrator;
+pub mod pm;
+pub mod stages;
+
+/// Calculates the gravitational acceleration of every particle into
+/// `SimulationBuffers::acceleration` from the current positions. Integrators record it between
+/// their stages through `Forces`.
+pub trait ForceCalculator {
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        softening: f32,
+    );
+}
+
+/// Which `ForceCalculator` the integrator uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Inspect)]
+pub enum ForceSolver {
+    /// Exact, but O(N²) limits it to around 100k particles
+    #[default]
+    Direct,
+    #[inspect(label = "Barnes-Hut")]
+    BarnesHut,
+    /// Runs on the CPU, so it stalls the GPU every step
+    #[inspect(label = "Fast multipole")]
+    FastMultipole,
+    /// Forces of a periodic box, smoothed on the scale of a mesh cell
+    #[inspect(label = "Particle mesh")]
+    ParticleMesh,
+    /// Particle mesh for the long range force and Barnes-Hut for the short range
+    #[inspect(label = "TreePM")]
+    TreePm,
+}
+
+impl ForceSolver {
+    /// Whether the solver only works in a periodic box
+    pub fn periodic(&self) -> bool {
+        matches!(self, ForceSolver::ParticleMesh | ForceSolver::TreePm)
+    }
+}
+
+/// Precision of the sums in direct summation and of the position updates of the integrators. The
+/// other force solvers always sum in f32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Inspect)]
+pub enum Precision {
+    #[default]
+    Single,
+    /// Kahan summation, the rounding error of every sum is carried in a second f32
+    Compensated,
+    /// The positions, separations and sums in f64, falls back to compensated summation if the
+    /// device lacks `shader_float64`
+    #[inspect(label = "Double (f64)")]
+    Double,
+}
+
+impl Precision {
+    /// Value of `precision_mode` in `precision.glsl`
+    pub fn mode(&self) -> u32 {
+        match self {
+            Precision::Single => 0,
+            Precision::Compensated => 1,
+            Precision::Double => 2,
+        }
+    }
+
+    /// The precision the shaders actually run with on this device
+    pub fn available(&self, context: &ConstructionContext) -> Self {
+        match self {
+            Precision::Double if !has_float64(context) => Precision::Compensated,
+            precision => *precision,
+        }
+    }
+}
+
+/// Whether `shader_float64` is enabled, the shaders with a f64 variant load it if so
+pub fn has_float64(context: &ConstructionContext) -> bool {
+    context.device().enabled_features().shader_float64
+}
 
 pub struct Particle {
     position: Point3<f32>,
@@ -52,14 +154,83 @@ pub struct ParticleAcceleration {
 
 impl_vertex!(ParticleAcceleration, acc);
 
+/// Time derivative of the acceleration, only calculated for the Hermite integrator
+#[repr(C)]
+#[derive(Default, Pod, Zeroable, Clone, Copy)]
+pub struct ParticleJerk {
+    jerk: [f32; 4],
+}
+
 type Buffer<T> = DeviceBuffer<T>;
 
+/// Snapshot of a single particle read back from the GPU
+#[derive(Debug, Clone, Copy)]
+pub struct ParticleState {
+    pub position: Point3<f32>,
+    pub velocity: Vector3<f32>,
+    pub acceleration: Vector3<f32>,
+    pub mass: f32,
+    /// Softened gravitational potential at the particle, excluding its own contribution
+    pub potential: f32,
+}
+
+impl ParticleState {
+    /// Kinetic plus potential energy per unit mass
+    pub fn specific_energy(&self) -> f32 {
+        0.5 * self.velocity.magnitude2() + self.potential
+    }
+}
+
+/// Indices of the particles at the end of their block timestep, compacted on the GPU so the
+/// passes which only update those can dispatch for just them
+pub struct ActiveSet {
+    pub indices: Buffer<u32>,
+    /// Number of active particles
+    pub count: Buffer<u32>,
+    /// Workgroups for the active particles, at 128 invocations each
+    pub dispatch: Buffer<DispatchIndirectCommand>,
+}
+
+impl ActiveSet {
+    fn new(context: &ConstructionContext, num_particles: u32) -> Self {
+        let storage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        };
+
+        Self {
+            indices: Buffer::new(context, storage, num_particles as u64),
+            count: Buffer::new(context, storage, 1),
+            dispatch: Buffer::new(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    indirect_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                1,
+            ),
+        }
+    }
+}
+
 pub struct SimulationBuffers {
     pub points: Buffer<RenderPoint>,
     pub position_mass: Buffer<ParticlePositionMass>,
+    /// Rounding error of the positions in `position_mass`, unless the precision is single
+    pub position_low: Buffer<[f32; 4]>,
     pub velocity: Buffer<ParticleVelocity>,
     pub acceleration: Buffer<ParticleAcceleration>,
-    pub num_particles: u32,
+    pub jerk: Buffer<ParticleJerk>,
+    /// Gravitational potential per unit mass, written by the tree when it calculates the energy
+    /// and for the inspected particle
+    pub potential: Buffer<f32>,
+    pub active: ActiveSet,
+    /// Length of the buffers, the number of particles the simulation started with
+    pub capacity: u32,
+    /// The particles are the first `num_particles` entries of the buffers, mergers compact them
+    /// and leave the rest hidden and without mass
+    num_particles: AtomicU32,
 }
 
 impl SimulationBuffers {
@@ -70,6 +241,7 @@ impl SimulationBuffers {
                 BufferUsage {
                     storage_buffer: true,
                     vertex_buffer: true,
+                    transfer_src: true,
                     ..BufferUsage::empty()
                 },
                 particles.iter().map(|p| RenderPoint {
@@ -80,16 +252,26 @@ impl SimulationBuffers {
                 context,
                 BufferUsage {
                     storage_buffer: true,
+                    transfer_src: true,
                     ..BufferUsage::empty()
                 },
                 particles.iter().map(|p| ParticlePositionMass {
                     pos_mass: [p.position.x, p.position.y, p.position.z, p.mass],
                 }),
             ),
+            position_low: Buffer::from_iter(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                particles.iter().map(|p| [0.0; 4]),
+            ),
             velocity: Buffer::from_iter(
                 context,
                 BufferUsage {
                     storage_buffer: true,
+                    transfer_src: true,
                     ..BufferUsage::empty()
                 },
                 particles.iter().map(|p| ParticleVelocity {
@@ -100,13 +282,132 @@ impl SimulationBuffers {
                 context,
                 BufferUsage {
                     storage_buffer: true,
+                    transfer_src: true,
                     ..BufferUsage::empty()
                 },
                 particles.iter().map(|p| ParticleAcceleration {
                     acc: [0.0, 0.0, 0.0, 0.0],
                 }),
             ),
-            num_particles: particles.len() as u32,
+            jerk: Buffer::from_iter(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                particles.iter().map(|p| ParticleJerk::default()),
+            ),
+            potential: Buffer::new(
+                context,
+                BufferUsage {
+                    storage_buffer: true,
+                    transfer_src: true,
+                    ..BufferUsage::empty()
+                },
+                particles.len() as u64,
+            ),
+            active: ActiveSet::new(context, particles.len() as u32),
+            capacity: particles.len() as u32,
+            num_particles: AtomicU32::new(particles.len() as u32),
         })
     }
+
+    /// Number of particles left, the solvers only dispatch for these
+    pub fn num_particles(&self) -> u32 {
+        self.num_particles.load(Ordering::Relaxed)
+    }
+
+    /// Shrink the particles to the first `num_particles` entries, after they were compacted
+    pub fn set_num_particles(&self, num_particles: u32) {
+        debug_assert!(num_particles <= self.capacity);
+        self.num_particles.store(num_particles, Ordering::Relaxed);
+    }
+
+    /// Read back the current state of the particle at `index`. The potential is taken from
+    /// `potential`, so it has to be calculated first with `EnergyCalculator::potential_at`.
+    pub fn particle(&self, context: &ConstructionContext, index: u32) -> ParticleState {
+        let index = index as u64;
+        let [x, y, z, mass] = self.position_mass.read_back(context, index, 1)[0].pos_mass;
+        let [vx, vy, vz, _] = self.velocity.read_back(context, index, 1)[0].vel;
+        let [ax, ay, az, _] = self.acceleration.read_back(context, index, 1)[0].acc;
+        let potential = self.potential.read_back(context, index, 1)[0];
+
+        ParticleState {
+            position: Point3::new(x, y, z),
+            velocity: Vector3::new(vx, vy, vz),
+            acceleration: Vector3::new(ax, ay, az),
+            mass,
+            potential,
+        }
+    }
+
+    /// Read back the position, velocity and mass of every particle
+    pub fn particles(&self, context: &ConstructionContext) -> Vec<Particle> {
+        let count = self.num_particles() as u64;
+        let pos_mass = self.position_mass.read_back(context, 0, count);
+        let velocity = self.velocity.read_back(context, 0, count);
+
+        pos_mass
+            .iter()
+            .zip(velocity.iter())
+            .map(|(pos_mass, velocity)| {
+                let [x, y, z, mass] = pos_mass.pos_mass;
+                let [vx, vy, vz, _] = velocity.vel;
+                Particle::new(Point3::new(x, y, z), Vector3::new(vx, vy, vz), mass)
+            })
+            .collect()
+    }
+
+    /// Read back the accelerations calculated in the last step
+    pub fn accelerations(&self, context: &ConstructionContext) -> Vec<Vector3<f32>> {
+        self.acceleration
+            .read_back(context, 0, self.num_particles() as u64)
+            .iter()
+            .map(|acceleration| {
+                let [ax, ay, az, _] = acceleration.acc;
+                Vector3::new(ax, ay, az)
+            })
+            .collect()
+    }
+
+    /// Overwrite the accelerations, for force solvers running on the CPU
+    pub fn write_accelerations<I>(&self, context: &ConstructionContext, accelerations: I)
+    where
+        I: IntoIterator<Item = Vector3<f32>>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let staging = SharedBuffer::from_iter(
+            context,
+            BufferUsage {
+                transfer_src: true,
+                ..BufferUsage::empty()
+            },
+            accelerations
+                .into_iter()
+                .map(|acceleration| ParticleAcceleration {
+                    acc: [acceleration.x, acceleration.y, acceleration.z, 0.0],
+                }),
+        );
+        self.acceleration.copy(context, &staging);
+    }
+
+    /// Write the position, velocity and mass of every particle to a csv file
+    pub fn write_snapshot(
+        &self,
+        context: &ConstructionContext,
+        path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        let count = self.num_particles() as u64;
+        let pos_mass = self.position_mass.read_back(context, 0, count);
+        let velocity = self.velocity.read_back(context, 0, count);
+
+        let mut csv = String::from("x,y,z,vx,vy,vz,mass\n");
+        for (pos_mass, velocity) in pos_mass.iter().zip(velocity.iter()) {
+            let [x, y, z, mass] = pos_mass.pos_mass;
+            let [vx, vy, vz, _] = velocity.vel;
+            writeln!(csv, "{x:e},{y:e},{z:e},{vx:e},{vy:e},{vz:e},{mass:e}").unwrap();
+        }
+
+        fs::write(path, csv)
+    }
 }
@@ -0,0 +1,200 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer PositionMass { vec4 data[]; } pos_mass;
+layout(set = 0, binding = 1) buffer Acceleration { vec4 data[]; } acc;
+// Mass of every cell, as bits of a float for the atomic add
+layout(set = 0, binding = 2) buffer Cells { uint data[]; } cells;
+// Complex grid transformed in place, holds the potential after the inverse transform
+layout(set = 0, binding = 3) buffer Grid { vec2 data[]; } grid;
+
+#define PASS_CLEAR 0
+#define PASS_DEPOSIT 1
+#define PASS_LOAD 2
+#define PASS_REVERSE 3
+#define PASS_BUTTERFLY 4
+#define PASS_GREEN 5
+#define PASS_INTERPOLATE 6
+
+#define PI 3.14159265358979
+
+// The box spans [0, box_size) along every axis and repeats periodically
+layout(push_constant) uniform MeshData {
+    uint num_particles;
+    uint resolution;
+    uint pass;
+    // Axis and stage of the FFT pass, butterflies of the stage span 2^(stage + 1) elements
+    uint axis;
+    uint stage;
+    // -1 for the forward transform, 1 for the inverse
+    float direction;
+    float box_size;
+    float G;
+    // Split radius of TreePM, the mesh only solves for the long range force if not 0
+    float split;
+} md;
+
+// There are no float atomics in core Vulkan, so emulate them with a compare and swap loop
+void atomic_add_cell(uint index, float value) {
+    uint expected = cells.data[index];
+    while (true) {
+        uint desired = floatBitsToUint(uintBitsToFloat(expected) + value);
+        uint actual = atomicCompSwap(cells.data[index], expected, desired);
+        if (actual == expected) {
+            break;
+        }
+        expected = actual;
+    }
+}
+
+uint cell_index(ivec3 cell) {
+    int n = int(md.resolution);
+    ivec3 c = ((cell % n) + n) % n;
+    return uint(c.x + n * (c.y + n * c.z));
+}
+
+// Position in grid units relative to the cell centers, wrapped into the box
+vec3 grid_position(vec3 position) {
+    float n = float(md.resolution);
+    return mod(position / md.box_size * n, n) - 0.5;
+}
+
+// Index of the `i`-th element of the `line`-th line of the grid along `md.axis`
+uint line_element(uint line, uint i) {
+    uint n = md.resolution;
+    uint a = line % n;
+    uint b = line / n;
+    if (md.axis == 0) {
+        return i + n * (a + n * b);
+    } else if (md.axis == 1) {
+        return a + n * (i + n * b);
+    }
+    return a + n * (b + n * i);
+}
+
+vec2 complex_mul(vec2 a, vec2 b) {
+    return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+float sinc(float x) {
+    return abs(x) < 1e-6 ? 1.0 : sin(x) / x;
+}
+
+float potential(ivec3 cell) {
+    return grid.data[cell_index(cell)].x;
+}
+
+// Fourth order finite difference of the potential, the acceleration at the center of a cell
+vec3 field(ivec3 cell) {
+    float h = md.box_size / float(md.resolution);
+    vec3 gradient;
+    for (int i = 0; i < 3; i++) {
+        ivec3 e = ivec3(0);
+        e[i] = 1;
+        gradient[i] = (2.0 / 3.0) * (potential(cell + e) - potential(cell - e))
+            - (1.0 / 12.0) * (potential(cell + 2 * e) - potential(cell - 2 * e));
+    }
+    return -gradient / h;
+}
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+    uint n = md.resolution;
+    uint cells_count = n * n * n;
+
+    if (md.pass == PASS_CLEAR) {
+        if (gi < cells_count) {
+            cells.data[gi] = 0u;
+        }
+    } else if (md.pass == PASS_DEPOSIT) {
+        // Cloud in cell, the mass is spread over the 8 nearest cells
+        if (gi < md.num_particles) {
+            vec4 particle = pos_mass.data[gi];
+            vec3 g = grid_position(particle.xyz);
+            ivec3 base = ivec3(floor(g));
+            vec3 f = g - vec3(base);
+            for (int i = 0; i < 8; i++) {
+                ivec3 offset = ivec3(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+                vec3 w = mix(1.0 - f, f, vec3(offset));
+                atomic_add_cell(cell_index(base + offset), particle.w * w.x * w.y * w.z);
+            }
+        }
+    } else if (md.pass == PASS_LOAD) {
+        if (gi < cells_count) {
+            float h = md.box_size / float(n);
+            grid.data[gi] = vec2(uintBitsToFloat(cells.data[gi]) / (h * h * h), 0.0);
+        }
+    } else if (md.pass == PASS_REVERSE) {
+        // Bit reversed order along the axis, for the in place radix 2 transform
+        if (gi < cells_count) {
+            uint i = gi % n;
+            uint r = bitfieldReverse(i) >> (32 - findMSB(n));
+            if (i < r) {
+                uint a = line_element(gi / n, i);
+                uint b = line_element(gi / n, r);
+                vec2 tmp = grid.data[a];
+                grid.data[a] = grid.data[b];
+                grid.data[b] = tmp;
+            }
+        }
+    } else if (md.pass == PASS_BUTTERFLY) {
+        if (gi < cells_count / 2) {
+            uint half_size = 1u << md.stage;
+            uint j = gi % (n / 2);
+            uint k = j & (half_size - 1u);
+            uint i = ((j >> md.stage) << (md.stage + 1)) + k;
+
+            uint a = line_element(gi / (n / 2), i);
+            uint b = line_element(gi / (n / 2), i + half_size);
+            float angle = md.direction * PI * float(k) / float(half_size);
+            vec2 t = complex_mul(vec2(cos(angle), sin(angle)), grid.data[b]);
+            vec2 x = grid.data[a];
+            grid.data[a] = x + t;
+            grid.data[b] = x - t;
+        }
+    } else if (md.pass == PASS_GREEN) {
+        // Solve the Poisson equation, -4 pi G / k^2 deconvolved by the window of the cloud in
+        // cell assignment. Also normalizes the inverse transform, and smooths with a Gaussian for
+        // the long range part of TreePM.
+        //
+        // The window is applied twice, by the deposit and the interpolation, but only the
+        // smoothed long range force is deconvolved twice. Without the smoothing that boosts the
+        // aliased modes near the Nyquist frequency, and forces a few cells apart are off by
+        // more than 10%.
+        if (gi < cells_count) {
+            ivec3 cell = ivec3(gi % n, (gi / n) % n, gi / (n * n));
+            ivec3 half_n = ivec3(n / 2);
+            vec3 m = vec3(cell - ivec3(greaterThanEqual(cell, half_n)) * int(n));
+            vec3 k = 2.0 * PI * m / md.box_size;
+            float k2 = dot(k, k);
+
+            float window = 1.0;
+            for (int i = 0; i < 3; i++) {
+                float s = sinc(PI * m[i] / float(n));
+                window *= s * s;
+            }
+
+            float deconvolution = md.split > 0.0 ? window * window : window;
+            float green = gi == 0 ? 0.0 : -4.0 * PI * md.G / (k2 * deconvolution);
+            green *= exp(-k2 * md.split * md.split);
+            grid.data[gi] *= green / float(cells_count);
+        }
+    } else if (md.pass == PASS_INTERPOLATE) {
+        // The same cloud in cell weights as the deposit, so particles don't accelerate themselves
+        if (gi < md.num_particles) {
+            vec3 g = grid_position(pos_mass.data[gi].xyz);
+            ivec3 base = ivec3(floor(g));
+            vec3 f = g - vec3(base);
+            vec3 a = vec3(0.0);
+            for (int i = 0; i < 8; i++) {
+                ivec3 offset = ivec3(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+                vec3 w = mix(1.0 - f, f, vec3(offset));
+                a += w.x * w.y * w.z * field(base + offset);
+            }
+            acc.data[gi].xyz = a;
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use cgmath::{Point3, Vector3, Zero};
+use hatchery::{
+    inspect::Inspect,
+    util::{
+        buffer::{AbstractBuffer, DeviceBuffer},
+        compute::{ComputeShader, ComputeShaderExecutor},
+        ConstructionContext,
+    },
+};
+use rayon::prelude::*;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use vulkano::{
+    buffer::BufferUsage,
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::WriteDescriptorSet,
+    device::Device,
+    shader::ShaderModule,
+};
+
+use super::{
+    cpu::{Body, Gravity},
+    ForceCalculator, SimulationBuffers,
+};
+
+hatchery::compute! { "src/physics/pm.glsl", pm }
+
+// Passes of `pm.glsl`
+const PASS_CLEAR: u32 = 0;
+const PASS_DEPOSIT: u32 = 1;
+const PASS_LOAD: u32 = 2;
+const PASS_REVERSE: u32 = 3;
+const PASS_BUTTERFLY: u32 = 4;
+const PASS_GREEN: u32 = 5;
+const PASS_INTERPOLATE: u32 = 6;
+
+const FORWARD: f32 = -1.0;
+const INVERSE: f32 = 1.0;
+
+/// Cells along each axis of the mesh, the buffers are allocated for the largest so it can be
+/// switched while running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Inspect)]
+pub enum MeshResolution {
+    #[inspect(label = "32³")]
+    Low,
+    #[default]
+    #[inspect(label = "64³")]
+    Medium,
+    #[inspect(label = "128³")]
+    High,
+}
+
+impl MeshResolution {
+    pub fn cells(&self) -> u32 {
+        match self {
+            MeshResolution::Low => 32,
+            MeshResolution::Medium => 64,
+            MeshResolution::High => 128,
+        }
+    }
+}
+
+struct MeshShader {
+    data: Arc<SimulationBuffers>,
+    cells: DeviceBuffer<u32>,
+    grid: DeviceBuffer<[f32; 2]>,
+    constants: pm::ty::MeshData,
+}
+
+impl ComputeShader for MeshShader {
+    type Constants = pm::ty::MeshData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        pm::load(device).unwrap()
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        let cells = self.constants.resolution.pow(3);
+        let threads = match self.constants.pass {
+            PASS_DEPOSIT | PASS_INTERPOLATE => self.constants.num_particles,
+            PASS_BUTTERFLY => cells / 2,
+            _ => cells,
+        };
+        [threads.div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.acceleration.buffer()),
+            WriteDescriptorSet::buffer(2, self.cells.buffer()),
+            WriteDescriptorSet::buffer(3, self.grid.buffer()),
+        ]
+    }
+}
+
+/// Particle mesh solver for a periodic box spanning `[0, box_size)` along every axis. The mass is
+/// deposited onto a grid with cloud in cell weights, the Poisson equation solved with an FFT and
+/// the finite difference gradient of the potential interpolated back to the particles. Forces are
+/// smoothed on the scale of a cell, so the softening isn't used.
+#[derive(Inspect)]
+pub struct ParticleMesh {
+    #[inspect(skip)]
+    mesh: ComputeShaderExecutor<MeshShader>,
+    #[inspect(label = "Mesh")]
+    resolution: MeshResolution,
+    /// Scale of the TreePM force split in mesh cells, smaller puts more of the force on the mesh
+    #[inspect(label = "Split scale", range = 0.5..=4.0, speed = 0.01)]
+    split: f32,
+    #[inspect(skip)]
+    box_size: f32,
+}
+
+impl ParticleMesh {
+    pub fn new(
+        context: &ConstructionContext,
+        data: Arc<SimulationBuffers>,
+        resolution: MeshResolution,
+        box_size: f32,
+        split: f32,
+    ) -> Self {
+        let cells = MeshResolution::High.cells().pow(3) as u64;
+        let storage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        };
+        let mesh = MeshShader {
+            constants: pm::ty::MeshData {
+                num_particles: data.num_particles(),
+                resolution: resolution.cells(),
+                pass: PASS_CLEAR,
+                axis: 0,
+                stage: 0,
+                direction: FORWARD,
+                box_size,
+                G: 0.0,
+                split: 0.0,
+            },
+            data,
+            cells: DeviceBuffer::new(context, storage, cells),
+            grid: DeviceBuffer::new(context, storage, cells),
+        };
+
+        Self {
+            mesh: ComputeShaderExecutor::new(context, mesh),
+            resolution,
+            split,
+            box_size,
+        }
+    }
+
+    pub fn resolution(&self) -> MeshResolution {
+        self.resolution
+    }
+
+    pub fn box_size(&self) -> f32 {
+        self.box_size
+    }
+
+    /// Split radius `r_s` of TreePM in mesh cells
+    pub fn split(&self) -> f32 {
+        self.split
+    }
+
+    /// Split radius `r_s` of TreePM in world units
+    pub fn split_radius(&self) -> f32 {
+        self.split * self.box_size / self.resolution.cells() as f32
+    }
+
+    pub fn set_resolution(&mut self, resolution: MeshResolution) {
+        self.resolution = resolution;
+    }
+
+    pub fn set_box_size(&mut self, box_size: f32) {
+        self.box_size = box_size;
+    }
+
+    pub fn set_split(&mut self, split: f32) {
+        self.split = split;
+    }
+
+    /// Record only the long range part of the force split, the short range part is left to
+    /// `BarnesHut::record_short_range`
+    pub fn record_long_range(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+    ) {
+        self.record_mesh(builder, g, self.split_radius());
+    }
+
+    fn record_mesh(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        split: f32,
+    ) {
+        let num_particles = self.mesh.data.num_particles();
+        let constants = &mut self.mesh.constants;
+        constants.num_particles = num_particles;
+        constants.resolution = self.resolution.cells();
+        constants.box_size = self.box_size;
+        constants.G = g;
+        constants.split = split;
+
+        for pass in [PASS_CLEAR, PASS_DEPOSIT, PASS_LOAD] {
+            self.record_pass(builder, pass);
+        }
+        self.record_fft(builder, FORWARD);
+        self.record_pass(builder, PASS_GREEN);
+        self.record_fft(builder, INVERSE);
+        self.record_pass(builder, PASS_INTERPOLATE);
+    }
+
+    fn record_pass(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pass: u32,
+    ) {
+        self.mesh.constants.pass = pass;
+        self.mesh.record(builder);
+    }
+
+    /// Radix 2 transform along every axis in turn, each stage is its own dispatch
+    fn record_fft(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        direction: f32,
+    ) {
+        self.mesh.constants.direction = direction;
+        for axis in 0..3 {
+            self.mesh.constants.axis = axis;
+            self.record_pass(builder, PASS_REVERSE);
+            for stage in 0..self.resolution.cells().trailing_zeros() {
+                self.mesh.constants.stage = stage;
+                self.record_pass(builder, PASS_BUTTERFLY);
+            }
+        }
+    }
+}
+
+impl ForceCalculator for ParticleMesh {
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        g: f32,
+        _softening: f32,
+    ) {
+        self.record_mesh(builder, g, 0.0);
+    }
+}
+
+/// The same particle mesh solver on the CPU in double precision, for running without a GPU
+pub struct Mesh {
+    resolution: usize,
+    box_size: f64,
+    split: f64,
+}
+
+impl Mesh {
+    pub fn new(resolution: usize, box_size: f64) -> Self {
+        Self {
+            resolution,
+            box_size,
+            split: 0.0,
+        }
+    }
+
+    /// Only solve for the long range force of TreePM, with the split radius in world units
+    pub fn with_split(mut self, split: f64) -> Self {
+        self.split = split;
+        self
+    }
+
+    pub fn accelerations(&self, bodies: &[Body], gravity: Gravity) -> Vec<Vector3<f64>> {
+        let n = self.resolution;
+        let h = self.box_size / n as f64;
+
+        let mut grid = vec![Complex::zero(); n * n * n];
+        for body in bodies {
+            for (cell, weight) in self.cloud(body.position) {
+                grid[self.index(cell)].re += body.mass * weight / (h * h * h);
+            }
+        }
+
+        let mut planner = FftPlanner::new();
+        self.transform(&mut grid, &*planner.plan_fft_forward(n));
+
+        // Green's function deconvolved by the cloud in cell window and smoothed by the split, see
+        // `pm.glsl`
+        let wave_number = |i: usize| match i < n / 2 {
+            true => i as f64,
+            false => i as f64 - n as f64,
+        };
+        grid.par_iter_mut().enumerate().for_each(|(index, value)| {
+            let m = [index % n, (index / n) % n, index / (n * n)].map(wave_number);
+            let k2: f64 = m
+                .iter()
+                .map(|m| (2.0 * PI * m / self.box_size).powi(2))
+                .sum();
+            let window: f64 = m.iter().map(|m| sinc(PI * m / n as f64).powi(2)).product();
+            let deconvolution = match self.split > 0.0 {
+                true => window * window,
+                false => window,
+            };
+
+            let smoothing = (-k2 * self.split * self.split).exp();
+            *value *= match index {
+                0 => 0.0,
+                _ => -4.0 * PI * gravity.g * smoothing / (k2 * deconvolution) / (n * n * n) as f64,
+            };
+        });
+
+        self.transform(&mut grid, &*planner.plan_fft_inverse(n));
+
+        // Fourth order finite difference gradient at the cell centers
+        let potential = |cell: [i64; 3]| grid[self.index(cell)].re;
+        let field: Vec<Vector3<f64>> = (0..n * n * n)
+            .into_par_iter()
+            .map(|index| {
+                let cell = [index % n, (index / n) % n, index / (n * n)].map(|i| i as i64);
+                let mut gradient = Vector3::zero();
+                for axis in 0..3 {
+                    let offset = |step: i64| {
+                        let mut neighbour = cell;
+                        neighbour[axis] += step;
+                        potential(neighbour)
+                    };
+                    gradient[axis] = 2.0 / 3.0 * (offset(1) - offset(-1))
+                        - 1.0 / 12.0 * (offset(2) - offset(-2));
+                }
+                -gradient / h
+            })
+            .collect();
+
+        bodies
+            .par_iter()
+            .map(|body| {
+                self.cloud(body.position)
+                    .into_iter()
+                    .map(|(cell, weight)| field[self.index(cell)] * weight)
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Index of a cell, wrapped around the periodic box
+    fn index(&self, cell: [i64; 3]) -> usize {
+        let n = self.resolution as i64;
+        let [x, y, z] = cell.map(|i| i.rem_euclid(n));
+        (x + n * (y + n * z)) as usize
+    }
+
+    /// The 8 cells overlapped by the cloud of a particle at `position`, with their weights
+    fn cloud(&self, position: Point3<f64>) -> [([i64; 3], f64); 8] {
+        let n = self.resolution as f64;
+        // Relative to the cell centers
+        let g = [position.x, position.y, position.z]
+            .map(|x| (x / self.box_size * n).rem_euclid(n) - 0.5);
+        let base = g.map(|g| g.floor());
+        let fraction = [0, 1, 2].map(|axis| g[axis] - base[axis]);
+
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            let offset = [i & 1, (i >> 1) & 1, (i >> 2) & 1];
+            let cell = [0, 1, 2].map(|axis| base[axis] as i64 + offset[axis] as i64);
+            let weight = [0, 1, 2]
+                .map(|axis| match offset[axis] {
+                    0 => 1.0 - fraction[axis],
+                    _ => fraction[axis],
+                })
+                .iter()
+                .product();
+            (cell, weight)
+        })
+    }
+
+    /// Transform along every axis in turn, the lines of an axis are transformed in parallel
+    fn transform(&self, grid: &mut [Complex<f64>], fft: &dyn Fft<f64>) {
+        let n = self.resolution;
+        for axis in 0..3 {
+            let element = |line: usize, i: usize| {
+                let (a, b) = (line % n, line / n);
+                match axis {
+                    0 => i + n * (a + n * b),
+                    1 => a + n * (i + n * b),
+                    _ => a + n * (b + n * i),
+                }
+            };
+
+            let lines: Vec<Vec<Complex<f64>>> = (0..n * n)
+                .into_par_iter()
+                .map(|line| {
+                    let mut values: Vec<_> = (0..n).map(|i| grid[element(line, i)]).collect();
+                    fft.process(&mut values);
+                    values
+                })
+                .collect();
+
+            for (line, values) in lines.into_iter().enumerate() {
+                for (i, value) in values.into_iter().enumerate() {
+                    grid[element(line, i)] = value;
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        x.sin() / x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::*;
+    use crate::physics::cpu::tests::{pairs, periodic_acceleration};
+
+    #[test]
+    fn two_masses_attract_like_point_masses() {
+        // 10 to 16 cells apart, small compared to the box. The periodic images still change
+        // the force by a few percent at these distances, so it is compared with an Ewald sum.
+        let mesh = Mesh::new(64, 1.0);
+        let gravity = Gravity::new(1.0, 0.0);
+
+        for separation in [0.15, 0.2, 0.25] {
+            for bodies in pairs(separation, 3, 4) {
+                let [a, b] = bodies;
+                let acceleration = mesh.accelerations(&bodies, gravity)[0];
+                let expected = periodic_acceleration(b.position - a.position, 1.0);
+
+                let error = (acceleration - expected).magnitude() / expected.magnitude();
+                assert!(error < 0.01, "error of {error:e} at {separation}");
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+// Summation in higher precision than f32, for the positions and the force sums. Positions are
+// stored as the f32 in `PositionMass` plus the part lost to rounding in `PositionLow`, so
+// increments below the spacing of f32 at the position aren't lost. The f64 variants are only
+// compiled with DOUBLE defined, as the device needs `shader_float64` to load them.
+
+#define PRECISION_SINGLE 0
+#define PRECISION_COMPENSATED 1
+#define PRECISION_DOUBLE 2
+
+// Add `increment` to the position `high + low`, returning the new high part
+vec3 add_position(vec3 high, inout vec3 low, vec3 increment, uint precision_mode) {
+#ifdef DOUBLE
+    if (precision_mode == PRECISION_DOUBLE) {
+        dvec3 x = dvec3(high) + dvec3(low) + dvec3(increment);
+        vec3 rounded = vec3(x);
+        low = vec3(x - dvec3(rounded));
+        return rounded;
+    }
+#endif
+    if (precision_mode == PRECISION_SINGLE) {
+        low = vec3(0.0);
+        return high + increment;
+    }
+
+    // Kahan summation, `precise` keeps the compiler from simplifying the error away
+    precise vec3 y = increment + low;
+    precise vec3 t = high + y;
+    low = y - (t - high);
+    return t;
+}
+
+// Running sum with the rounding error carried separately
+struct CompensatedSum {
+    vec3 sum;
+    vec3 error;
+};
+
+void compensated_add(inout CompensatedSum s, vec3 term) {
+    precise vec3 y = term - s.error;
+    precise vec3 t = s.sum + y;
+    s.error = (t - s.sum) - y;
+    s.sum = t;
+}
@@ -0,0 +1,116 @@
+#version 450
+
+#define PARALLELISM 128
+
+layout(local_size_x = PARALLELISM, local_size_y = 1, local_size_z = 1) in;
+
+#include "precision.glsl"
+
+layout(set = 0, binding = 0) buffer Points { vec4 data[]; } points;
+layout(set = 0, binding = 1) buffer PositionMass { vec4 data[]; } pos_mass;
+layout(set = 0, binding = 2) buffer Velocity { vec4 data[]; } vel;
+layout(set = 0, binding = 3) buffer Acceleration { vec4 data[]; } acc;
+layout(set = 0, binding = 4) buffer Jerk { vec4 data[]; } jerk;
+// State at the start of the step, for the schemes which need more than the current state
+layout(set = 0, binding = 5) buffer SavedPosition { vec4 data[]; } saved_pos;
+layout(set = 0, binding = 6) buffer SavedVelocity { vec4 data[]; } saved_vel;
+layout(set = 0, binding = 7) buffer SavedAcceleration { vec4 data[]; } saved_acc;
+layout(set = 0, binding = 8) buffer SavedJerk { vec4 data[]; } saved_jerk;
+// Weighted sums of the Runge-Kutta stages
+layout(set = 0, binding = 9) buffer SumPosition { vec4 data[]; } sum_pos;
+layout(set = 0, binding = 10) buffer SumVelocity { vec4 data[]; } sum_vel;
+layout(set = 0, binding = 11) buffer PositionLow { vec4 data[]; } pos_low;
+layout(set = 0, binding = 12) buffer SavedPositionLow { vec4 data[]; } saved_low;
+
+// Stages the integrators are built from, the accelerations in between are calculated by one of
+// the force solvers
+#define PASS_KICK 0
+#define PASS_DRIFT 1
+#define PASS_SAVE 2
+#define PASS_RUNGE_KUTTA 3
+#define PASS_RUNGE_KUTTA_FINISH 4
+#define PASS_PREDICT 5
+#define PASS_CORRECT 6
+
+layout(push_constant) uniform StageData {
+    uint buffer_size;
+    uint pass;
+    // Length of the stage, already multiplied by its coefficient
+    float dt;
+    // Weight of the current derivatives in the Runge-Kutta sums
+    float weight;
+    // Positions wrap around a periodic box spanning [0, box_size), unless it is 0
+    float box_size;
+    // One of the PRECISION_ modes for the positions
+    uint precision_mode;
+} sd;
+
+// Move to `base + increment`, where the base is the current or the saved position
+void set_position(uint gi, vec3 base, vec3 base_low, vec3 increment) {
+    vec3 low = base_low;
+    vec3 p = add_position(base, low, increment, sd.precision_mode);
+    if (sd.box_size > 0.0) {
+        p = mod(p, sd.box_size);
+    }
+    pos_mass.data[gi].xyz = p;
+    pos_low.data[gi].xyz = low;
+    points.data[gi].xyz = p;
+}
+
+void main() {
+    uint gi = gl_GlobalInvocationID.x;
+
+    if (gi >= sd.buffer_size) {
+        return;
+    }
+
+    float dt = sd.dt;
+    vec3 x = pos_mass.data[gi].xyz;
+    vec3 v = vel.data[gi].xyz;
+    vec3 a = acc.data[gi].xyz;
+
+    if (sd.pass == PASS_KICK) {
+        vel.data[gi].xyz = v + a * dt;
+    } else if (sd.pass == PASS_DRIFT) {
+        set_position(gi, x, pos_low.data[gi].xyz, v * dt);
+    } else if (sd.pass == PASS_SAVE) {
+        saved_pos.data[gi] = vec4(x, 0.0);
+        saved_low.data[gi] = pos_low.data[gi];
+        saved_vel.data[gi] = vec4(v, 0.0);
+        saved_acc.data[gi] = vec4(a, 0.0);
+        saved_jerk.data[gi] = jerk.data[gi];
+        sum_pos.data[gi] = vec4(0.0);
+        sum_vel.data[gi] = vec4(0.0);
+    } else if (sd.pass == PASS_RUNGE_KUTTA || sd.pass == PASS_RUNGE_KUTTA_FINISH) {
+        // The current velocity and acceleration are the derivatives of the stage
+        vec3 dx = sum_pos.data[gi].xyz + sd.weight * v;
+        vec3 dv = sum_vel.data[gi].xyz + sd.weight * a;
+        sum_pos.data[gi].xyz = dx;
+        sum_vel.data[gi].xyz = dv;
+
+        // Either move to the point of the next stage, or to the end of the step with the sums
+        bool finish = sd.pass == PASS_RUNGE_KUTTA_FINISH;
+        set_position(gi, saved_pos.data[gi].xyz, saved_low.data[gi].xyz, dt * (finish ? dx : v));
+        vel.data[gi].xyz = saved_vel.data[gi].xyz + dt * (finish ? dv : a);
+    } else if (sd.pass == PASS_PREDICT) {
+        // Taylor series from the saved acceleration and jerk
+        vec3 x0 = saved_pos.data[gi].xyz;
+        vec3 v0 = saved_vel.data[gi].xyz;
+        vec3 a0 = saved_acc.data[gi].xyz;
+        vec3 j0 = saved_jerk.data[gi].xyz;
+        vec3 dx = dt * (v0 + dt * (a0 / 2.0 + dt * j0 / 6.0));
+        set_position(gi, x0, saved_low.data[gi].xyz, dx);
+        vel.data[gi].xyz = v0 + dt * (a0 + dt * j0 / 2.0);
+    } else if (sd.pass == PASS_CORRECT) {
+        // Hermite interpolation between the forces at the start and the predicted end
+        vec3 x0 = saved_pos.data[gi].xyz;
+        vec3 v0 = saved_vel.data[gi].xyz;
+        vec3 a0 = saved_acc.data[gi].xyz;
+        vec3 j0 = saved_jerk.data[gi].xyz;
+        vec3 j = jerk.data[gi].xyz;
+        vec3 v1 = v0 + dt * ((a0 + a) / 2.0 + dt * (j0 - j) / 12.0);
+        vec3 dx = dt * ((v0 + v1) / 2.0 + dt * (a0 - a) / 12.0);
+        set_position(gi, x0, saved_low.data[gi].xyz, dx);
+        vel.data[gi].xyz = v1;
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use hatchery::util::{
+    buffer::{AbstractBuffer, DeviceBuffer},
+    compute::{ComputeShader, ComputeShaderExecutor},
+    ConstructionContext,
+};
+use vulkano::{
+    buffer::BufferUsage,
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::WriteDescriptorSet,
+    device::Device,
+    shader::ShaderModule,
+};
+
+use super::{has_float64, Precision, SimulationBuffers};
+
+hatchery::compute! { "src/physics/stages.glsl", stages }
+hatchery::compute! { "src/physics/stages.glsl", stages_f64, ("DOUBLE", "1") }
+
+// Passes of `stages.glsl`
+const PASS_KICK: u32 = 0;
+const PASS_DRIFT: u32 = 1;
+const PASS_SAVE: u32 = 2;
+const PASS_RUNGE_KUTTA: u32 = 3;
+const PASS_RUNGE_KUTTA_FINISH: u32 = 4;
+const PASS_PREDICT: u32 = 5;
+const PASS_CORRECT: u32 = 6;
+
+struct StageShader {
+    data: Arc<SimulationBuffers>,
+    /// Load the variant with f64 positions, which also handles the other precisions
+    double: bool,
+    /// Position, velocity, acceleration, jerk and the rounding error of the position
+    saved: [DeviceBuffer<[f32; 4]>; 5],
+    sums: [DeviceBuffer<[f32; 4]>; 2],
+    constants: stages::ty::StageData,
+}
+
+impl ComputeShader for StageShader {
+    type Constants = stages::ty::StageData;
+
+    fn push_constants(&self) -> Option<Self::Constants> {
+        Some(self.constants)
+    }
+
+    fn load_module(device: Arc<Device>) -> Arc<ShaderModule> {
+        stages::load(device).unwrap()
+    }
+
+    fn load_variant(&self, device: Arc<Device>) -> Arc<ShaderModule> {
+        if self.double {
+            stages_f64::load(device).unwrap()
+        } else {
+            Self::load_module(device)
+        }
+    }
+
+    fn dispatch_size(&self) -> [u32; 3] {
+        [self.constants.buffer_size.div_ceil(128), 1, 1]
+    }
+
+    fn write_descriptors(&self) -> Vec<WriteDescriptorSet> {
+        let [saved_pos, saved_vel, saved_acc, saved_jerk, saved_low] = &self.saved;
+        let [sum_pos, sum_vel] = &self.sums;
+        vec![
+            WriteDescriptorSet::buffer(0, self.data.points.buffer()),
+            WriteDescriptorSet::buffer(1, self.data.position_mass.buffer()),
+            WriteDescriptorSet::buffer(2, self.data.velocity.buffer()),
+            WriteDescriptorSet::buffer(3, self.data.acceleration.buffer()),
+            WriteDescriptorSet::buffer(4, self.data.jerk.buffer()),
+            WriteDescriptorSet::buffer(5, saved_pos.buffer()),
+            WriteDescriptorSet::buffer(6, saved_vel.buffer()),
+            WriteDescriptorSet::buffer(7, saved_acc.buffer()),
+            WriteDescriptorSet::buffer(8, saved_jerk.buffer()),
+            WriteDescriptorSet::buffer(9, sum_pos.buffer()),
+            WriteDescriptorSet::buffer(10, sum_vel.buffer()),
+            WriteDescriptorSet::buffer(11, self.data.position_low.buffer()),
+            WriteDescriptorSet::buffer(12, saved_low.buffer()),
+        ]
+    }
+}
+
+/// Per particle updates of the positions and velocities that the integrators are built from. Each
+/// stage records a single pass, the forces in between are up to the integrator.
+pub struct Stages {
+    shader: ComputeShaderExecutor<StageShader>,
+}
+
+impl Stages {
+    pub fn new(context: &ConstructionContext, data: Arc<SimulationBuffers>) -> Self {
+        let count = data.capacity as u64;
+        let storage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        };
+        let buffer = || DeviceBuffer::new(context, storage, count);
+
+        let shader = StageShader {
+            constants: stages::ty::StageData {
+                buffer_size: data.num_particles(),
+                pass: PASS_KICK,
+                dt: 0.0,
+                weight: 0.0,
+                box_size: 0.0,
+                precision_mode: Precision::default().mode(),
+            },
+            double: has_float64(context),
+            data,
+            saved: [buffer(), buffer(), buffer(), buffer(), buffer()],
+            sums: [buffer(), buffer()],
+        };
+
+        Self {
+            shader: ComputeShaderExecutor::new(context, shader),
+        }
+    }
+
+    /// Wrap the positions around a periodic box spanning `[0, box_size)`, or not at all if 0
+    pub fn set_box_size(&mut self, box_size: f32) {
+        self.shader.constants.box_size = box_size;
+    }
+
+    /// Precision of the position updates, must be available on the device
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.shader.constants.precision_mode = precision.mode();
+    }
+
+    /// `v += a dt`
+    pub fn kick(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dt: f32,
+    ) {
+        self.record(builder, PASS_KICK, dt, 0.0);
+    }
+
+    /// `x += v dt`
+    pub fn drift(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dt: f32,
+    ) {
+        self.record(builder, PASS_DRIFT, dt, 0.0);
+    }
+
+    /// Remember the position, velocity, acceleration and jerk at the start of the step, and clear
+    /// the Runge-Kutta sums
+    pub fn save(&mut self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        self.record(builder, PASS_SAVE, 0.0, 0.0);
+    }
+
+    /// Add the current derivatives to the sums with `weight`, then move `dt` from the saved state
+    /// along them to the point of the next stage
+    pub fn runge_kutta(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dt: f32,
+        weight: f32,
+    ) {
+        self.record(builder, PASS_RUNGE_KUTTA, dt, weight);
+    }
+
+    /// Add the derivatives of the last stage, then move `dt` from the saved state along the sums
+    pub fn runge_kutta_finish(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dt: f32,
+        weight: f32,
+    ) {
+        self.record(builder, PASS_RUNGE_KUTTA_FINISH, dt, weight);
+    }
+
+    /// Predict the state `dt` after the saved one from its acceleration and jerk
+    pub fn predict(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dt: f32,
+    ) {
+        self.record(builder, PASS_PREDICT, dt, 0.0);
+    }
+
+    /// Correct the predicted state with the acceleration and jerk at the end of the step
+    pub fn correct(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dt: f32,
+    ) {
+        self.record(builder, PASS_CORRECT, dt, 0.0);
+    }
+
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pass: u32,
+        dt: f32,
+        weight: f32,
+    ) {
+        let num_particles = self.shader.data.num_particles();
+        let constants = &mut self.shader.constants;
+        constants.buffer_size = num_particles;
+        constants.pass = pass;
+        constants.dt = dt;
+        constants.weight = weight;
+        self.shader.record(builder);
+    }
+}
@@ -0,0 +1,297 @@
+use std::{cell::RefCell, fs, path::Path, rc::Rc};
+
+use cgmath::{Point3, Vector3};
+use rand::thread_rng;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Scope, AST, FLOAT, INT};
+
+use crate::{
+    distributions::{BallOfGas, Galaxy, Plummer},
+    physics::Particle,
+};
+
+/// Something a script asked the app to do, applied before the next simulation step
+#[derive(Debug, Clone)]
+pub enum Command {
+    Pause,
+    Resume,
+    /// Write all particles to a csv file
+    Capture(String),
+    SetDt(f32),
+    SetG(f32),
+    SetSoftening(f32),
+}
+
+/// Values scripts can read, updated by the app after every step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Diagnostics {
+    pub time: f32,
+    pub steps: u64,
+    pub kinetic_energy: f32,
+    pub potential_energy: f32,
+    /// Particles which haven't been removed by a merger
+    pub num_particles: u32,
+}
+
+/// State shared between the registered functions and `Scripting`
+#[derive(Default)]
+struct ScriptState {
+    particles: Vec<Particle>,
+    commands: Vec<Command>,
+    /// Actions scheduled by the script which is currently running
+    scheduled: Vec<(f32, FnPtr)>,
+    diagnostics: Diagnostics,
+    output: Vec<String>,
+}
+
+struct ScheduledAction {
+    time: f32,
+    action: FnPtr,
+    /// Script which defined the action, needed to call closures
+    ast: Rc<AST>,
+}
+
+/// Rhai scripting for building initial conditions and automating runs. Scripts can add particles
+/// with `galaxy` and `ball_of_gas`, change the integrator with `set_dt`, `set_g` and
+/// `set_softening`, schedule closures with `at(time, || ...)` and read diagnostics like `time()`
+/// and `energy()`, `kinetic_energy()` and `potential_energy()`.
+///
+/// ```rhai
+/// galaxy(1000.0, 1.0, plummer(1.0, 0.1), [0.0, 4.0, 4.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], 50000);
+/// set_dt(0.0005);
+/// at(2.0, || { capture("snapshot.csv"); pause(); });
+/// ```
+pub struct Scripting {
+    engine: Engine,
+    /// Variables persist between console commands
+    scope: Scope<'static>,
+    state: Rc<RefCell<ScriptState>>,
+    scheduled: Vec<ScheduledAction>,
+    /// Output of `print` and errors, shown in the console
+    pub log: Vec<String>,
+}
+
+fn vector(array: &Array) -> Result<Vector3<f32>, Box<EvalAltResult>> {
+    let components = array
+        .iter()
+        .map(|value| {
+            value
+                .as_float()
+                .or_else(|_| value.as_int().map(|value| value as FLOAT))
+                .map(|value| value as f32)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "expected an array of numbers")?;
+
+    match components[..] {
+        [x, y, z] => Ok(Vector3::new(x, y, z)),
+        _ => Err(format!("expected 3 components, got {}", components.len()).into()),
+    }
+}
+
+fn point(array: &Array) -> Result<Point3<f32>, Box<EvalAltResult>> {
+    vector(array).map(|vector| Point3::new(vector.x, vector.y, vector.z))
+}
+
+impl Scripting {
+    pub fn new() -> Self {
+        let state = Rc::new(RefCell::new(ScriptState::default()));
+        let mut engine = Engine::new();
+
+        let print_state = state.clone();
+        engine.on_print(move |text| print_state.borrow_mut().output.push(text.to_owned()));
+
+        // Initial conditions
+        engine.register_type_with_name::<Plummer>("Plummer");
+        engine.register_fn("plummer", |scale_length: FLOAT, offset: FLOAT| {
+            Plummer::new(scale_length, offset)
+        });
+
+        let galaxy_state = state.clone();
+        engine.register_fn(
+            "galaxy",
+            move |central_mass: FLOAT,
+                  orbit_mass: FLOAT,
+                  radius: Plummer,
+                  position: Array,
+                  velocity: Array,
+                  look_at: Array,
+                  count: INT|
+                  -> Result<(), Box<EvalAltResult>> {
+                let galaxy = Galaxy::new(
+                    central_mass as f32,
+                    orbit_mass as f32,
+                    radius,
+                    point(&position)?,
+                    vector(&velocity)?,
+                    vector(&look_at)?,
+                );
+                let particles = galaxy.get_particles(count.max(0) as u32, &mut thread_rng());
+                galaxy_state.borrow_mut().particles.extend(particles);
+                Ok(())
+            },
+        );
+
+        let gas_state = state.clone();
+        engine.register_fn(
+            "ball_of_gas",
+            move |mass: FLOAT,
+                  radius: FLOAT,
+                  position: Array,
+                  velocity: Array,
+                  count: INT|
+                  -> Result<(), Box<EvalAltResult>> {
+                let gas = BallOfGas::new(
+                    mass as f32,
+                    radius as f32,
+                    point(&position)?,
+                    vector(&velocity)?,
+                );
+                let particles = gas.get_particles(count.max(0) as u32, &mut thread_rng());
+                gas_state.borrow_mut().particles.extend(particles);
+                Ok(())
+            },
+        );
+
+        // Commands
+        let register_command = |engine: &mut Engine, name: &str, command: Command| {
+            let state = state.clone();
+            engine.register_fn(name, move || {
+                state.borrow_mut().commands.push(command.clone());
+            });
+        };
+        register_command(&mut engine, "pause", Command::Pause);
+        register_command(&mut engine, "resume", Command::Resume);
+
+        let register_parameter = |engine: &mut Engine, name: &str, command: fn(f32) -> Command| {
+            let state = state.clone();
+            engine.register_fn(name, move |value: FLOAT| {
+                state.borrow_mut().commands.push(command(value as f32));
+            });
+        };
+        register_parameter(&mut engine, "set_dt", Command::SetDt);
+        register_parameter(&mut engine, "set_g", Command::SetG);
+        register_parameter(&mut engine, "set_softening", Command::SetSoftening);
+
+        let capture_state = state.clone();
+        engine.register_fn("capture", move |path: &str| {
+            let command = Command::Capture(path.to_owned());
+            capture_state.borrow_mut().commands.push(command);
+        });
+
+        let at_state = state.clone();
+        engine.register_fn("at", move |time: FLOAT, action: FnPtr| {
+            at_state.borrow_mut().scheduled.push((time as f32, action));
+        });
+
+        // Diagnostics
+        let register_diagnostic =
+            |engine: &mut Engine, name: &str, value: fn(&Diagnostics) -> Dynamic| {
+                let state = state.clone();
+                engine.register_fn(name, move || value(&state.borrow().diagnostics));
+            };
+        register_diagnostic(&mut engine, "time", |d| {
+            Dynamic::from_float(d.time as FLOAT)
+        });
+        register_diagnostic(&mut engine, "steps", |d| Dynamic::from_int(d.steps as INT));
+        register_diagnostic(&mut engine, "energy", |d| {
+            Dynamic::from_float((d.kinetic_energy + d.potential_energy) as FLOAT)
+        });
+        register_diagnostic(&mut engine, "kinetic_energy", |d| {
+            Dynamic::from_float(d.kinetic_energy as FLOAT)
+        });
+        register_diagnostic(&mut engine, "potential_energy", |d| {
+            Dynamic::from_float(d.potential_energy as FLOAT)
+        });
+        register_diagnostic(&mut engine, "particle_count", |d| {
+            Dynamic::from_int(d.num_particles as INT)
+        });
+
+        Self {
+            engine,
+            scope: Scope::new(),
+            state,
+            scheduled: Vec::new(),
+            log: Vec::new(),
+        }
+    }
+
+    /// Run a script, errors are also written to the log
+    pub fn run(&mut self, source: &str) -> Result<(), String> {
+        let result = self
+            .engine
+            .compile_with_scope(&self.scope, source)
+            .map_err(|err| err.to_string())
+            .and_then(|ast| {
+                let result = self
+                    .engine
+                    .run_ast_with_scope(&mut self.scope, &ast)
+                    .map_err(|err| err.to_string());
+
+                self.schedule(&Rc::new(ast));
+                result
+            });
+
+        self.flush_output();
+        if let Err(err) = &result {
+            self.log.push(format!("error: {err}"));
+        }
+        result
+    }
+
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+        self.run(&source)
+    }
+
+    /// Particles added by scripts since the last call
+    pub fn take_particles(&mut self) -> Vec<Particle> {
+        std::mem::take(&mut self.state.borrow_mut().particles)
+    }
+
+    /// Commands issued by scripts since the last call
+    pub fn take_commands(&mut self) -> Vec<Command> {
+        std::mem::take(&mut self.state.borrow_mut().commands)
+    }
+
+    /// Update the diagnostics and run the scheduled actions which are due
+    pub fn update(&mut self, diagnostics: Diagnostics) {
+        self.state.borrow_mut().diagnostics = diagnostics;
+
+        let (due, scheduled) = std::mem::take(&mut self.scheduled)
+            .into_iter()
+            .partition::<Vec<_>, _>(|action| action.time <= diagnostics.time);
+        self.scheduled = scheduled;
+
+        for ScheduledAction { action, ast, .. } in due {
+            if let Err(err) = action.call::<Dynamic>(&self.engine, &ast, ()) {
+                self.log.push(format!("error in scheduled action: {err}"));
+            }
+            self.schedule(&ast);
+        }
+
+        self.flush_output();
+    }
+
+    /// Number of actions which haven't run yet
+    pub fn pending(&self) -> usize {
+        self.scheduled.len()
+    }
+
+    /// Move the actions scheduled by the script `ast` into the queue. Closures can only be
+    /// called together with the script which defined them, so it is kept alongside.
+    fn schedule(&mut self, ast: &Rc<AST>) {
+        let scheduled = std::mem::take(&mut self.state.borrow_mut().scheduled);
+        self.scheduled
+            .extend(scheduled.into_iter().map(|(time, action)| ScheduledAction {
+                time,
+                action,
+                ast: ast.clone(),
+            }));
+    }
+
+    fn flush_output(&mut self) {
+        self.log.append(&mut self.state.borrow_mut().output);
+    }
+}
@@ -10,11 +10,9 @@ use cgmath::{num_traits::Float, Vector3, Zero};
 use egui_implementation::*;
 use graphics::renderer::Renderer;
 use hatchery::{
-    dpi::PhysicalPosition,
-    egui_implementation::egui::plot::{Line, Plot, PlotPoints},
-    event::{
-        ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
-    },
+    egui_widgets::{KeymapHelp, Retention, TimeSeries, TimeSeriesPlot},
+    event::{VirtualKeyCode, WindowEvent},
+    input::{ActionMap, Binding},
     util::compute::ComputeShaderExecutor,
     *,
 };
@@ -29,6 +27,9 @@ pub struct GuiState {
     pub brightness: f32,
     pub size: f32,
     pub active: bool,
+    pub show_help: bool,
+    pub steps: u64,
+    pub energy: TimeSeries,
 }
 
 impl Default for GuiState {
@@ -37,6 +38,9 @@ impl Default for GuiState {
             brightness: 0.1,
             size: 0.01,
             active: false,
+            show_help: false,
+            steps: 0,
+            energy: TimeSeries::new(4096, Retention::Decimate),
         }
     }
 }
@@ -45,6 +49,7 @@ pub struct TardigradeEngine {
     simulation: ComputeShaderExecutor<SimulationShader>,
     renderer: Renderer,
     camera: Camera,
+    actions: ActionMap,
     state: GuiState,
     last_time: Duration,
 }
@@ -143,6 +148,14 @@ impl Engine for TardigradeEngine {
             renderer: Renderer::new(context.api().construction(), context.viewport_subpass()),
             last_time: Default::default(),
             camera: Camera::new(),
+            actions: ActionMap::new()
+                .with_camera_controls()
+                .action(
+                    "help",
+                    "Show the key bindings",
+                    [Binding::key(VirtualKeyCode::F1)],
+                )
+                .with_keymap(&context.api().storage),
             state: Default::default(),
         }
     }
@@ -153,7 +166,15 @@ impl Engine for TardigradeEngine {
             for _ in 0..10 {
                 self.simulation.execute(api.construction());
             }
-            self.simulation.shader_mut().calculate_energy();
+            self.state.steps += 10;
+
+            let (kinetic, potential) = self.simulation.shader_mut().calculate_energy();
+            let step = self.state.steps as f64;
+            self.state.energy.push("Kinetic", step, kinetic as f64);
+            self.state.energy.push("Potential", step, potential as f64);
+            self.state
+                .energy
+                .push("Total", step, (kinetic + potential) as f64);
             self.last_time = start.elapsed();
         }
 
@@ -187,93 +208,32 @@ impl Engine for TardigradeEngine {
 
                 ui.label(format!("last time: {} us", self.last_time.as_micros()));
 
-                let kinetic: PlotPoints = self
-                    .simulation
-                    .shader()
-                    .kinetic_energy
-                    .iter()
-                    .enumerate()
-                    .map(|(i, x)| [i as f64, *x as f64])
-                    .collect();
-                let potential: PlotPoints = self
-                    .simulation
-                    .shader()
-                    .potential_energy
-                    .iter()
-                    .enumerate()
-                    .map(|(i, x)| [i as f64, *x as f64])
-                    .collect();
-                let total: PlotPoints = self
-                    .simulation
-                    .shader()
-                    .potential_energy
-                    .iter()
-                    .zip(self.simulation.shader().kinetic_energy.iter())
-                    .map(|(x, y)| x + y)
-                    .enumerate()
-                    .map(|(i, x)| [i as f64, x as f64])
-                    .collect();
-                let line = Line::new(kinetic);
-                let line2 = Line::new(potential);
-                let line3 = Line::new(total);
-                Plot::new(format!("{}", thread_rng().gen_range(0.0..1.0)))
-                    .view_aspect(2.0)
-                    .show(ui, |plot_ui| {
-                        plot_ui.line(line);
-                    });
-                Plot::new(format!("{}", thread_rng().gen_range(0.0..1.0)))
-                    .view_aspect(2.0)
-                    .show(ui, |plot_ui| {
-                        plot_ui.line(line2);
-                    });
-                Plot::new(format!("{}", thread_rng().gen_range(0.0..1.0)))
-                    .view_aspect(2.0)
-                    .show(ui, |plot_ui| {
-                        plot_ui.line(line3);
-                    });
+                ui.add(TimeSeriesPlot::new("energy", &self.state.energy));
             });
-    }
 
-    fn on_winit_event(&mut self, event: &WindowEvent, api: &mut EngineApi) {
-        match event {
-            WindowEvent::KeyboardInput { input, .. } => self.on_keyboard_event(input),
-            WindowEvent::MouseInput { state, button, .. } => {
-                self.on_mouse_click_event(*state, *button)
-            }
-            WindowEvent::CursorMoved { position, .. } => self.on_cursor_moved_event(position),
-            WindowEvent::MouseWheel { delta, .. } => self.on_mouse_wheel_event(delta),
-            _ => {}
-        }
+        egui::Window::new("Key Bindings")
+            .open(&mut self.state.show_help)
+            .show(context, |ui| {
+                ui.add(KeymapHelp::new(&self.actions));
+            });
     }
-}
 
-impl TardigradeEngine {
-    fn on_keyboard_event(&mut self, input: &KeyboardInput) {
-        if let Some(key_code) = input.virtual_keycode {
-            match key_code {
-                VirtualKeyCode::W => self.camera.forward(),
-                VirtualKeyCode::S => self.camera.backward(),
-                VirtualKeyCode::D => self.camera.right(),
-                VirtualKeyCode::A => self.camera.left(),
-                VirtualKeyCode::Q => self.camera.up(),
-                VirtualKeyCode::E => self.camera.down(),
+    fn on_winit_event(&mut self, event: &WindowEvent, api: &mut EngineApi) {
+        for action in self.actions.handle(event) {
+            match action.name {
+                "forward" => self.camera.forward(),
+                "backward" => self.camera.backward(),
+                "right" => self.camera.right(),
+                "left" => self.camera.left(),
+                "up" => self.camera.up(),
+                "down" => self.camera.down(),
+                "zoom_in" => self.camera.zoom(action.amount),
+                "zoom_out" => self.camera.zoom(-action.amount),
+                "help" => self.state.show_help = !self.state.show_help,
                 _ => (),
             }
         }
     }
-
-    fn on_mouse_click_event(&mut self, state: ElementState, button: MouseButton) {}
-
-    fn on_cursor_moved_event(&mut self, position: &PhysicalPosition<f64>) {}
-
-    fn on_mouse_wheel_event(&mut self, delta: &MouseScrollDelta) {
-        let change = match delta {
-            MouseScrollDelta::LineDelta(_x, y) => *y,
-            MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
-        };
-
-        self.camera.zoom(change);
-    }
 }
 
 fn main() {
@@ -282,6 +242,7 @@ fn main() {
             title: "Tardigrade Engine",
             dimensions: LogicalSize::new(1400, 1000),
         },
+        app_id: Some("tardigrade"),
         features: Features {
             ..Features::empty()
         },
@@ -32,8 +32,6 @@ pub struct SimulationShader {
     position_mass: SharedBuffer<ParticlePosition>,
     potential: SharedBuffer<f32>,
     velocity: SharedBuffer<ParticleVelocityMass>,
-    pub kinetic_energy: Vec<f32>,
-    pub potential_energy: Vec<f32>,
     num_particles: u64,
 }
 
@@ -109,8 +107,6 @@ impl SimulationShader {
                     .collect(),
             ),
             num_particles: particles.len() as u64,
-            kinetic_energy: Vec::new(),
-            potential_energy: Vec::new(),
         }
     }
 
@@ -122,7 +118,8 @@ impl SimulationShader {
         &self.velocity
     }
 
-    pub fn calculate_energy(&mut self) {
+    /// Returns the kinetic and potential energy
+    pub fn calculate_energy(&mut self) -> (f32, f32) {
         let kinetic_energy: f32 = self
             .velocity_mass()
             .typed_buffer()
@@ -136,10 +133,9 @@ impl SimulationShader {
                         + vm.p_vel_mass[2] * vm.p_vel_mass[2])
             })
             .sum();
-        self.kinetic_energy.push(kinetic_energy);
         let potential_energy: f32 = self.potential.typed_buffer().read().unwrap().iter().sum();
-        self.potential_energy.push(potential_energy);
 
         println!("total {}", kinetic_energy + potential_energy);
+        (kinetic_energy, potential_energy)
     }
 }